@@ -0,0 +1,64 @@
+#ifndef MGFRC_H
+#define MGFRC_H
+
+/* generated by cbindgen from src/ffi.rs; do not edit */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+typedef struct QuantizedPDFSet QuantizedPDFSet;
+
+// model from `len` frequencies (256, or 257 with the end-of-stream symbol).
+// returns NULL unless every frequency is nonzero and the total fits in 32 bits.
+//
+// # Safety
+// `freq` must point to `len` readable values.
+QuantizedPDFSet *mgfrc_model_from_freq(const uint32_t *freq, uintptr_t len);
+
+// model of the mixture of `count` gaussians over the 256 values,
+// given as `weight, mean, sigma` triples in `params`.
+// returns NULL if a sigma is not a positive finite number or the mixture has no mass.
+//
+// # Safety
+// `params` must point to `3 * count` readable values.
+QuantizedPDFSet *mgfrc_model_from_gaussians(const double *params, uintptr_t count);
+
+// free a model made by this library. NULL is ignored.
+//
+// # Safety
+// `model` must be NULL or a model from this library, not freed before.
+void mgfrc_model_free(QuantizedPDFSet *model);
+
+// encode `len` symbols with the model, and write the number of bytes to `out_len`.
+// returns the bytes, to be freed by `mgfrc_buffer_free`,
+// or NULL if a symbol is outside the model's alphabet.
+//
+// # Safety
+// `model` must be a model from this library, `symbols` must point to `len` readable values,
+// and `out_len` must be writable.
+uint8_t *mgfrc_encode(const QuantizedPDFSet *model,
+                      const uint32_t *symbols,
+                      uintptr_t len,
+                      uintptr_t *out_len);
+
+// free bytes returned by `mgfrc_encode`, with the length written with them. NULL is ignored.
+//
+// # Safety
+// `data` must be NULL or bytes from `mgfrc_encode` of length `len`, not freed before.
+void mgfrc_buffer_free(uint8_t *data, uintptr_t len);
+
+// decode `len` symbols from `data_len` bytes into `out`.
+// returns 0, or -1 if the data is corrupt or truncated.
+//
+// # Safety
+// `model` must be a model from this library, `data` must point to `data_len` readable bytes,
+// and `out` to `len` writable values.
+int32_t mgfrc_decode(const QuantizedPDFSet *model,
+                     const uint8_t *data,
+                     uintptr_t data_len,
+                     uint32_t *out,
+                     uintptr_t len);
+
+#endif /* MGFRC_H */
@@ -0,0 +1,568 @@
+//! 符号化しながら頻度を学習する適応モデル
+//! 適応モデルを表すトレイト: Adaptive
+//! シンボル毎の頻度による適応モデル: AdaptiveModel
+//! 過去の頻度が指数的に減衰する適応モデル: DecayModel
+//! 直近のシンボルだけを数える適応モデル: WindowModel
+//! 符号化器と復号器は同じ順序でupdateを呼び，同じモデルの状態を保つ
+
+use crate::prelude::*;
+use crate::{coded_freq, debug_check_tables, search_cum_freq, QuantizedPDFSet};
+use range_coder::decoder::Decoder;
+use range_coder::pmodel::PModel;
+
+/// model which learns from the symbols coded with it.
+pub trait Adaptive: PModel {
+    /// learn from a coded symbol.
+    fn update(&mut self, symbol: usize);
+}
+
+/// default count added to a symbol each time it is coded.
+pub const DEFAULT_INCREMENT: u32 = 32;
+/// default total frequency at which counts are halved.
+pub const DEFAULT_LIMIT: u32 = 1 << 16;
+
+/// how fast an `AdaptiveModel` follows the source:
+/// each coded symbol adds `increment` to its count, and all counts are halved
+/// when the total exceeds `limit`. the model remembers about `limit / increment` symbols.
+/// `limit` plus `increment` must fit in u32, so that an update never overflows the total.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdaptationRate {
+    pub increment: u32,
+    pub limit: u32,
+}
+impl AdaptationRate {
+    /// for short packets: remembers about 32 symbols.
+    pub const FAST: Self = Self {
+        increment: 32,
+        limit: 1 << 10,
+    };
+    /// the default: remembers about 2048 symbols.
+    pub const MEDIUM: Self = Self {
+        increment: DEFAULT_INCREMENT,
+        limit: DEFAULT_LIMIT,
+    };
+    /// for long stationary files: remembers about 131072 symbols.
+    pub const SLOW: Self = Self {
+        increment: 8,
+        limit: 1 << 20,
+    };
+}
+impl Default for AdaptationRate {
+    fn default() -> Self {
+        Self::MEDIUM
+    }
+}
+
+/// 頻度表と累積頻度表
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Counts {
+    freq: Vec<u32>,
+    cum_freq: Vec<u32>,
+    total: u32,
+}
+impl Counts {
+    fn new(freq: Vec<u32>) -> Self {
+        let mut counts = Self {
+            cum_freq: vec![0; freq.len()],
+            freq,
+            total: 0,
+        };
+        counts.rebuild();
+        counts
+    }
+    fn add(&mut self, symbol: usize, n: u32) {
+        self.freq[symbol] += n;
+        for c in self.cum_freq[symbol + 1..].iter_mut() {
+            *c += n;
+        }
+        self.total += n;
+        self.debug_check();
+    }
+    fn sub(&mut self, symbol: usize, n: u32) {
+        self.freq[symbol] -= n;
+        for c in self.cum_freq[symbol + 1..].iter_mut() {
+            *c -= n;
+        }
+        self.total -= n;
+        self.debug_check();
+    }
+    /// 全ての頻度を，0にならないように半分にする
+    fn halve(&mut self) {
+        for f in self.freq.iter_mut() {
+            *f = (*f).div_ceil(2);
+        }
+        self.rebuild();
+    }
+    /// 頻度表と累積頻度表のバイト数
+    fn heap_bytes(&self) -> usize {
+        (self.freq.capacity() + self.cum_freq.capacity()) * core::mem::size_of::<u32>()
+    }
+    /// 頻度表から累積頻度表と総頻度を作り直す
+    fn rebuild(&mut self) {
+        let mut cum = 0;
+        for (c, f) in self.cum_freq.iter_mut().zip(&self.freq) {
+            *c = cum;
+            cum += f;
+        }
+        self.total = cum;
+        self.debug_check();
+    }
+    fn debug_check(&self) {
+        debug_check_tables(&self.freq, &self.cum_freq, self.total);
+    }
+}
+/// `counts`フィールドの頻度表でPModelを実装する
+macro_rules! impl_pmodel_by_counts {
+    ($t:ty) => {
+        impl PModel for $t {
+            fn c_freq(&self, index: usize) -> u32 {
+                self.counts.freq[index]
+            }
+            fn cum_freq(&self, index: usize) -> u32 {
+                self.counts.cum_freq[index]
+            }
+            fn total_freq(&self) -> u32 {
+                self.counts.total
+            }
+            fn find_index(&self, decoder: &Decoder) -> usize {
+                search_cum_freq(
+                    &self.counts.cum_freq,
+                    coded_freq(decoder, self.counts.total),
+                )
+            }
+        }
+    };
+}
+/// 静的なモデルの頻度を，総頻度がおよそtargetになるように縮める
+fn scaled_prior(prior: &QuantizedPDFSet, target: u64) -> Vec<u32> {
+    let total = prior.total_freq() as u64;
+    prior
+        .freq
+        .iter()
+        .map(|f| (*f as u64 * target / total).max(1) as u32)
+        .collect()
+}
+
+/// adaptive model with integer counts per symbol,
+/// incremented on each coded symbol and halved when the total exceeds a limit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdaptiveModel {
+    counts: Counts,
+    increment: u32,
+    limit: u32,
+    /// 頻度表に反映するまでに貯めるシンボルの数
+    batch: usize,
+    /// まだ頻度表に反映していないシンボル
+    pending: Vec<usize>,
+}
+impl AdaptiveModel {
+    /// model starting from the uniform distribution over `alphabet_size` symbols.
+    pub fn new(alphabet_size: usize) -> Self {
+        Self::with_rate(alphabet_size, AdaptationRate::default())
+    }
+    /// model starting from a static model, scaled to half of the limit.
+    pub fn from_prior(prior: &QuantizedPDFSet) -> Self {
+        Self::from_prior_with_rate(prior, AdaptationRate::default())
+    }
+    /// `new` with the adaptation rate.
+    pub fn with_rate(alphabet_size: usize, rate: AdaptationRate) -> Self {
+        assert!(alphabet_size > 0 && (alphabet_size as u64) * 2 <= rate.limit as u64);
+        Self::from_counts(vec![1; alphabet_size], rate)
+    }
+    /// `from_prior` with the adaptation rate.
+    pub fn from_prior_with_rate(prior: &QuantizedPDFSet, rate: AdaptationRate) -> Self {
+        Self::from_counts(scaled_prior(prior, (rate.limit / 2) as u64), rate)
+    }
+    /// model starting from a static model weighted as `strength` already coded symbols:
+    /// a strong prior makes short messages cheap, a weak one lets the source take over sooner.
+    /// the weight is clamped to the limit of the rate.
+    pub fn from_prior_with_strength(
+        prior: &QuantizedPDFSet,
+        rate: AdaptationRate,
+        strength: u32,
+    ) -> Self {
+        let target = (strength as u64 * rate.increment as u64).clamp(1, rate.limit as u64);
+        let mut model = Self::from_counts(scaled_prior(prior, target), rate);
+        // 頻度を1以上にしたことで上限を超えることがある
+        while model.counts.total > model.limit {
+            model.rescale();
+        }
+        model
+    }
+    fn from_counts(freq: Vec<u32>, rate: AdaptationRate) -> Self {
+        assert!(rate.increment > 0);
+        let model = Self {
+            counts: Counts::new(freq),
+            increment: rate.increment,
+            limit: rate.limit,
+            batch: 1,
+            pending: Vec::new(),
+        };
+        model.check_rate();
+        model
+    }
+    /// 総頻度が上限以下のときに1回の反映（batch回の加算）をしても，u32があふれないこと
+    fn check_rate(&self) {
+        assert!(
+            self.limit as u64 + self.increment as u64 * self.batch as u64 <= u32::MAX as u64,
+            "the limit plus the increments of a batch overflows u32"
+        );
+    }
+    pub fn rate(&self) -> AdaptationRate {
+        AdaptationRate {
+            increment: self.increment,
+            limit: self.limit,
+        }
+    }
+    /// count added on each coded symbol. larger adapts faster.
+    /// an increment beyond the limit halves the counts more than once per update.
+    pub fn with_increment(mut self, increment: u32) -> Self {
+        assert!(increment > 0);
+        self.increment = increment;
+        self.check_rate();
+        self
+    }
+    /// total frequency at which counts are halved. smaller adapts faster.
+    pub fn with_limit(mut self, limit: u32) -> Self {
+        assert!(limit as u64 >= 2 * self.alphabet_size() as u64);
+        self.limit = limit;
+        self.check_rate();
+        while self.counts.total > self.limit {
+            self.rescale();
+        }
+        self
+    }
+    /// apply updates in batches of `symbols`, rebuilding the cumulative frequencies once per batch.
+    /// the coded symbols of a batch use the counts from before it, which costs a little rate;
+    /// the decoder must use the same batch size.
+    pub fn with_batch(mut self, symbols: usize) -> Self {
+        assert!(symbols > 0);
+        self.flush();
+        self.batch = symbols;
+        self.check_rate();
+        self
+    }
+    /// number of symbols per batch of updates. 1 when updates are applied immediately.
+    pub fn batch(&self) -> usize {
+        self.batch
+    }
+    pub fn alphabet_size(&self) -> usize {
+        self.counts.freq.len()
+    }
+    /// current count of the symbol, without the updates pending in the batch.
+    pub fn count(&self, symbol: usize) -> u32 {
+        self.counts.freq[symbol]
+    }
+    /// bytes used by the model, including the pending updates.
+    pub fn memory_usage(&self) -> usize {
+        core::mem::size_of::<Self>()
+            + self.counts.heap_bytes()
+            + self.pending.capacity() * core::mem::size_of::<usize>()
+    }
+    /// halve all counts, keeping them nonzero.
+    pub fn rescale(&mut self) {
+        self.counts.halve();
+    }
+    /// 貯めた更新を頻度表に反映する
+    fn flush(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+        for symbol in self.pending.drain(..) {
+            self.counts.freq[symbol] += self.increment;
+        }
+        self.counts.rebuild();
+        while self.counts.total > self.limit {
+            self.rescale();
+        }
+    }
+}
+impl_pmodel_by_counts!(AdaptiveModel);
+impl Adaptive for AdaptiveModel {
+    fn update(&mut self, symbol: usize) {
+        if self.batch > 1 {
+            assert!(symbol < self.alphabet_size());
+            self.pending.push(symbol);
+            if self.pending.len() == self.batch {
+                self.flush();
+            }
+            return;
+        }
+        self.counts.add(symbol, self.increment);
+        while self.counts.total > self.limit {
+            self.rescale();
+        }
+    }
+}
+
+/// total frequency at which counts of `DecayModel` are halved.
+pub const DECAY_LIMIT: u32 = 1 << 24;
+/// ln 2（32ビット固定小数）
+const LN2_Q32: u64 = 2_977_044_472;
+
+/// adaptive model whose past counts decay geometrically,
+/// so that a symbol coded `half_life` symbols ago weighs half of the latest one.
+/// the decay is made by growing the increment, and halving everything at the limit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecayModel {
+    counts: Counts,
+    half_life: u32,
+    /// 加算する頻度（32ビット固定小数）
+    increment: u64,
+    /// 1シンボル毎の加算する頻度の増加率（32ビット固定小数）
+    growth: u64,
+}
+impl DecayModel {
+    /// model starting from the uniform distribution over `alphabet_size` symbols.
+    /// `half_life` is in symbols.
+    pub fn new(alphabet_size: usize, half_life: u32) -> Self {
+        assert!(alphabet_size > 0 && alphabet_size < (DECAY_LIMIT / 2) as usize);
+        Self::from_counts(vec![1; alphabet_size], half_life)
+    }
+    /// model starting from a static model.
+    pub fn from_prior(prior: &QuantizedPDFSet, half_life: u32) -> Self {
+        Self::from_counts(scaled_prior(prior, (DEFAULT_LIMIT / 2) as u64), half_life)
+    }
+    fn from_counts(freq: Vec<u32>, half_life: u32) -> Self {
+        assert!(half_life > 0);
+        Self {
+            counts: Counts::new(freq),
+            half_life,
+            increment: (DEFAULT_INCREMENT as u64) << 32,
+            growth: LN2_Q32 / half_life as u64,
+        }
+    }
+    pub fn half_life(&self) -> u32 {
+        self.half_life
+    }
+    pub fn alphabet_size(&self) -> usize {
+        self.counts.freq.len()
+    }
+    /// current count of the symbol.
+    pub fn count(&self, symbol: usize) -> u32 {
+        self.counts.freq[symbol]
+    }
+    /// bytes used by the model.
+    pub fn memory_usage(&self) -> usize {
+        core::mem::size_of::<Self>() + self.counts.heap_bytes()
+    }
+}
+impl_pmodel_by_counts!(DecayModel);
+impl Adaptive for DecayModel {
+    fn update(&mut self, symbol: usize) {
+        self.counts
+            .add(symbol, (self.increment >> 32).max(1) as u32);
+        // 加算する頻度を増やすことは，過去の頻度を減衰させることと同じ
+        self.increment += ((self.increment as u128 * self.growth as u128) >> 32) as u64;
+        while self.counts.total > DECAY_LIMIT {
+            self.counts.halve();
+            self.increment >>= 1;
+        }
+    }
+}
+
+/// adaptive model counting only the last `window` symbols,
+/// for sources whose distribution changes abruptly.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WindowModel {
+    counts: Counts,
+    /// 直近のシンボルのリングバッファ
+    recent: Vec<u16>,
+    /// 次に書き込むrecentの位置
+    head: usize,
+    window: usize,
+    increment: u32,
+}
+impl WindowModel {
+    /// model starting from the uniform distribution over `alphabet_size` symbols.
+    pub fn new(alphabet_size: usize, window: usize) -> Self {
+        assert!(alphabet_size > 0 && alphabet_size <= u16::MAX as usize + 1);
+        assert!(window > 0 && window < (u32::MAX / DEFAULT_INCREMENT) as usize - alphabet_size);
+        Self {
+            counts: Counts::new(vec![1; alphabet_size]),
+            recent: Vec::with_capacity(window),
+            head: 0,
+            window,
+            increment: DEFAULT_INCREMENT,
+        }
+    }
+    pub fn window(&self) -> usize {
+        self.window
+    }
+    pub fn alphabet_size(&self) -> usize {
+        self.counts.freq.len()
+    }
+    /// bytes used by the model, including the window of recent symbols.
+    pub fn memory_usage(&self) -> usize {
+        core::mem::size_of::<Self>()
+            + self.counts.heap_bytes()
+            + self.recent.capacity() * core::mem::size_of::<u16>()
+    }
+    /// number of times the symbol occurs in the window.
+    pub fn occurrences(&self, symbol: usize) -> u32 {
+        (self.counts.freq[symbol] - 1) / self.increment
+    }
+}
+impl_pmodel_by_counts!(WindowModel);
+impl Adaptive for WindowModel {
+    fn update(&mut self, symbol: usize) {
+        if self.recent.len() < self.window {
+            self.recent.push(symbol as u16);
+        } else {
+            // 窓から外れるシンボルを数えなくする
+            let old = core::mem::replace(&mut self.recent[self.head], symbol as u16);
+            self.counts.sub(old as usize, self.increment);
+        }
+        self.head = (self.head + 1) % self.window;
+        self.counts.add(symbol, self.increment);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{ModelDecoder, ModelEncoder};
+    use crate::test_util::simple_pmodel;
+    fn roundtrip<M: Adaptive + Clone>(model: &M, symbols: &[usize]) -> usize {
+        let mut encoder = ModelEncoder::new();
+        let mut m = model.clone();
+        for s in symbols {
+            encoder.encode_adaptive(&mut m, *s);
+        }
+        let data = encoder.finish();
+        let len = data.len();
+        let mut decoder = ModelDecoder::new(data);
+        let mut m = model.clone();
+        for s in symbols {
+            assert_eq!(decoder.try_decode_adaptive(&mut m).unwrap(), *s);
+        }
+        len
+    }
+    #[test]
+    fn learns_skewed_source() {
+        let symbols: Vec<usize> = (0..2000)
+            .map(|i| if i % 10 == 0 { 200 } else { 7 })
+            .collect();
+        let len = roundtrip(&AdaptiveModel::new(256), &symbols);
+        // 一様分布なら2000バイト
+        assert!(len < 200, "{}", len);
+    }
+    #[test]
+    fn prior_and_rescale() {
+        let prior = simple_pmodel();
+        let model = AdaptiveModel::from_prior(&prior);
+        assert!(model.count(128) > model.count(0));
+        let symbols: Vec<usize> = (0..5000).map(|i| (i * 31) % 256).collect();
+        roundtrip(&model, &symbols);
+        let mut small = AdaptiveModel::new(4).with_limit(64);
+        for _ in 0..100 {
+            small.update(3);
+        }
+        assert!(small.total_freq() <= 64);
+        assert!((0..4).all(|s| small.count(s) > 0));
+        assert_eq!(small.cum_freq(3) + small.c_freq(3), small.total_freq());
+        // 上限より大きい増分でも，1回の更新で上限以下に戻る
+        let mut fast = AdaptiveModel::new(4).with_limit(64).with_increment(1000);
+        fast.update(1);
+        assert!(fast.total_freq() <= 64);
+        roundtrip(&fast, &symbols.iter().map(|s| s % 4).collect::<Vec<_>>());
+    }
+    #[test]
+    #[should_panic(expected = "overflows u32")]
+    fn overflowing_increment() {
+        AdaptiveModel::new(4).with_increment(u32::MAX);
+    }
+    #[test]
+    fn prior_strength() {
+        let prior = simple_pmodel();
+        let rate = AdaptationRate::default();
+        // 事前分布に従う短いメッセージでは強い事前分布が，従わないものでは弱い方が良い
+        let short: Vec<usize> = (0..40).map(|i| [128, 30, 70, 128][i % 4]).collect();
+        let weak = AdaptiveModel::from_prior_with_strength(&prior, rate, 1);
+        let strong = AdaptiveModel::from_prior_with_strength(&prior, rate, 1000);
+        assert!(roundtrip(&strong, &short) < roundtrip(&weak, &short));
+        let other: Vec<usize> = (0..400).map(|i| [250, 251][i % 2]).collect();
+        assert!(roundtrip(&weak, &other) < roundtrip(&strong, &other));
+        let clamped = AdaptiveModel::from_prior_with_strength(&prior, rate, u32::MAX);
+        assert!(clamped.total_freq() <= rate.limit);
+    }
+    #[test]
+    fn batched_updates() {
+        let symbols: Vec<usize> = (0..3000).map(|i| if i % 7 == 0 { 40 } else { 3 }).collect();
+        let immediate = roundtrip(&AdaptiveModel::new(256), &symbols);
+        let batched = AdaptiveModel::new(256).with_batch(16);
+        assert_eq!(batched.batch(), 16);
+        let len = roundtrip(&batched, &symbols);
+        assert!(len < immediate + immediate / 10, "{} {}", len, immediate);
+        let mut model = AdaptiveModel::new(4).with_limit(64).with_batch(3);
+        for _ in 0..2 {
+            model.update(2);
+        }
+        assert_eq!(model.count(2), 1);
+        for _ in 0..100 {
+            model.update(2);
+        }
+        assert!(model.total_freq() <= 64);
+        assert_eq!(model.cum_freq(3), model.cum_freq(2) + model.c_freq(2));
+    }
+    #[test]
+    fn rate_presets() {
+        // 短いパケットでは速い方が，長く定常なデータでは遅い方が良い
+        let packet: Vec<usize> = (0..64).map(|i| [9, 200][i % 2]).collect();
+        let fast = roundtrip(
+            &AdaptiveModel::with_rate(256, AdaptationRate::FAST),
+            &packet,
+        );
+        let slow = roundtrip(
+            &AdaptiveModel::with_rate(256, AdaptationRate::SLOW),
+            &packet,
+        );
+        assert!(fast < slow, "{} {}", fast, slow);
+        let file: Vec<usize> = (0..20000).map(|i| (i * i) % 13).collect();
+        let fast = roundtrip(&AdaptiveModel::with_rate(256, AdaptationRate::FAST), &file);
+        let slow = roundtrip(&AdaptiveModel::with_rate(256, AdaptationRate::SLOW), &file);
+        assert!(slow < fast, "{} {}", slow, fast);
+        let prior = simple_pmodel();
+        let model = AdaptiveModel::from_prior_with_rate(&prior, AdaptationRate::SLOW);
+        assert_eq!(model.rate(), AdaptationRate::SLOW);
+        assert!(model.total_freq() <= AdaptationRate::SLOW.limit);
+    }
+    #[test]
+    fn decay_tracks_regime_changes() {
+        // 500シンボル毎に分布が切り替わる
+        let symbols: Vec<usize> = (0..6000)
+            .map(|i| {
+                if (i / 500) % 2 == 0 {
+                    [3, 4][i % 2]
+                } else {
+                    [100, 101, 102][i % 3]
+                }
+            })
+            .collect();
+        let decay = roundtrip(&DecayModel::new(256, 32), &symbols);
+        let counts = roundtrip(&AdaptiveModel::new(256).with_limit(1 << 20), &symbols);
+        assert!(decay < counts, "{} {}", decay, counts);
+        // half_life毎に，加算する頻度はおよそ2倍になる
+        let mut model = DecayModel::new(2, 64);
+        let before = model.increment;
+        for _ in 0..64 {
+            model.update(0);
+        }
+        let ratio = model.increment as f64 / before as f64;
+        assert!((ratio - 2.0).abs() < 0.05, "{}", ratio);
+    }
+    #[test]
+    fn window_forgets_old_symbols() {
+        let mut model = WindowModel::new(256, 4);
+        for s in &[1, 1, 2, 3, 4, 4] {
+            model.update(*s);
+        }
+        assert_eq!(model.occurrences(1), 0);
+        assert_eq!(model.occurrences(4), 2);
+        assert_eq!(model.total_freq(), 256 + 4 * DEFAULT_INCREMENT);
+        let symbols: Vec<usize> = (0..3000).map(|i| (i / 300) * 20 + i % 3).collect();
+        let window = roundtrip(&WindowModel::new(256, 64), &symbols);
+        let counts = roundtrip(&AdaptiveModel::new(256).with_limit(1 << 20), &symbols);
+        assert!(window < counts, "{} {}", window, counts);
+    }
+}
@@ -0,0 +1,87 @@
+//! ndarrayの配列の符号化/復号 (feature = "ndarray")
+//! シンボルの行列を行単位で，行毎のモデルで符号化する
+//! 行は別のストリームになるので，必要な行だけ復号できる
+//! 1行の符号化: encode_row
+//! 行列の符号化/復号: encode_rows / decode_rows
+//! 行の数とモデルの数が異なれば Error::ModelCountMismatch
+
+use crate::codec::{ModelDecoder, ModelEncoder};
+use crate::prelude::*;
+use crate::{Error, Result};
+use ndarray::{Array2, ArrayView1, ArrayView2};
+use range_coder::pmodel::PModel;
+
+/// encode the symbols of a row with one model.
+pub fn encode_row<M: PModel>(model: &M, row: ArrayView1<'_, u8>) -> Vec<u8> {
+    let mut encoder = ModelEncoder::new();
+    for symbol in row.iter() {
+        encoder.encode(model, *symbol as usize);
+    }
+    encoder.finish()
+}
+
+/// encode each row of the matrix with the model of the same index, into a stream per row.
+pub fn encode_rows<M: PModel>(models: &[M], matrix: ArrayView2<'_, u8>) -> Result<Vec<Vec<u8>>> {
+    check_count(models.len(), matrix.nrows())?;
+    Ok(models
+        .iter()
+        .zip(matrix.rows())
+        .map(|(model, row)| encode_row(model, row))
+        .collect())
+}
+
+/// decode the streams of `encode_rows` into a matrix of `columns` columns,
+/// failing on corrupt data or a decoded symbol beyond 255 (the end-of-stream symbol).
+pub fn decode_rows<M: PModel>(
+    models: &[M],
+    streams: &[Vec<u8>],
+    columns: usize,
+) -> Result<Array2<u8>> {
+    check_count(models.len(), streams.len())?;
+    let mut symbols = Vec::with_capacity(streams.len() * columns);
+    let mut row = vec![0; columns];
+    for (model, stream) in models.iter().zip(streams) {
+        ModelDecoder::new(stream.clone()).try_decode_slice(model, &mut row)?;
+        for symbol in &row {
+            if *symbol > u8::MAX as usize {
+                return Err(Error::SymbolOutOfRange {
+                    symbol: *symbol,
+                    alphabet_size: u8::MAX as usize + 1,
+                });
+            }
+            symbols.push(*symbol as u8);
+        }
+    }
+    Ok(Array2::from_shape_vec((streams.len(), columns), symbols)
+        .expect("rows of `columns` symbols"))
+}
+
+fn check_count(models: usize, rows: usize) -> Result<()> {
+    if models == rows {
+        Ok(())
+    } else {
+        Err(Error::ModelCountMismatch { models, rows })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::simple_pmodel;
+    use crate::QuantizedPDFSet;
+    #[test]
+    fn rows_with_own_models() {
+        let models = vec![simple_pmodel(), QuantizedPDFSet::uniform(), simple_pmodel()];
+        let matrix = Array2::from_shape_fn((3, 50), |(r, c)| [128, 30, 70, r as u8][(r + c) % 4]);
+        let streams = encode_rows(&models, matrix.view()).unwrap();
+        assert_eq!(streams[1], encode_row(&models[1], matrix.row(1)));
+        assert_eq!(decode_rows(&models, &streams, 50).unwrap(), matrix);
+        assert!(matches!(
+            encode_rows(&models[..2], matrix.view()),
+            Err(Error::ModelCountMismatch { models: 2, rows: 3 })
+        ));
+        let eof = simple_pmodel().with_eof();
+        let data = crate::codec::encode_slice(&eof, &[256, 256]);
+        assert!(decode_rows(&[eof], &[data], 2).is_err());
+    }
+}
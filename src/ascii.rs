@@ -0,0 +1,137 @@
+//! 端末に表示するための頻度表の図
+//! ログやテストの失敗で，モデルの形をすぐに見るためのもの
+//! 値を幅の数の列にまとめ，列の確率を棒で描く: QuantizedPDFSet::render_ascii
+//! 2つのモデルの列毎の確率の差を，増加を上に，減少を下に描く: QuantizedPDFSet::render_ascii_diff
+//! 高さが1なら，棒の上端の文字だけの1行のスパークラインになる
+
+use crate::prelude::*;
+use crate::QuantizedPDFSet;
+use range_coder::pmodel::PModel;
+
+/// 棒の上端の，埋まった割合毎の文字
+const LEVELS: [char; 4] = [' ', '.', ':', '#'];
+
+impl QuantizedPDFSet {
+    /// bar chart of the probabilities in `width` columns (at most one per symbol)
+    /// and `height` rows, scaled to the most probable column,
+    /// followed by a line of `-` and a line with the first and the last symbol.
+    /// panics if `width` or `height` is zero.
+    pub fn render_ascii(&self, width: usize, height: usize) -> String {
+        assert!(width > 0 && height > 0, "chart of zero width or height");
+        let columns = columns(self, self.alphabet_size(), width);
+        let max = columns.iter().fold(0.0, |a: f64, b| a.max(*b));
+        let mut chart = String::new();
+        for row in (0..height).rev() {
+            let line: String = columns.iter().map(|p| cell(p / max, row, height)).collect();
+            chart.push_str(line.trim_end());
+            chart.push('\n');
+        }
+        chart + &axis(columns.len(), self.alphabet_size())
+    }
+    /// chart of the change of the probabilities from `self` to `other`, in `width` columns,
+    /// with increases as `+` bars above a line of `=` and decreases as `-` bars below it,
+    /// `height` rows each, scaled to the largest change. an alphabet without the end-of-stream
+    /// symbol is compared as if its probability were 0.
+    /// panics if `width` or `height` is zero.
+    pub fn render_ascii_diff(
+        &self,
+        other: &QuantizedPDFSet,
+        width: usize,
+        height: usize,
+    ) -> String {
+        assert!(width > 0 && height > 0, "chart of zero width or height");
+        let alphabet_size = self.alphabet_size().max(other.alphabet_size());
+        let before = columns(self, alphabet_size, width);
+        let after = columns(other, alphabet_size, width);
+        let diff: Vec<f64> = after.iter().zip(&before).map(|(a, b)| a - b).collect();
+        let max = diff.iter().fold(0.0, |a: f64, b| a.max(b.abs()));
+        let bars = |rows: Vec<usize>, sign: f64, bar: char| -> String {
+            let mut lines = String::new();
+            for row in rows {
+                let line: String = diff
+                    .iter()
+                    .map(|d| {
+                        match cell(if max > 0.0 { sign * d / max } else { 0.0 }, row, height) {
+                            '#' => bar,
+                            c => c,
+                        }
+                    })
+                    .collect();
+                lines.push_str(line.trim_end());
+                lines.push('\n');
+            }
+            lines
+        };
+        let mut chart = bars((0..height).rev().collect(), 1.0, '+');
+        chart.push_str(&"=".repeat(diff.len()));
+        chart.push('\n');
+        chart += &bars((0..height).collect(), -1.0, '-');
+        chart + &axis(diff.len(), alphabet_size)
+    }
+}
+
+/// 値を幅の数（値の数まで）の列にまとめた確率
+fn columns(model: &QuantizedPDFSet, alphabet_size: usize, width: usize) -> Vec<f64> {
+    let width = width.min(alphabet_size);
+    let total = model.total_freq() as f64;
+    let freq = |s: usize| {
+        if s < model.alphabet_size() {
+            model.c_freq(s) as f64
+        } else {
+            0.0
+        }
+    };
+    (0..width)
+        .map(|c| {
+            let symbols = c * alphabet_size / width..(c + 1) * alphabet_size / width;
+            symbols.map(freq).sum::<f64>() / total
+        })
+        .collect()
+}
+
+/// 最大を1とした高さの棒の，下から数えた行の文字
+fn cell(height_ratio: f64, row: usize, height: usize) -> char {
+    let fill = (height_ratio * height as f64 - row as f64).clamp(0.0, 1.0);
+    LEVELS[(fill * (LEVELS.len() - 1) as f64).round() as usize]
+}
+
+/// 横軸と，最初と最後の値
+fn axis(width: usize, alphabet_size: usize) -> String {
+    let last = (alphabet_size - 1).to_string();
+    let gap = width.saturating_sub(1 + last.len()).max(1);
+    format!("{}\n0{}{}\n", "-".repeat(width), " ".repeat(gap), last)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::simple_pmodel;
+    use crate::QuantizedPDFSet;
+    #[test]
+    fn ascii_chart_and_diff() {
+        let pm = simple_pmodel();
+        let chart = pm.render_ascii(64, 8);
+        let lines: Vec<&str> = chart.lines().collect();
+        assert_eq!(lines.len(), 10);
+        // 128の値の列が最も高い
+        assert_eq!(lines[0].find('#'), Some(128 * 64 / 256));
+        assert!(lines.iter().all(|l| l.len() <= 64));
+        assert_eq!(lines[9], format!("0{}255", " ".repeat(60)));
+        let sparkline = pm.render_ascii(256, 1);
+        assert_eq!(
+            sparkline.lines().next().unwrap().chars().nth(128),
+            Some('#')
+        );
+        let same = pm.render_ascii_diff(&pm, 32, 4);
+        assert!(same
+            .lines()
+            .take(9)
+            .all(|l| !l.contains('+') && !l.contains('-')));
+        let diff = pm.render_ascii_diff(&QuantizedPDFSet::uniform(), 32, 4);
+        let lines: Vec<&str> = diff.lines().collect();
+        assert_eq!(lines.len(), 11);
+        assert_eq!(lines[4], "=".repeat(32));
+        assert_eq!(lines[5].chars().nth(128 * 32 / 256), Some('-'));
+        // 一様なモデルでは，他の多くの値の確率が少しずつ増える
+        assert!(lines[3].chars().filter(|c| *c != ' ').count() > 16);
+    }
+}
@@ -0,0 +1,260 @@
+//! 非同期入出力上での符号化/復号（feature = "async"）
+//! 非同期の符号化器: AsyncModelEncoder
+//! 非同期の復号器: AsyncModelDecoder
+//!
+//! シンボル列はフレーム単位で読み書きする
+//! ストリーム: ヘッダ（container::Header） | フレーム...
+//! フレーム: シンボル数(u32 LE) | バイト数(u32 LE) | 符号化したバイト列
+
+use crate::codec::{ModelDecoder, ModelEncoder};
+use crate::container::Header;
+use core::convert::TryFrom;
+use futures::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use range_coder::pmodel::PModel;
+use std::io;
+
+const FRAME_HEADER_SIZE: usize = 8;
+/// フレームを読むときに一度に確保する大きさ．ヘッダのバイト数は信頼できないので，一度には確保しない
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+/// 1つのフレームのシンボル数の上限
+/// 1シンボルの符号は8バイト以下なので，終わりの8バイトを足してもバイト数もu32に収まる
+const MAX_FRAME_SYMBOLS: u32 = (u32::MAX - 8) / 8;
+
+/// encodes symbols into frames written to an `AsyncWrite`.
+pub struct AsyncModelEncoder<W: AsyncWrite + Unpin> {
+    writer: W,
+    encoder: ModelEncoder,
+    symbols: u32,
+    max_frame_symbols: u32,
+    /// 書き出していない，閉じたフレーム
+    pending: Vec<u8>,
+    header_written: bool,
+}
+impl<W: AsyncWrite + Unpin> AsyncModelEncoder<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            encoder: ModelEncoder::new(),
+            symbols: 0,
+            max_frame_symbols: MAX_FRAME_SYMBOLS,
+            pending: Vec::new(),
+            header_written: false,
+        }
+    }
+    /// encode one symbol into the current frame.
+    /// a frame whose symbol count or size would not fit its u32 header is closed
+    /// before the symbol, and written by the next `write_frame`.
+    pub fn encode<M: PModel>(&mut self, model: &M, symbol: usize) {
+        if self.symbols == self.max_frame_symbols {
+            self.close_frame();
+        }
+        self.encoder.encode(model, symbol);
+        self.symbols += 1;
+    }
+    /// finish the current frame and write it with the frames closed by `encode`.
+    /// does nothing when there is no frame to write.
+    pub async fn write_frame(&mut self) -> io::Result<()> {
+        self.write_header().await?;
+        self.close_frame();
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        self.writer.write_all(&self.pending).await?;
+        self.pending.clear();
+        self.writer.flush().await
+    }
+    /// 今のフレームを符号化してpendingに足す
+    fn close_frame(&mut self) {
+        if self.symbols == 0 {
+            return;
+        }
+        let data = self.encoder.flush();
+        let len = u32::try_from(data.len()).expect("frame of at most 8 bytes per symbol");
+        self.pending.extend_from_slice(&self.symbols.to_le_bytes());
+        self.pending.extend_from_slice(&len.to_le_bytes());
+        self.pending.extend_from_slice(&data);
+        self.symbols = 0;
+    }
+    async fn write_header(&mut self) -> io::Result<()> {
+        if !self.header_written {
+            self.writer.write_all(&Header::new(0).to_bytes()).await?;
+            self.header_written = true;
+        }
+        Ok(())
+    }
+    /// write the last frame and return the writer.
+    pub async fn finish(mut self) -> io::Result<W> {
+        self.write_frame().await?;
+        Ok(self.writer)
+    }
+}
+
+/// decodes frames read from an `AsyncRead`.
+pub struct AsyncModelDecoder<R: AsyncRead + Unpin> {
+    reader: R,
+    decoder: Option<ModelDecoder>,
+    remaining: u32,
+    header_read: bool,
+}
+impl<R: AsyncRead + Unpin> AsyncModelDecoder<R> {
+    pub fn new(reader: R) -> Self {
+        Self {
+            reader,
+            decoder: None,
+            remaining: 0,
+            header_read: false,
+        }
+    }
+    /// read the next frame and return the number of symbols in it.
+    /// returns `None` at the end of the stream.
+    /// fails with `InvalidData` when the stream header has an unknown version or flags.
+    pub async fn next_frame(&mut self) -> io::Result<Option<usize>> {
+        if !self.header_read {
+            let mut header = [0u8; Header::SIZE];
+            self.reader.read_exact(&mut header).await?;
+            let header = Header::from_bytes(&header)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            if header.flags() != 0 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    crate::Error::UnknownFlags(header.flags()),
+                ));
+            }
+            self.header_read = true;
+        }
+        let mut header = [0u8; FRAME_HEADER_SIZE];
+        // フレームの境界でのEOFはストリームの終端
+        if self.reader.read(&mut header[..1]).await? == 0 {
+            self.decoder = None;
+            self.remaining = 0;
+            return Ok(None);
+        }
+        self.reader.read_exact(&mut header[1..]).await?;
+        let mut symbols = [0u8; 4];
+        let mut len = [0u8; 4];
+        symbols.copy_from_slice(&header[..4]);
+        len.copy_from_slice(&header[4..]);
+        let len = u32::from_le_bytes(len) as usize;
+        let mut data = Vec::new();
+        while data.len() < len {
+            let start = data.len();
+            data.resize(len.min(start + READ_CHUNK_SIZE), 0);
+            self.reader.read_exact(&mut data[start..]).await?;
+        }
+        self.remaining = u32::from_le_bytes(symbols);
+        self.decoder = Some(ModelDecoder::new(data));
+        Ok(Some(self.remaining as usize))
+    }
+    /// number of symbols left in the current frame.
+    pub fn remaining(&self) -> usize {
+        self.remaining as usize
+    }
+    /// decode one symbol of the current frame.
+    /// returns `None` when the current frame is exhausted.
+    pub fn decode<M: PModel>(&mut self, model: &M) -> Option<usize> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        self.decoder.as_mut().map(|decoder| decoder.decode(model))
+    }
+    /// `decode`, failing with `InvalidData` on corrupt or truncated data.
+    pub fn try_decode<M: PModel>(&mut self, model: &M) -> io::Result<Option<usize>> {
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        let decoder = match self.decoder.as_mut() {
+            Some(decoder) => decoder,
+            None => return Ok(None),
+        };
+        self.remaining -= 1;
+        decoder
+            .try_decode(model)
+            .map(Some)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::simple_pmodel;
+    use futures::executor::block_on;
+    #[test]
+    fn frames_roundtrip() {
+        let frames: Vec<Vec<usize>> = vec![vec![34, 45, 128, 255, 0], vec![], vec![30; 100]];
+        let pm = simple_pmodel();
+        let bytes = block_on(async {
+            let mut encoder = AsyncModelEncoder::new(Vec::new());
+            for frame in &frames {
+                for s in frame {
+                    encoder.encode(&pm, *s);
+                }
+                encoder.write_frame().await.unwrap();
+            }
+            encoder.finish().await.unwrap()
+        });
+        let decoded = block_on(async {
+            let mut decoder = AsyncModelDecoder::new(&bytes[..]);
+            let mut decoded = Vec::new();
+            while let Some(n) = decoder.next_frame().await.unwrap() {
+                decoded.push((0..n).map(|_| decoder.decode(&pm).unwrap()).collect::<Vec<_>>());
+                assert_eq!(decoder.decode(&pm), None);
+            }
+            decoded
+        });
+        let expected: Vec<Vec<usize>> = frames.into_iter().filter(|f| !f.is_empty()).collect();
+        assert_eq!(expected, decoded);
+        let mut version = bytes;
+        version[4] += 1;
+        let err = block_on(AsyncModelDecoder::new(&version[..]).next_frame()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+    #[test]
+    fn full_frames_are_split() {
+        let pm = simple_pmodel();
+        let message: Vec<usize> = (0..10).map(|i| [34, 45, 128][i % 3]).collect();
+        let bytes = block_on(async {
+            let mut encoder = AsyncModelEncoder::new(Vec::new());
+            encoder.max_frame_symbols = 4;
+            for s in &message {
+                encoder.encode(&pm, *s);
+            }
+            encoder.finish().await.unwrap()
+        });
+        let (frames, decoded) = block_on(async {
+            let mut decoder = AsyncModelDecoder::new(&bytes[..]);
+            let (mut frames, mut decoded) = (Vec::new(), Vec::new());
+            while let Some(n) = decoder.next_frame().await.unwrap() {
+                frames.push(n);
+                decoded.extend((0..n).map(|_| decoder.decode(&pm).unwrap()));
+            }
+            (frames, decoded)
+        });
+        assert_eq!(frames, vec![4, 4, 2]);
+        assert_eq!(decoded, message);
+    }
+    #[test]
+    fn untrusted_frame_length() {
+        // 4GiB近いと主張するフレーム．確保する前にデータが尽きる
+        let mut bytes = Header::new(0).to_bytes().to_vec();
+        bytes.extend_from_slice(&10u32.to_le_bytes());
+        bytes.extend_from_slice(&u32::MAX.to_le_bytes());
+        bytes.extend_from_slice(&[1, 2, 3]);
+        let err = block_on(AsyncModelDecoder::new(&bytes[..]).next_frame()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        let garbage = [
+            Header::new(0).to_bytes().to_vec(),
+            vec![200, 0, 0, 0, 2, 0, 0, 0, 0xff, 0xff],
+        ]
+        .concat();
+        let mut decoder = AsyncModelDecoder::new(&garbage[..]);
+        assert_eq!(block_on(decoder.next_frame()).unwrap(), Some(200));
+        let pm = simple_pmodel();
+        let decoded: io::Result<Vec<_>> = (0..200).map(|_| decoder.try_decode(&pm)).collect();
+        assert!(decoded.is_err());
+    }
+}
@@ -0,0 +1,222 @@
+//! エントロピー符号化のバックエンド抽象化
+//! バックエンドを表すトレイト: EntropyBackend
+//! range_coderによる実装: RangeCoderBackend
+
+use crate::prelude::*;
+use crate::sink::BitSink;
+use crate::{checked_coded_freq, DecodeError, QuantizedPDFSet};
+use range_coder::decoder::Decoder;
+use range_coder::encoder::Encoder;
+use range_coder::pmodel::PModel;
+
+/// entropy coder which codes symbols with a `PModel`.
+pub trait EntropyBackend {
+    /// saved state of the decoder.
+    type Checkpoint;
+    /// backend for encoding.
+    fn new_encoder() -> Self
+    where
+        Self: Sized;
+    /// backend for decoding `data`.
+    fn new_decoder(data: Vec<u8>) -> Self
+    where
+        Self: Sized;
+    fn encode_symbol<M: PModel>(&mut self, model: &M, symbol: usize);
+    fn decode_symbol<M: PModel>(&mut self, model: &M) -> usize;
+    /// decode one symbol, failing instead of returning garbage on corrupt or truncated data.
+    fn try_decode_symbol<M: PModel>(&mut self, model: &M) -> Result<usize, DecodeError> {
+        Ok(self.decode_symbol(model))
+    }
+    /// decode `out.len()` symbols of one model, as `try_decode_symbol` on each of them.
+    fn decode_batch(
+        &mut self,
+        model: &QuantizedPDFSet,
+        out: &mut [usize],
+    ) -> Result<(), DecodeError> {
+        for symbol in out.iter_mut() {
+            *symbol = self.try_decode_symbol(model)?;
+        }
+        Ok(())
+    }
+    /// save the decoder state. how much this costs depends on the backend.
+    fn checkpoint(&self) -> Self::Checkpoint;
+    /// go back to the saved decoder state.
+    fn restore(&mut self, checkpoint: &Self::Checkpoint);
+    /// flush the encoder and put the encoded bytes to `sink`.
+    /// the backend is ready to encode a new message after this.
+    fn finish_to<S: BitSink>(&mut self, sink: &mut S);
+    /// flush the encoder and return the encoded bytes.
+    fn finish(&mut self) -> Vec<u8> {
+        let mut out = Vec::new();
+        self.finish_to(&mut out);
+        out
+    }
+    /// discard the symbols encoded so far.
+    fn reset(&mut self);
+}
+
+/// `EntropyBackend` over range_coder's `Encoder`/`Decoder`.
+pub struct RangeCoderBackend {
+    encoder: Encoder,
+    decoder: Decoder,
+    /// 復号するデータのバイト数
+    data_len: usize,
+    /// 復号器が読み込んだバイト数（データの終わりの先も含む）
+    consumed: usize,
+}
+/// saved state of `RangeCoderBackend`'s decoder.
+/// the backend counts the bytes read, but range_coder's `Decoder` cannot be set to saved registers
+/// and models find their symbols through a `&Decoder`, so this is a copy of the whole decoder,
+/// input included: saving and restoring cost O(input length), not O(1).
+#[derive(Clone)]
+pub struct RangeCoderCheckpoint {
+    decoder: Decoder,
+    consumed: usize,
+}
+impl EntropyBackend for RangeCoderBackend {
+    type Checkpoint = RangeCoderCheckpoint;
+    fn new_encoder() -> Self {
+        Self {
+            encoder: Encoder::new(),
+            decoder: Decoder::new(),
+            data_len: 0,
+            consumed: 0,
+        }
+    }
+    fn new_decoder(data: Vec<u8>) -> Self {
+        let data_len = data.len();
+        let mut decoder = Decoder::new();
+        decoder.set_data(data);
+        decoder.decode_start();
+        Self {
+            encoder: Encoder::new(),
+            decoder,
+            data_len,
+            consumed: START_BYTES,
+        }
+    }
+    fn encode_symbol<M: PModel>(&mut self, model: &M, symbol: usize) {
+        self.encoder.encode(model, symbol);
+    }
+    fn decode_symbol<M: PModel>(&mut self, model: &M) -> usize {
+        self.decode_counted(model)
+    }
+    /// in addition to checking the coded value against the table,
+    /// fails with `DecodeError::Truncated` on the first symbol which reads past the end of the data.
+    fn try_decode_symbol<M: PModel>(&mut self, model: &M) -> Result<usize, DecodeError> {
+        checked_coded_freq(&self.decoder, model.total_freq())?;
+        let symbol = self.decode_counted(model);
+        self.check_consumed()?;
+        Ok(symbol)
+    }
+    /// the total frequency is read once and each symbol is looked up once instead of twice.
+    fn decode_batch(
+        &mut self,
+        model: &QuantizedPDFSet,
+        out: &mut [usize],
+    ) -> Result<(), DecodeError> {
+        let total = model.total_freq();
+        for symbol in out.iter_mut() {
+            let index = model.index_of(checked_coded_freq(&self.decoder, total)?);
+            self.decode_counted(&Resolved { model, index });
+            self.check_consumed()?;
+            *symbol = index;
+        }
+        Ok(())
+    }
+    fn checkpoint(&self) -> RangeCoderCheckpoint {
+        RangeCoderCheckpoint {
+            decoder: self.decoder.clone(),
+            consumed: self.consumed,
+        }
+    }
+    fn restore(&mut self, checkpoint: &RangeCoderCheckpoint) {
+        self.decoder = checkpoint.decoder.clone();
+        self.consumed = checkpoint.consumed;
+    }
+    /// the bytes are put from the encoder's buffer once, without an intermediate `Vec`.
+    /// range_coder's `Encoder` can neither clear its buffer nor give it away,
+    /// so every message is encoded into a new buffer, allocated as it grows.
+    fn finish_to<S: BitSink>(&mut self, sink: &mut S) {
+        self.encoder.finish();
+        sink.put_bytes(self.encoder.data());
+        self.reset();
+    }
+    /// the bytes are copied out of the encoder's buffer, which is then dropped.
+    fn finish(&mut self) -> Vec<u8> {
+        self.encoder.finish();
+        let data = self.encoder.data().to_vec();
+        self.reset();
+        data
+    }
+    fn reset(&mut self) {
+        self.encoder = Encoder::new();
+    }
+}
+
+impl RangeCoderBackend {
+    /// 1つ復号し，その間に読み込んだバイト数を数える
+    fn decode_counted<M: PModel>(&mut self, model: &M) -> usize {
+        let rc = self.decoder.range_coder();
+        let (lower_bound, range_par_total) =
+            (rc.lower_bound(), rc.range_par_total(model.total_freq()));
+        let symbol = self.decoder.decode_one_alphabet(model);
+        // 復号器と同じ区間の更新と正規化をなぞる
+        let mut lower_bound =
+            lower_bound.wrapping_add(range_par_total * model.cum_freq(symbol) as u64);
+        let mut range = range_par_total * model.c_freq(symbol) as u64;
+        loop {
+            if (lower_bound ^ lower_bound.wrapping_add(range)) >= NORMALIZE_TOP {
+                if range >= NORMALIZE_BOTTOM {
+                    break;
+                }
+                range = lower_bound.wrapping_neg() & (NORMALIZE_BOTTOM - 1);
+            }
+            lower_bound <<= 8;
+            range <<= 8;
+            self.consumed += 1;
+        }
+        debug_assert_eq!(
+            (lower_bound, range),
+            (
+                self.decoder.range_coder().lower_bound(),
+                self.decoder.range_coder().range()
+            )
+        );
+        symbol
+    }
+    /// データの終わりの先を読んでいたら失敗する
+    fn check_consumed(&self) -> Result<(), DecodeError> {
+        if self.consumed > self.data_len {
+            return Err(DecodeError::Truncated {
+                consumed: self.consumed,
+                len: self.data_len,
+            });
+        }
+        Ok(())
+    }
+}
+/// decode_startで読み込むバイト数
+const START_BYTES: usize = 8;
+/// range_coderの（桁上がりのない）正規化の閾値
+const NORMALIZE_TOP: u64 = 1 << 56;
+const NORMALIZE_BOTTOM: u64 = 1 << 48;
+/// 探索済みの値を返すモデル．復号器の中で同じ値をもう一度探さないためのもの
+struct Resolved<'a> {
+    model: &'a QuantizedPDFSet,
+    index: usize,
+}
+impl PModel for Resolved<'_> {
+    fn c_freq(&self, index: usize) -> u32 {
+        self.model.c_freq(index)
+    }
+    fn cum_freq(&self, index: usize) -> u32 {
+        self.model.cum_freq(index)
+    }
+    fn total_freq(&self) -> u32 {
+        self.model.total_freq()
+    }
+    fn find_index(&self, _decoder: &Decoder) -> usize {
+        self.index
+    }
+}
@@ -0,0 +1,197 @@
+//! 名前付きの複数のモデルをまとめたモデルバンク
+//! モデルバンク: ModelBank
+//! バンクを先頭に埋め込んだストリーム: BankEncoder / BankDecoder
+//! ストリーム: ヘッダ（container::Header） | バンク | 符号化したバイト列
+//! バンク: モデル数(u16 LE) | (名前のバイト数(u8) | 名前(UTF-8) | モデル)...
+
+use crate::codec::{ModelDecoder, ModelEncoder};
+use crate::container::Header;
+use crate::prelude::*;
+use crate::{Error, QuantizedPDFSet, Result};
+
+/// numbered and named models, serialized once and referenced by id.
+#[derive(Default)]
+pub struct ModelBank {
+    names: Vec<String>,
+    models: Vec<QuantizedPDFSet>,
+}
+impl ModelBank {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// add a model and return its id. names longer than 255 bytes are rejected.
+    pub fn add(&mut self, name: &str, model: QuantizedPDFSet) -> Result<usize> {
+        if name.len() > u8::MAX as usize || self.models.len() >= u16::MAX as usize {
+            return Err(Error::InvalidBank);
+        }
+        self.names.push(name.to_string());
+        self.models.push(model);
+        Ok(self.models.len() - 1)
+    }
+    pub fn get(&self, id: usize) -> Option<&QuantizedPDFSet> {
+        self.models.get(id)
+    }
+    /// id of the model with the name.
+    pub fn id(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|n| n == name)
+    }
+    pub fn name(&self, id: usize) -> Option<&str> {
+        self.names.get(id).map(|n| n.as_str())
+    }
+    pub fn len(&self) -> usize {
+        self.models.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.models.is_empty()
+    }
+    /// bytes used by the names and the models.
+    pub fn memory_usage(&self) -> usize {
+        core::mem::size_of::<Self>()
+            + self.names.capacity() * core::mem::size_of::<String>()
+            + self.names.iter().map(|n| n.capacity()).sum::<usize>()
+            + (self.models.capacity() - self.models.len()) * core::mem::size_of::<QuantizedPDFSet>()
+            + self.models.iter().map(|m| m.memory_usage()).sum::<usize>()
+    }
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = (self.models.len() as u16).to_le_bytes().to_vec();
+        for (name, model) in self.names.iter().zip(&self.models) {
+            bytes.push(name.len() as u8);
+            bytes.extend_from_slice(name.as_bytes());
+            bytes.extend(model.to_bytes());
+        }
+        bytes
+    }
+    /// load a bank serialized by `to_bytes`.
+    /// returns the bank and the number of bytes read.
+    pub fn from_bytes(bytes: &[u8]) -> Result<(Self, usize)> {
+        if bytes.len() < 2 {
+            return Err(Error::UnexpectedEof);
+        }
+        let count = u16::from_le_bytes([bytes[0], bytes[1]]) as usize;
+        let mut pos = 2;
+        let mut bank = Self::new();
+        for _ in 0..count {
+            let len = *bytes.get(pos).ok_or(Error::UnexpectedEof)? as usize;
+            pos += 1;
+            let name = bytes.get(pos..pos + len).ok_or(Error::UnexpectedEof)?;
+            let name = core::str::from_utf8(name).map_err(|_| Error::InvalidBank)?;
+            pos += len;
+            let (model, read) = QuantizedPDFSet::from_bytes(&bytes[pos..])?;
+            pos += read;
+            bank.add(name, model)?;
+        }
+        Ok((bank, pos))
+    }
+    fn model(&self, id: usize) -> Result<&QuantizedPDFSet> {
+        self.get(id).ok_or(Error::UnknownModel(id))
+    }
+}
+
+/// encodes symbols with models of a bank, which is written at the start of the stream.
+pub struct BankEncoder {
+    bank: ModelBank,
+    encoder: ModelEncoder,
+}
+impl BankEncoder {
+    pub fn new(bank: ModelBank) -> Self {
+        Self {
+            bank,
+            encoder: ModelEncoder::new(),
+        }
+    }
+    pub fn bank(&self) -> &ModelBank {
+        &self.bank
+    }
+    /// encode the symbol with the model of id `model`.
+    pub fn encode(&mut self, model: usize, symbol: usize) -> Result<()> {
+        let model = self.bank.model(model)?;
+        if symbol >= model.alphabet_size() {
+            return Err(Error::SymbolOutOfRange {
+                symbol,
+                alphabet_size: model.alphabet_size(),
+            });
+        }
+        self.encoder.encode(model, symbol);
+        Ok(())
+    }
+    pub fn finish(self) -> Vec<u8> {
+        let mut out = Header::new(0).to_bytes().to_vec();
+        out.extend(self.bank.to_bytes());
+        out.extend(self.encoder.finish());
+        out
+    }
+}
+
+/// decodes a stream written by `BankEncoder`.
+pub struct BankDecoder {
+    bank: ModelBank,
+    decoder: ModelDecoder,
+}
+impl BankDecoder {
+    pub fn new(data: &[u8]) -> Result<Self> {
+        let header = Header::from_bytes(data)?;
+        if header.flags() != 0 {
+            return Err(Error::UnknownFlags(header.flags()));
+        }
+        let (bank, len) = ModelBank::from_bytes(&data[Header::SIZE..])?;
+        let decoder = ModelDecoder::new(data[Header::SIZE + len..].to_vec());
+        Ok(Self { bank, decoder })
+    }
+    pub fn bank(&self) -> &ModelBank {
+        &self.bank
+    }
+    /// decode a symbol with the model of id `model`.
+    pub fn decode(&mut self, model: usize) -> Result<usize> {
+        let model = self.bank.model(model)?;
+        self.decoder.try_decode(model)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{simple_pmodel, GaussianDist};
+    use crate::PDFSet;
+    fn bank() -> ModelBank {
+        let mut bank = ModelBank::new();
+        bank.add("mixture", simple_pmodel()).unwrap();
+        let narrow = PDFSet::new(vec![GaussianDist {
+            h: 1.0,
+            w: 1.0,
+            m: 10,
+        }]);
+        bank.add("narrow", narrow.finalize()).unwrap();
+        bank
+    }
+    #[test]
+    fn bank_stream_roundtrip() {
+        let records: Vec<(usize, usize)> = (0..50).map(|i| (i % 2, [128, 10, 30, 11][i % 4])).collect();
+        let mut encoder = BankEncoder::new(bank());
+        for (model, symbol) in &records {
+            encoder.encode(*model, *symbol).unwrap();
+        }
+        assert!(encoder.encode(2, 0).is_err());
+        let data = encoder.finish();
+        let mut decoder = BankDecoder::new(&data).unwrap();
+        assert_eq!(decoder.bank().id("narrow"), Some(1));
+        assert_eq!(decoder.bank().name(0), Some("mixture"));
+        for (model, symbol) in &records {
+            assert_eq!(decoder.decode(*model).unwrap(), *symbol);
+        }
+    }
+    #[test]
+    fn truncated_bank() {
+        let bytes = bank().to_bytes();
+        assert!(ModelBank::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        let (loaded, len) = ModelBank::from_bytes(&bytes).unwrap();
+        assert_eq!(len, bytes.len());
+        assert_eq!(loaded.len(), 2);
+    }
+    #[test]
+    fn bank_memory_usage() {
+        let bank = bank();
+        let models = bank.get(0).unwrap().memory_usage() + bank.get(1).unwrap().memory_usage();
+        assert!(bank.memory_usage() >= models + "mixture".len() + "narrow".len());
+        assert!(bank.memory_usage() < models + 1024);
+    }
+}
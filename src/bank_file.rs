@@ -0,0 +1,265 @@
+//! 多数のモデルを1つのファイルにまとめる，モデルバンクのファイル形式
+//! 数百の文脈のモデルを，1つのアセットとして配布して必要なものだけ取り出すためのもの
+//! ファイル: ヘッダ | 索引 | (詰め物 | 頻度表)...
+//! ヘッダ: マジック(4バイト) | バージョン(u8) | 予約(3バイト，0) | モデル数(u32 LE) | 索引のバイト数(u32 LE)
+//! 索引: (頻度表の位置(u64 LE) | 値の数(u16 LE) | 名前のバイト数(u8) | 名前(UTF-8))...
+//! 頻度表: 頻度(u32 LE)を値の数だけ．位置はファイルの先頭からALIGNMENTの倍数なので，mmapした表はそのまま読める
+//! モデルのidは索引の順番で，ModelBankのidと同じ
+//! 読み込み: ModelBankFile::open / ModelBankFile::from_bytes（mmapしたバイト列を借りる）
+//! 書き出し: ModelBankFile::write / ModelBankFile::to_bytes
+
+use crate::bank::ModelBank;
+use crate::prelude::*;
+use crate::{Error, QuantizedPDFSet, RawModel, Result, EOF_SYMBOL, RANGE_SIZE};
+use alloc::borrow::Cow;
+use core::convert::TryFrom;
+#[cfg(feature = "std")]
+use std::io::Write;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+pub const MAGIC: [u8; 4] = *b"MGFB";
+/// current version of the model bank file format.
+pub const FORMAT_VERSION: u8 = 1;
+/// alignment of every frequency table from the start of the file.
+pub const ALIGNMENT: usize = 64;
+const HEADER_SIZE: usize = MAGIC.len() + 4 + 4 + 4;
+
+/// 索引の1つのモデル
+#[derive(Debug, Clone)]
+struct Entry {
+    name: String,
+    offset: usize,
+    size: usize,
+}
+
+/// models of a model bank file, read from its index on demand.
+/// every table is checked when the file is loaded, so getting a model does not fail.
+#[derive(Debug, Clone)]
+pub struct ModelBankFile<'a> {
+    bytes: Cow<'a, [u8]>,
+    entries: Vec<Entry>,
+}
+impl ModelBankFile<'static> {
+    /// read a model bank file into memory.
+    #[cfg(feature = "std")]
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_vec(std::fs::read(path)?)
+    }
+    pub fn from_vec(bytes: Vec<u8>) -> Result<Self> {
+        Self::load(Cow::Owned(bytes))
+    }
+}
+impl<'a> ModelBankFile<'a> {
+    /// load a model bank file, e.g. memory mapped, without copying it.
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self> {
+        Self::load(Cow::Borrowed(bytes))
+    }
+    fn load(bytes: Cow<'a, [u8]>) -> Result<Self> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(Error::UnexpectedEof);
+        }
+        if bytes[..4] != MAGIC {
+            return Err(Error::BadMagic);
+        }
+        if bytes[4] != FORMAT_VERSION {
+            return Err(Error::UnsupportedVersion(bytes[4]));
+        }
+        let count = read_u32(&bytes, 8)? as usize;
+        let index_size = read_u32(&bytes, 12)? as usize;
+        let index = bytes
+            .get(HEADER_SIZE..HEADER_SIZE + index_size)
+            .ok_or(Error::UnexpectedEof)?;
+        let mut entries = Vec::with_capacity(count.min(index_size / 11));
+        let mut pos = 0;
+        for _ in 0..count {
+            let offset = read_u64(index, pos)?;
+            let size = u16::from_le_bytes([
+                *index.get(pos + 8).ok_or(Error::UnexpectedEof)?,
+                *index.get(pos + 9).ok_or(Error::UnexpectedEof)?,
+            ]) as usize;
+            let len = *index.get(pos + 10).ok_or(Error::UnexpectedEof)? as usize;
+            pos += 11;
+            let name = index.get(pos..pos + len).ok_or(Error::UnexpectedEof)?;
+            let name = core::str::from_utf8(name).map_err(|_| Error::InvalidBank)?;
+            pos += len;
+            if offset % ALIGNMENT as u64 != 0 {
+                return Err(Error::InvalidBank);
+            }
+            if size != RANGE_SIZE && size != EOF_SYMBOL + 1 {
+                return Err(Error::InvalidAlphabetSize(size));
+            }
+            let offset = usize::try_from(offset).map_err(|_| Error::UnexpectedEof)?;
+            let entry = Entry {
+                name: name.to_string(),
+                offset,
+                size,
+            };
+            // 表を一度だけ検査する
+            RawModel::from_freq(read_freq(&bytes, &entry)?).validate()?;
+            entries.push(entry);
+        }
+        if pos != index_size {
+            return Err(Error::InvalidBank);
+        }
+        Ok(Self { bytes, entries })
+    }
+    /// write the models of the bank, in the order of their ids.
+    #[cfg(feature = "std")]
+    pub fn write<W: Write>(bank: &ModelBank, mut writer: W) -> Result<()> {
+        writer.write_all(&Self::to_bytes(bank))?;
+        Ok(())
+    }
+    /// bytes of the model bank file of the bank.
+    pub fn to_bytes(bank: &ModelBank) -> Vec<u8> {
+        let ids = 0..bank.len();
+        let index_size: usize = ids
+            .clone()
+            .map(|id| 11 + bank.name(id).unwrap().len())
+            .sum();
+        let mut offset = (HEADER_SIZE + index_size).next_multiple_of(ALIGNMENT);
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&[FORMAT_VERSION, 0, 0, 0]);
+        bytes.extend_from_slice(&(bank.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(index_size as u32).to_le_bytes());
+        for id in ids.clone() {
+            let (name, model) = (bank.name(id).unwrap(), bank.get(id).unwrap());
+            bytes.extend_from_slice(&(offset as u64).to_le_bytes());
+            bytes.extend_from_slice(&(model.alphabet_size() as u16).to_le_bytes());
+            bytes.push(name.len() as u8);
+            bytes.extend_from_slice(name.as_bytes());
+            offset = (offset + 4 * model.alphabet_size()).next_multiple_of(ALIGNMENT);
+        }
+        for id in ids {
+            bytes.resize(bytes.len().next_multiple_of(ALIGNMENT), 0);
+            for f in &bank.get(id).unwrap().freq {
+                bytes.extend_from_slice(&f.to_le_bytes());
+            }
+        }
+        bytes
+    }
+    /// the model with the name.
+    pub fn get(&self, name: &str) -> Option<QuantizedPDFSet> {
+        self.get_id(self.id(name)?)
+    }
+    pub fn get_id(&self, id: usize) -> Option<QuantizedPDFSet> {
+        let entry = self.entries.get(id)?;
+        let freq = read_freq(&self.bytes, entry).expect("table checked on load");
+        Some(QuantizedPDFSet::from_freq(freq))
+    }
+    /// little endian frequencies of the model, aligned to `ALIGNMENT` from the start of the file.
+    pub fn table(&self, id: usize) -> Option<&[u8]> {
+        let entry = self.entries.get(id)?;
+        Some(&self.bytes[entry.offset..entry.offset + 4 * entry.size])
+    }
+    /// id of the model with the name.
+    pub fn id(&self, name: &str) -> Option<usize> {
+        self.entries.iter().position(|e| e.name == name)
+    }
+    pub fn name(&self, id: usize) -> Option<&str> {
+        self.entries.get(id).map(|e| e.name.as_str())
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    /// every model, loaded into a bank.
+    pub fn to_bank(&self) -> Result<ModelBank> {
+        let mut bank = ModelBank::new();
+        for (id, entry) in self.entries.iter().enumerate() {
+            bank.add(&entry.name, self.get_id(id).unwrap())?;
+        }
+        Ok(bank)
+    }
+}
+
+fn read_u32(bytes: &[u8], pos: usize) -> Result<u32> {
+    let b = bytes.get(pos..pos + 4).ok_or(Error::UnexpectedEof)?;
+    Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+}
+fn read_u64(bytes: &[u8], pos: usize) -> Result<u64> {
+    let b = bytes.get(pos..pos + 8).ok_or(Error::UnexpectedEof)?;
+    let mut le = [0; 8];
+    le.copy_from_slice(b);
+    Ok(u64::from_le_bytes(le))
+}
+fn read_freq(bytes: &[u8], entry: &Entry) -> Result<Vec<u32>> {
+    let end = entry
+        .offset
+        .checked_add(4 * entry.size)
+        .ok_or(Error::UnexpectedEof)?;
+    let table = bytes.get(entry.offset..end).ok_or(Error::UnexpectedEof)?;
+    Ok(table
+        .chunks_exact(4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::simple_pmodel;
+    use range_coder::pmodel::PModel;
+    fn bank() -> ModelBank {
+        let mut bank = ModelBank::new();
+        bank.add("mixture", simple_pmodel()).unwrap();
+        bank.add("uniform", QuantizedPDFSet::uniform()).unwrap();
+        bank.add("eof", simple_pmodel().with_eof()).unwrap();
+        bank
+    }
+    #[test]
+    fn file_roundtrip() {
+        let bank = bank();
+        let path = std::env::temp_dir().join(format!("bank-file-{}.mgfb", std::process::id()));
+        ModelBankFile::write(&bank, std::fs::File::create(&path).unwrap()).unwrap();
+        let file = ModelBankFile::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(file.len(), 3);
+        assert_eq!((file.id("eof"), file.name(1)), (Some(2), Some("uniform")));
+        let eof = file.get("eof").unwrap();
+        assert_eq!(eof.alphabet_size(), 257);
+        assert_eq!(eof.to_bytes(), bank.get(2).unwrap().to_bytes());
+        assert!(file.get("missing").is_none());
+        let bytes = ModelBankFile::to_bytes(&bank);
+        for id in 0..3 {
+            let table = file.table(id).unwrap();
+            let offset = table.as_ptr() as usize - file.bytes.as_ptr() as usize;
+            assert_eq!(offset % ALIGNMENT, 0);
+            assert_eq!(&bytes[offset..offset + 8], &table[..8]);
+        }
+        let borrowed = ModelBankFile::from_bytes(&bytes).unwrap();
+        assert_eq!(borrowed.to_bank().unwrap().to_bytes(), bank.to_bytes());
+        assert_eq!(
+            borrowed.get_id(0).unwrap().c_freq(30),
+            simple_pmodel().c_freq(30)
+        );
+    }
+    #[test]
+    fn corrupt_file() {
+        let bytes = ModelBankFile::to_bytes(&bank());
+        assert!(matches!(
+            ModelBankFile::from_bytes(&bytes[..bytes.len() - 1]),
+            Err(Error::UnexpectedEof)
+        ));
+        let mut bad = bytes.clone();
+        bad[0] = b'X';
+        assert!(matches!(
+            ModelBankFile::from_bytes(&bad),
+            Err(Error::BadMagic)
+        ));
+        // 表の頻度を0にする
+        let mut bad = bytes.clone();
+        let offset = read_u64(&bytes, HEADER_SIZE).unwrap() as usize;
+        bad[offset..offset + 4].copy_from_slice(&[0; 4]);
+        assert!(ModelBankFile::from_bytes(&bad).is_err());
+        // 揃っていない位置
+        let mut bad = bytes;
+        bad[HEADER_SIZE] += 4;
+        assert!(matches!(
+            ModelBankFile::from_bytes(&bad),
+            Err(Error::InvalidBank)
+        ));
+    }
+}
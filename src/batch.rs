@@ -0,0 +1,152 @@
+//! 多数の混合分布の一括評価
+//! フレーム毎に多数の混合分布を量子化する用途（学習した圧縮のハイパー事前分布の出力など）のため，
+//! 全てのモデルの成分のパラメータを1つの表にまとめ，各値の確率をまとめて評価する
+//! 評価は BatchEvaluator で差し替えられ，別の実装（クレートの外のGPUの実装など）は parameters の表を入力として使う
+//! このクレートの実装はCPUでの評価（CpuEvaluator）だけ
+//! 組み込みの分布のどちらか: Component
+//! 混合分布の集まり: MixtureBatch
+//! 確率の評価: BatchEvaluator / CpuEvaluator
+
+use crate::dist::{Gaussian, Laplace};
+use crate::prelude::*;
+use crate::{
+    accumulated_into, add_weighted_row, quantize_mass, total_mass, QuantizedPDFSet, ZeroMass, PDF,
+};
+
+const ALPHABET_SIZE: usize = u8::MAX as usize + 1;
+
+/// component of a mixture in a `MixtureBatch`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Component {
+    Gaussian(Gaussian),
+    Laplace(Laplace),
+}
+impl Component {
+    /// `[kind, weight, mean, scale]`, as laid out in `MixtureBatch::parameters`.
+    /// kind is 0 for `Gaussian` (scale is sigma) and 1 for `Laplace`.
+    pub fn parameters(&self) -> [f64; 4] {
+        match self {
+            Self::Gaussian(g) => [0.0, g.weight, g.mean, g.sigma],
+            Self::Laplace(l) => [1.0, l.weight, l.mean, l.scale],
+        }
+    }
+}
+impl PDF for Component {
+    fn freq(&self, v: usize) -> f64 {
+        match self {
+            Self::Gaussian(g) => g.freq(v),
+            Self::Laplace(l) => l.freq(v),
+        }
+    }
+    fn freq_range(&self, out: &mut [f64]) {
+        match self {
+            Self::Gaussian(g) => g.freq_range(out),
+            Self::Laplace(l) => l.freq_range(out),
+        }
+    }
+}
+
+/// mixtures to be evaluated and quantized together.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MixtureBatch {
+    components: Vec<Component>,
+    /// モデル毎の，成分の終わりの位置
+    ends: Vec<usize>,
+}
+impl MixtureBatch {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// add a mixture and return its index.
+    pub fn add_model(&mut self, components: &[Component]) -> usize {
+        self.components.extend_from_slice(components);
+        self.ends.push(self.components.len());
+        self.ends.len() - 1
+    }
+    /// number of mixtures.
+    pub fn len(&self) -> usize {
+        self.ends.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.ends.is_empty()
+    }
+    /// components of the mixture.
+    pub fn components(&self, model: usize) -> &[Component] {
+        let start = if model == 0 { 0 } else { self.ends[model - 1] };
+        &self.components[start..self.ends[model]]
+    }
+    /// the components of all mixtures in order, as `Component::parameters`,
+    /// and the end of each mixture in them. this is the input of an evaluator on a device.
+    pub fn parameters(&self) -> (Vec<[f64; 4]>, &[usize]) {
+        let parameters = self.components.iter().map(|c| c.parameters()).collect();
+        (parameters, &self.ends)
+    }
+    /// quantize all mixtures with the densities evaluated by `evaluator`.
+    /// mixtures with no positive finite mass get the uniform distribution.
+    pub fn finalize<E: BatchEvaluator>(&self, evaluator: &E) -> Vec<QuantizedPDFSet> {
+        let mut rows = vec![0.0; self.len() * ALPHABET_SIZE];
+        evaluator.evaluate(self, &mut rows);
+        rows.chunks_exact(ALPHABET_SIZE)
+            .map(|row| quantize_mass(row, total_mass(row), ZeroMass::Uniform).unwrap())
+            .collect()
+    }
+}
+
+/// evaluates the densities of all mixtures of a batch.
+/// implement it to evaluate on other hardware, e.g. a GPU, from `MixtureBatch::parameters`.
+pub trait BatchEvaluator {
+    /// write the mixed density of mixture `m` at value `v` to `rows[m * 256 + v]`.
+    fn evaluate(&self, batch: &MixtureBatch, rows: &mut [f64]);
+}
+
+/// evaluates on the CPU, with the same results as `PDFSet::finalize` of each mixture.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CpuEvaluator;
+impl BatchEvaluator for CpuEvaluator {
+    fn evaluate(&self, batch: &MixtureBatch, rows: &mut [f64]) {
+        let mut component = vec![0.0; ALPHABET_SIZE];
+        let mut acc = vec![0.0; ALPHABET_SIZE];
+        for (model, row) in rows.chunks_exact_mut(ALPHABET_SIZE).enumerate() {
+            acc.iter_mut().for_each(|a| *a = 0.0);
+            for c in batch.components(model) {
+                c.freq_range(&mut component);
+                add_weighted_row(&mut acc, &component, 1.0);
+            }
+            accumulated_into(&acc, row);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PDFSet;
+    use range_coder::pmodel::PModel;
+    #[test]
+    fn batch_matches_pdf_sets() {
+        let mut batch = MixtureBatch::new();
+        let mixtures: Vec<Vec<Component>> = (0..20)
+            .map(|i| {
+                vec![
+                    Component::Gaussian(Gaussian::new(1.0, i as f64 * 12.0, 1.0 + i as f64)),
+                    Component::Laplace(Laplace::new(0.5, 255.0 - i as f64 * 3.0, 2.0)),
+                ]
+            })
+            .collect();
+        for m in &mixtures {
+            batch.add_model(m);
+        }
+        batch.add_model(&[Component::Gaussian(Gaussian::new(0.0, 0.0, 1.0))]);
+        assert_eq!(batch.len(), 21);
+        let (parameters, ends) = batch.parameters();
+        assert_eq!((parameters.len(), ends[20]), (41, 41));
+        assert_eq!(parameters[1], [1.0, 0.5, 255.0, 2.0]);
+        let models = batch.finalize(&CpuEvaluator);
+        for (model, m) in models.iter().zip(&mixtures) {
+            let expected = PDFSet::new(m.clone()).finalize();
+            assert_eq!(model.to_bytes(), expected.to_bytes());
+        }
+        // 質量が0なら一様分布
+        assert_eq!(models[20].c_freq(0), models[20].c_freq(255));
+    }
+}
@@ -0,0 +1,116 @@
+//! 利用者のデータでの速度と圧縮率の計測 (feature = "bench")
+//! 量子化の精度，復号の表，バックエンドなどの設定を，実際のデータで比べるためのもの
+//! 時間は複数回の計測の最小値で，他の処理による揺らぎを抑える
+//! 速度はシンボルを1バイトとして数える
+//! 計測の結果: BenchReport
+//! 静的なモデルの計測: bench_model
+//! 任意の処理の時間: measure
+
+use crate::codec::{encode_slice, try_decode_slice};
+use crate::{DecodeError, Result};
+use range_coder::pmodel::PModel;
+use std::time::{Duration, Instant};
+
+/// throughput and compression of a model on some data.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BenchReport {
+    pub symbols: usize,
+    pub compressed_bytes: usize,
+    pub encode_time: Duration,
+    pub decode_time: Duration,
+    /// entropy of the symbol frequencies of the data, in bits per symbol.
+    pub entropy: f64,
+}
+impl BenchReport {
+    /// encoded megabytes (10^6 symbols) per second.
+    pub fn encode_mb_per_s(&self) -> f64 {
+        mb_per_s(self.symbols, self.encode_time)
+    }
+    /// decoded megabytes (10^6 symbols) per second.
+    pub fn decode_mb_per_s(&self) -> f64 {
+        mb_per_s(self.symbols, self.decode_time)
+    }
+    /// bits of the coded data per symbol.
+    pub fn bits_per_symbol(&self) -> f64 {
+        if self.symbols == 0 {
+            return 0.0;
+        }
+        (self.compressed_bytes * 8) as f64 / self.symbols as f64
+    }
+    /// bits per symbol above the entropy of the data.
+    /// the cost of both model mismatch and coding overhead.
+    pub fn overhead(&self) -> f64 {
+        self.bits_per_symbol() - self.entropy
+    }
+}
+fn mb_per_s(symbols: usize, time: Duration) -> f64 {
+    symbols as f64 / 1e6 / time.as_secs_f64().max(f64::MIN_POSITIVE)
+}
+
+/// encode and decode `symbols` with the model `repeats` times (at least once),
+/// keeping the fastest time of each. fails if the decoded symbols differ.
+pub fn bench_model<M: PModel>(model: &M, symbols: &[usize], repeats: usize) -> Result<BenchReport> {
+    let mut data = Vec::new();
+    let encode_time = measure(repeats, || data = encode_slice(model, symbols));
+    let mut decoded = Ok(Vec::new());
+    let decode_time = measure(repeats, || {
+        decoded = try_decode_slice(model, data.clone(), symbols.len())
+    });
+    if decoded? != symbols {
+        return Err(DecodeError::Corrupt.into());
+    }
+    Ok(BenchReport {
+        symbols: symbols.len(),
+        compressed_bytes: data.len(),
+        encode_time,
+        decode_time,
+        entropy: entropy(symbols),
+    })
+}
+
+/// the fastest of `repeats` runs of `f` (at least one),
+/// for measuring adaptive models or other backends.
+pub fn measure<F: FnMut()>(repeats: usize, mut f: F) -> Duration {
+    (0..repeats.max(1))
+        .map(|_| {
+            let start = Instant::now();
+            f();
+            start.elapsed()
+        })
+        .min()
+        .unwrap()
+}
+
+/// entropy of the symbol frequencies, in bits per symbol.
+pub fn entropy(symbols: &[usize]) -> f64 {
+    let mut counts = std::collections::HashMap::new();
+    for s in symbols {
+        *counts.entry(*s).or_insert(0usize) += 1;
+    }
+    let n = symbols.len() as f64;
+    counts
+        .values()
+        .map(|c| {
+            let p = *c as f64 / n;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::simple_pmodel;
+    #[test]
+    fn report_of_model() {
+        let symbols: Vec<usize> = (0..4000).map(|i| [128, 129, 30, 70][i % 4]).collect();
+        let report = bench_model(&simple_pmodel(), &symbols, 3).unwrap();
+        assert_eq!(report.symbols, 4000);
+        assert!((report.entropy - 2.0).abs() < 1e-12);
+        // モデルがデータに合っていないので，エントロピーより大きい
+        assert!(report.bits_per_symbol() > report.entropy);
+        assert!(report.overhead() > 0.0);
+        assert!(report.encode_mb_per_s() > 0.0 && report.decode_mb_per_s() > 0.0);
+        assert_eq!(entropy(&[]), 0.0);
+    }
+}
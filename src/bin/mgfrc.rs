@@ -0,0 +1,198 @@
+//! ファイルの圧縮/伸長とモデルの学習/表示のコマンド
+//! mgfrc compress <入力> <出力> [--model <モデル>]: コンテナ形式に圧縮する
+//!   モデルを与えなければ入力の頻度をモデルとして埋め込み，与えればモデルを埋め込まない
+//! mgfrc decompress <入力> <出力> [--model <モデル>]: コンテナを伸長する
+//! mgfrc train <モデル> <ファイル>...: ファイルのバイトの頻度からモデルを作る
+//! mgfrc inspect <ファイル>: モデルかコンテナの統計を表示する
+//! mgfrc vectors <ディレクトリ>: 他の言語の実装のためのテストベクタを書き出す
+
+use pdf_set::container::{self, ContainerOptions, Header, FLAG_EMBEDDED_MODEL, MAGIC};
+use pdf_set::range_coder::pmodel::PModel;
+use pdf_set::train::Accumulator;
+use pdf_set::vectors::write_test_vectors;
+use pdf_set::QuantizedPDFSet;
+use std::error::Error;
+use std::fs::{self, File};
+use std::process;
+
+const USAGE: &str = "usage:
+  mgfrc compress <input> <output> [--model <model>]
+  mgfrc decompress <input> <output> [--model <model>]
+  mgfrc train <model> <file>...
+  mgfrc inspect <file>
+  mgfrc vectors <directory>";
+
+type CliResult<T> = std::result::Result<T, Box<dyn Error>>;
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match run(&args) {
+        Ok(report) => print!("{}", report),
+        Err(e) => {
+            eprintln!("mgfrc: {}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// コマンドを実行し，表示する文字列を返す
+fn run(args: &[String]) -> CliResult<String> {
+    let (command, rest) = args.split_first().ok_or(USAGE)?;
+    match command.as_str() {
+        "compress" => {
+            let (paths, model) = paths_and_model(rest)?;
+            let symbols: Vec<usize> = fs::read(&paths[0])?.into_iter().map(usize::from).collect();
+            let data = match model {
+                Some(model) => {
+                    let options = ContainerOptions {
+                        embed_model: false,
+                        ..ContainerOptions::default()
+                    };
+                    container::compress(&model, &symbols, options)?
+                }
+                None => container::compress_two_pass(&symbols, ContainerOptions::default())?,
+            };
+            fs::write(&paths[1], &data)?;
+            Ok(format!("{} -> {} bytes\n", symbols.len(), data.len()))
+        }
+        "decompress" => {
+            let (paths, model) = paths_and_model(rest)?;
+            let symbols = container::decompress(&fs::read(&paths[0])?, model.as_ref())?;
+            let bytes: Vec<u8> = symbols.into_iter().map(|s| s as u8).collect();
+            fs::write(&paths[1], &bytes)?;
+            Ok(format!("{} bytes\n", bytes.len()))
+        }
+        "train" => {
+            let (output, files) = rest.split_first().ok_or(USAGE)?;
+            if files.is_empty() {
+                return Err(USAGE.into());
+            }
+            let mut accumulator = Accumulator::new();
+            for file in files {
+                accumulator.add_reader(File::open(file)?)?;
+            }
+            let model = accumulator.finalize()?;
+            fs::write(output, model.to_bytes())?;
+            Ok(format!(
+                "{} symbols\n{}",
+                accumulator.total(),
+                statistics(&model)
+            ))
+        }
+        "inspect" => match rest {
+            [path] => inspect(&fs::read(path)?),
+            _ => Err(USAGE.into()),
+        },
+        "vectors" => match rest {
+            [dir] => Ok(format!("{}\n", write_test_vectors(dir)?.join("\n"))),
+            _ => Err(USAGE.into()),
+        },
+        _ => Err(USAGE.into()),
+    }
+}
+
+/// 入力と出力のパスと，--modelで与えたモデル
+fn paths_and_model(args: &[String]) -> CliResult<(&[String], Option<QuantizedPDFSet>)> {
+    match args {
+        [_, _] => Ok((args, None)),
+        [_, _, flag, model] if flag == "--model" => {
+            let (model, _) = QuantizedPDFSet::from_bytes(&fs::read(model)?)?;
+            Ok((&args[..2], Some(model)))
+        }
+        _ => Err(USAGE.into()),
+    }
+}
+
+/// コンテナならヘッダと埋め込んだモデルを，そうでなければモデルとして表示する
+fn inspect(bytes: &[u8]) -> CliResult<String> {
+    if !bytes.starts_with(&MAGIC) {
+        return Ok(statistics(&QuantizedPDFSet::from_bytes(bytes)?.0));
+    }
+    let header = Header::from_bytes(bytes)?;
+    let mut report = format!(
+        "container version {}, flags {:#04x}, {} bytes\n",
+        header.version(),
+        header.flags(),
+        bytes.len()
+    );
+    if header.has_flag(FLAG_EMBEDDED_MODEL) {
+        report += &statistics(&QuantizedPDFSet::from_bytes(&bytes[Header::SIZE..])?.0);
+    }
+    Ok(report)
+}
+
+/// モデルの統計
+fn statistics(model: &QuantizedPDFSet) -> String {
+    let total = model.total_freq() as f64;
+    let p = |s: usize| model.c_freq(s) as f64 / total;
+    let entropy: f64 = (0..model.alphabet_size())
+        .map(|s| -p(s) * p(s).log2())
+        .sum();
+    let floor = (0..model.alphabet_size())
+        .filter(|s| model.c_freq(*s) == 1)
+        .count();
+    let mut top: Vec<usize> = (0..model.alphabet_size()).collect();
+    top.sort_by_key(|s| std::cmp::Reverse(model.c_freq(*s)));
+    let top: Vec<String> = top[..4]
+        .iter()
+        .map(|s| format!("{} ({:.4})", s, p(*s)))
+        .collect();
+    format!(
+        concat!(
+            "alphabet size {}{}\n",
+            "entropy {:.4} bits per symbol\n",
+            "{} symbols at the floor frequency\n",
+            "most probable: {}\n",
+            "memory {} bytes\n"
+        ),
+        model.alphabet_size(),
+        if model.has_eof() {
+            " (with end-of-stream)"
+        } else {
+            ""
+        },
+        entropy,
+        floor,
+        top.join(", "),
+        model.memory_usage()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn compress_train_inspect() {
+        let dir = std::env::temp_dir().join(format!("mgfrc-test-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        let text = b"a command-line tool exercising the whole pipeline. ".repeat(40);
+        fs::write(path("input"), &text).unwrap();
+        let run_args = |args: &[&str]| {
+            let args: Vec<String> = args.iter().map(|a| a.to_string()).collect();
+            run(&args)
+        };
+        // 埋め込んだモデルと，学習したモデル
+        run_args(&["compress", &path("input"), &path("packed")]).unwrap();
+        run_args(&["decompress", &path("packed"), &path("output")]).unwrap();
+        assert_eq!(fs::read(path("output")).unwrap(), text);
+        let trained = run_args(&["train", &path("model"), &path("input")]).unwrap();
+        assert!(trained.starts_with(&format!("{} symbols", text.len())));
+        let with_model = ["--model", &path("model")];
+        let (packed, output) = (path("packed"), path("output"));
+        run_args(&[&["compress", &path("input"), &packed][..], &with_model].concat()).unwrap();
+        run_args(&[&["decompress", &packed, &output][..], &with_model].concat()).unwrap();
+        assert_eq!(fs::read(path("output")).unwrap(), text);
+        assert!(run_args(&["decompress", &packed, &output]).is_err());
+        let report = run_args(&["inspect", &path("model")]).unwrap();
+        assert!(report.contains("alphabet size 256"));
+        assert!(run_args(&["inspect", &packed])
+            .unwrap()
+            .starts_with("container"));
+        let vectors = run_args(&["vectors", &path("vectors")]).unwrap();
+        assert!(vectors.contains("laplace_eof\n"));
+        assert!(dir.join("vectors/manifest.csv").exists());
+        assert!(run_args(&["unknown"]).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
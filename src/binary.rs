@@ -0,0 +1,552 @@
+//! 二値算術符号化（CABAC風）
+//! ビットの確率の推定器を表すトレイト: BitEstimator
+//! 適応ビットモデル: BitModel
+//! 状態遷移表による1バイトの推定器: StateModel
+//! 0..=255のシンボルをビット列へ写像する二値化: Binarizer
+//! 二値化とビットモデルをまとめたシンボル符号化器: BinarySymbolCoder
+//! 255節点の二分木の節点毎にビットモデルを持つシンボル符号化器: BinaryTreeCoder
+//! 二値レンジコーダ: BinaryEncoder / BinaryDecoder
+
+use crate::prelude::*;
+use crate::sink::BitSink;
+
+/// precision of bit probability.
+pub const PROB_BITS: u32 = 12;
+const PROB_ONE: u32 = 1 << PROB_BITS;
+const DEFAULT_SHIFT: u32 = 5;
+/// 正規化の閾値
+const TOP: u32 = 1 << 24;
+
+/// estimator of the probability of one binary decision, updated with each coded bit.
+pub trait BitEstimator {
+    /// probability of `false`, scaled by `1 << PROB_BITS`, in `1..(1 << PROB_BITS)`.
+    fn p0(&self) -> u32;
+    fn update(&mut self, bit: bool);
+}
+
+/// adaptive probability model of one binary decision.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BitModel {
+    /// 0が出る確率（PROB_BITSビット固定小数）
+    p0: u16,
+}
+impl BitModel {
+    pub fn new() -> Self {
+        Self {
+            p0: (PROB_ONE / 2) as u16,
+        }
+    }
+    /// probability of `false`, scaled by `1 << PROB_BITS`.
+    pub fn p0(&self) -> u32 {
+        self.p0 as u32
+    }
+    pub fn update(&mut self, bit: bool) {
+        if bit {
+            self.p0 -= self.p0 >> DEFAULT_SHIFT;
+        } else {
+            self.p0 += ((PROB_ONE - self.p0 as u32) >> DEFAULT_SHIFT) as u16;
+        }
+    }
+}
+impl Default for BitModel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl BitEstimator for BitModel {
+    fn p0(&self) -> u32 {
+        BitModel::p0(self)
+    }
+    fn update(&mut self, bit: bool) {
+        BitModel::update(self, bit);
+    }
+}
+
+/// number of probability states of `StateModel`.
+const STATES: usize = 64;
+/// 劣勢シンボルの確率（16ビット固定小数）．状態毎に約0.949倍になる
+const LPS_PROB: [u16; STATES] = lps_prob();
+const fn lps_prob() -> [u16; STATES] {
+    let mut table = [0u16; STATES];
+    let mut p: u32 = 1 << 15;
+    let mut i = 0;
+    while i < STATES {
+        table[i] = p as u16;
+        p = (p * 62_208) >> 16;
+        i += 1;
+    }
+    table
+}
+/// 劣勢シンボルが出たときの遷移先（CABACの表）
+const NEXT_LPS: [u8; STATES] = [
+    0, 0, 1, 2, 2, 4, 4, 5, 6, 7, 8, 9, 9, 11, 11, 12, 13, 13, 15, 15, 16, 16, 18, 18, 19, 19, 21,
+    21, 22, 22, 23, 24, 24, 25, 26, 26, 27, 27, 28, 29, 29, 30, 30, 30, 31, 32, 32, 33, 33, 33, 34,
+    34, 35, 35, 35, 36, 36, 36, 37, 37, 37, 38, 38, 63,
+];
+
+/// finite-state estimator in one byte (a la CABAC):
+/// a probability state and the more probable bit, moved by transition tables.
+/// suited to huge numbers of contexts.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StateModel {
+    /// 下位6ビットが状態，最上位ビットが優勢シンボル
+    state: u8,
+}
+impl StateModel {
+    const MPS_BIT: u8 = 0x80;
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// probability state, 0 (equiprobable) to 62 (most skewed).
+    pub fn state(&self) -> usize {
+        (self.state & !Self::MPS_BIT) as usize
+    }
+    /// the more probable bit.
+    pub fn mps(&self) -> bool {
+        self.state & Self::MPS_BIT != 0
+    }
+}
+impl BitEstimator for StateModel {
+    fn p0(&self) -> u32 {
+        let lps = (LPS_PROB[self.state()] as u32 >> (16 - PROB_BITS)).max(1);
+        if self.mps() {
+            lps
+        } else {
+            PROB_ONE - lps
+        }
+    }
+    fn update(&mut self, bit: bool) {
+        let state = self.state();
+        let mps = self.mps();
+        let (state, mps) = if bit == mps {
+            ((state + 1).min(STATES - 2), mps)
+        } else {
+            // 等確率の状態で劣勢シンボルが出たら，優勢シンボルを入れ替える
+            (
+                NEXT_LPS[state] as usize,
+                if state == 0 { !mps } else { mps },
+            )
+        };
+        self.state = state as u8 | if mps { Self::MPS_BIT } else { 0 };
+    }
+}
+
+/// binary range encoder, writing to a `BitSink`.
+pub struct BinaryEncoder<S: BitSink = Vec<u8>> {
+    low: u64,
+    range: u32,
+    cache: u8,
+    cache_size: u64,
+    data: S,
+}
+impl BinaryEncoder {
+    pub fn new() -> Self {
+        Self::with_sink(Vec::new())
+    }
+    pub fn data(&self) -> &Vec<u8> {
+        &self.data
+    }
+}
+impl<S: BitSink> BinaryEncoder<S> {
+    pub fn with_sink(sink: S) -> Self {
+        Self {
+            low: 0,
+            range: u32::MAX,
+            cache: 0,
+            cache_size: 1,
+            data: sink,
+        }
+    }
+    /// encode one bit with adaptive model, and update the model.
+    pub fn encode<E: BitEstimator>(&mut self, model: &mut E, bit: bool) {
+        let bound = (self.range >> PROB_BITS) * model.p0();
+        if bit {
+            self.low += bound as u64;
+            self.range -= bound;
+        } else {
+            self.range = bound;
+        }
+        model.update(bit);
+        self.normalize();
+    }
+    /// encode one bit with fixed probability 1/2.
+    pub fn encode_bypass(&mut self, bit: bool) {
+        self.range >>= 1;
+        if bit {
+            self.low += self.range as u64;
+        }
+        self.normalize();
+    }
+    pub fn finish(&mut self) {
+        for _ in 0..5 {
+            self.shift_low();
+        }
+    }
+    pub fn sink(&self) -> &S {
+        &self.data
+    }
+    pub fn into_sink(self) -> S {
+        self.data
+    }
+    fn normalize(&mut self) {
+        while self.range < TOP {
+            self.range <<= 8;
+            self.shift_low();
+        }
+    }
+    /// 桁上がりを処理しながら上位バイトを出力する
+    fn shift_low(&mut self) {
+        if (self.low as u32) < 0xFF00_0000 || (self.low >> 32) != 0 {
+            let carry = (self.low >> 32) as u8;
+            let mut byte = self.cache;
+            loop {
+                self.data.put_byte(byte.wrapping_add(carry));
+                byte = 0xFF;
+                self.cache_size -= 1;
+                if self.cache_size == 0 {
+                    break;
+                }
+            }
+            self.cache = (self.low >> 24) as u8;
+        }
+        self.cache_size += 1;
+        self.low = (self.low & 0x00FF_FFFF) << 8;
+    }
+}
+impl Default for BinaryEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// binary range decoder.
+pub struct BinaryDecoder {
+    code: u32,
+    range: u32,
+    data: Vec<u8>,
+    pos: usize,
+}
+impl BinaryDecoder {
+    pub fn new(data: Vec<u8>) -> Self {
+        let mut decoder = Self {
+            code: 0,
+            range: u32::MAX,
+            data,
+            pos: 0,
+        };
+        // 先頭バイトは常に0
+        for _ in 0..5 {
+            decoder.code = (decoder.code << 8) | decoder.next_byte() as u32;
+        }
+        decoder
+    }
+    /// decode one bit with adaptive model, and update the model.
+    pub fn decode<E: BitEstimator>(&mut self, model: &mut E) -> bool {
+        let bound = (self.range >> PROB_BITS) * model.p0();
+        let bit = if self.code < bound {
+            self.range = bound;
+            false
+        } else {
+            self.code -= bound;
+            self.range -= bound;
+            true
+        };
+        model.update(bit);
+        self.normalize();
+        bit
+    }
+    /// decode one bit with fixed probability 1/2.
+    pub fn decode_bypass(&mut self) -> bool {
+        self.range >>= 1;
+        let bit = self.code >= self.range;
+        if bit {
+            self.code -= self.range;
+        }
+        self.normalize();
+        bit
+    }
+    fn normalize(&mut self) {
+        while self.range < TOP {
+            self.range <<= 8;
+            self.code = (self.code << 8) | self.next_byte() as u32;
+        }
+    }
+    fn next_byte(&mut self) -> u8 {
+        // 終端以降は0を読む
+        let byte = self.data.get(self.pos).copied().unwrap_or(0);
+        self.pos += 1;
+        byte
+    }
+}
+
+/// mapping from symbol (0..=255) to bit string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Binarizer {
+    /// `n` ones followed by a zero (no zero for 255).
+    TruncatedUnary,
+    /// k-th order exp-Golomb code.
+    ExpGolomb(u32),
+    /// fixed length binary code, MSB first.
+    Fixed(u32),
+}
+impl Binarizer {
+    /// maximum number of ones in exp-Golomb prefix for symbols in 0..=255.
+    const EG_MAX_PREFIX: usize = 8;
+    /// bit string of the symbol.
+    pub fn binarize(&self, symbol: usize) -> Vec<bool> {
+        assert!(symbol <= self.max_symbol());
+        let mut bits = Vec::new();
+        match *self {
+            Binarizer::TruncatedUnary => {
+                bits.resize(symbol, true);
+                if symbol < u8::MAX as usize {
+                    bits.push(false);
+                }
+            }
+            Binarizer::ExpGolomb(k) => {
+                let (prefix, k, rest) = Self::exp_golomb_split(symbol, k);
+                bits.resize(prefix, true);
+                bits.push(false);
+                bits.extend((0..k).rev().map(|i| (rest >> i) & 1 == 1));
+            }
+            Binarizer::Fixed(n) => bits.extend((0..n).rev().map(|i| (symbol >> i) & 1 == 1)),
+        }
+        bits
+    }
+    /// number of adaptive contexts needed to code with this binarizer.
+    pub fn context_count(&self) -> usize {
+        match *self {
+            Binarizer::TruncatedUnary => u8::MAX as usize,
+            Binarizer::ExpGolomb(_) => Self::EG_MAX_PREFIX + 1,
+            Binarizer::Fixed(n) => n as usize,
+        }
+    }
+    fn max_symbol(&self) -> usize {
+        match *self {
+            Binarizer::Fixed(n) if n < 8 => (1 << n) - 1,
+            _ => u8::MAX as usize,
+        }
+    }
+    /// (プレフィックスの1の数, サフィックスのビット数, サフィックスの値)
+    fn exp_golomb_split(mut symbol: usize, mut k: u32) -> (usize, u32, usize) {
+        let mut prefix = 0;
+        while symbol >= 1 << k {
+            symbol -= 1 << k;
+            k += 1;
+            prefix += 1;
+        }
+        (prefix, k, symbol)
+    }
+}
+
+/// codes symbols (0..=255) as binarized bits with one adaptive model per bin position.
+/// exp-Golomb suffix bits are coded in bypass mode.
+#[derive(Debug, Clone)]
+pub struct BinarySymbolCoder {
+    binarizer: Binarizer,
+    contexts: Vec<BitModel>,
+}
+impl BinarySymbolCoder {
+    pub fn new(binarizer: Binarizer) -> Self {
+        Self {
+            binarizer,
+            contexts: vec![BitModel::new(); binarizer.context_count()],
+        }
+    }
+    pub fn binarizer(&self) -> Binarizer {
+        self.binarizer
+    }
+    pub fn encode<S: BitSink>(&mut self, encoder: &mut BinaryEncoder<S>, symbol: usize) {
+        let bits = self.binarizer.binarize(symbol);
+        match self.binarizer {
+            Binarizer::TruncatedUnary | Binarizer::Fixed(_) => {
+                for (ctx, bit) in self.contexts.iter_mut().zip(bits) {
+                    encoder.encode(ctx, bit);
+                }
+            }
+            Binarizer::ExpGolomb(_) => {
+                let prefix = bits.iter().take_while(|b| **b).count() + 1;
+                for (i, bit) in bits.iter().enumerate() {
+                    if i < prefix {
+                        encoder.encode(&mut self.contexts[i], *bit);
+                    } else {
+                        encoder.encode_bypass(*bit);
+                    }
+                }
+            }
+        }
+    }
+    pub fn decode(&mut self, decoder: &mut BinaryDecoder) -> usize {
+        match self.binarizer {
+            Binarizer::TruncatedUnary => {
+                let mut symbol = 0;
+                while symbol < u8::MAX as usize && decoder.decode(&mut self.contexts[symbol]) {
+                    symbol += 1;
+                }
+                symbol
+            }
+            Binarizer::ExpGolomb(mut k) => {
+                let mut base = 0;
+                let mut prefix = 0;
+                while prefix < Binarizer::EG_MAX_PREFIX
+                    && decoder.decode(&mut self.contexts[prefix])
+                {
+                    base += 1 << k;
+                    k += 1;
+                    prefix += 1;
+                }
+                // 最長のプレフィックスの終端の0を読み捨てる
+                if prefix == Binarizer::EG_MAX_PREFIX {
+                    decoder.decode(&mut self.contexts[prefix]);
+                }
+                (0..k).fold(base, |acc, i| {
+                    acc + ((decoder.decode_bypass() as usize) << (k - 1 - i))
+                })
+            }
+            Binarizer::Fixed(n) => self.contexts[..n as usize]
+                .iter_mut()
+                .fold(0, |acc, ctx| (acc << 1) | decoder.decode(ctx) as usize),
+        }
+    }
+}
+
+/// codes symbols (0..=255) as paths from the root of a binary tree, MSB first,
+/// with one adaptive model per node (255 in total).
+/// adapts much faster than a frequency table on skewed data.
+#[derive(Debug, Clone)]
+pub struct BinaryTreeCoder {
+    /// 節点の番号は根が1，節点nの子は2nと2n+1（0番は使わない）
+    nodes: Vec<BitModel>,
+}
+impl BinaryTreeCoder {
+    pub fn new() -> Self {
+        Self {
+            nodes: vec![BitModel::new(); u8::MAX as usize + 1],
+        }
+    }
+    /// current probability of the symbol, the product of the probabilities along its path.
+    pub fn probability(&self, symbol: usize) -> f64 {
+        assert!(symbol <= u8::MAX as usize);
+        let mut node = 1;
+        let mut p = 1.0;
+        for i in (0..8).rev() {
+            let bit = (symbol >> i) & 1 == 1;
+            let p0 = self.nodes[node].p0() as f64 / PROB_ONE as f64;
+            p *= if bit { 1.0 - p0 } else { p0 };
+            node = (node << 1) | bit as usize;
+        }
+        p
+    }
+    pub fn encode<S: BitSink>(&mut self, encoder: &mut BinaryEncoder<S>, symbol: usize) {
+        assert!(symbol <= u8::MAX as usize);
+        let mut node = 1;
+        for i in (0..8).rev() {
+            let bit = (symbol >> i) & 1 == 1;
+            encoder.encode(&mut self.nodes[node], bit);
+            node = (node << 1) | bit as usize;
+        }
+    }
+    pub fn decode(&mut self, decoder: &mut BinaryDecoder) -> usize {
+        let mut node = 1;
+        while node <= u8::MAX as usize {
+            node = (node << 1) | decoder.decode(&mut self.nodes[node]) as usize;
+        }
+        node - (u8::MAX as usize + 1)
+    }
+}
+impl Default for BinaryTreeCoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    fn roundtrip(binarizer: Binarizer, symbols: &[usize]) -> usize {
+        let mut coder = BinarySymbolCoder::new(binarizer);
+        let mut encoder = BinaryEncoder::new();
+        for s in symbols {
+            coder.encode(&mut encoder, *s);
+        }
+        encoder.finish();
+        let len = encoder.data().len();
+        let mut coder = BinarySymbolCoder::new(binarizer);
+        let mut decoder = BinaryDecoder::new(encoder.data().clone());
+        let decoded: Vec<usize> = symbols.iter().map(|_| coder.decode(&mut decoder)).collect();
+        assert_eq!(symbols, &decoded[..]);
+        len
+    }
+    #[test]
+    fn binarizers_roundtrip() {
+        let symbols: Vec<usize> = (0..=255).chain((0..=255).rev()).collect();
+        roundtrip(Binarizer::TruncatedUnary, &symbols);
+        roundtrip(Binarizer::ExpGolomb(0), &symbols);
+        roundtrip(Binarizer::ExpGolomb(3), &symbols);
+        roundtrip(Binarizer::Fixed(8), &symbols);
+    }
+    #[test]
+    fn skewed_source_compresses() {
+        let symbols: Vec<usize> = (0..4000).map(|i| if i % 17 == 0 { 3 } else { 0 }).collect();
+        let len = roundtrip(Binarizer::ExpGolomb(0), &symbols);
+        assert!(len < symbols.len() / 8);
+    }
+    #[test]
+    fn exp_golomb_codes() {
+        assert_eq!(Binarizer::ExpGolomb(0).binarize(0), vec![false]);
+        assert_eq!(
+            Binarizer::ExpGolomb(0).binarize(3),
+            vec![true, true, false, false, false]
+        );
+        assert_eq!(Binarizer::Fixed(3).binarize(5), vec![true, false, true]);
+        assert_eq!(Binarizer::TruncatedUnary.binarize(255).len(), 255);
+    }
+    #[test]
+    fn tree_adapts_per_symbol() {
+        // 多くのビットが異なる2つの値: ビット位置毎のモデルでは学習できない
+        let symbols: Vec<usize> = (0..2000)
+            .map(|i| if i % 2 == 0 { 0x0F } else { 0xF0 })
+            .collect();
+        let mut tree = BinaryTreeCoder::new();
+        let mut encoder = BinaryEncoder::new();
+        for s in &symbols {
+            tree.encode(&mut encoder, *s);
+        }
+        encoder.finish();
+        assert!(tree.probability(0x0F) > 0.4 && tree.probability(0x00) < 0.01);
+        let fixed = roundtrip(Binarizer::Fixed(8), &symbols);
+        assert!(
+            encoder.data().len() * 2 < fixed,
+            "{} {}",
+            encoder.data().len(),
+            fixed
+        );
+        let mut tree = BinaryTreeCoder::new();
+        let mut decoder = BinaryDecoder::new(encoder.data().clone());
+        for s in &symbols {
+            assert_eq!(tree.decode(&mut decoder), *s);
+        }
+    }
+    #[test]
+    fn state_model_learns() {
+        assert_eq!(core::mem::size_of::<StateModel>(), 1);
+        assert_eq!(StateModel::new().p0(), PROB_ONE / 2);
+        // 文脈が多く，各文脈は偏っている
+        let mut contexts = vec![StateModel::new(); 256];
+        let bits: Vec<(usize, bool)> = (0..40000)
+            .map(|i| (i % 256, (i % 256) % 3 == 0 && i % 23 != 0))
+            .collect();
+        let mut encoder = BinaryEncoder::new();
+        for (ctx, bit) in &bits {
+            encoder.encode(&mut contexts[*ctx], *bit);
+        }
+        encoder.finish();
+        assert!(encoder.data().len() < bits.len() / 8 / 2);
+        assert!(contexts[3].mps() && contexts[3].state() > 0);
+        let mut contexts = vec![StateModel::new(); 256];
+        let mut decoder = BinaryDecoder::new(encoder.data().clone());
+        for (ctx, bit) in &bits {
+            assert_eq!(decoder.decode(&mut contexts[*ctx]), *bit);
+        }
+    }
+}
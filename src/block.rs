@@ -0,0 +1,302 @@
+//! 独立に復号できるブロック単位の符号化と符号量の制御
+//! ブロック符号化器: BlockEncoder
+//! ブロック復号器: BlockDecoder
+//! 符号量の制御: RateController
+//! 複数のスレッドでのブロックの復号: decode_blocks_parallel (feature = "parallel")
+//! ストリーム: ヘッダ（container::Header） | ブロック...
+//! ブロック: モデル番号(u8) | シンボル数(u32 LE) | バイト数(u32 LE) | 符号化したバイト列
+
+use crate::codec::{ModelDecoder, ModelEncoder};
+use crate::container::Header;
+use crate::prelude::*;
+use crate::rate::RateEstimator;
+use crate::{Error, Result};
+use range_coder::pmodel::PModel;
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+
+const BLOCK_HEADER_SIZE: usize = 9;
+
+/// how to code the next block.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RateAction {
+    /// code with the primary model (index 0).
+    Encode,
+    /// code with another model of the given index, e.g. a coarser one.
+    UseModel(usize),
+    /// drop the block, e.g. enhancement data over budget.
+    Skip,
+}
+
+/// consulted by `BlockEncoder` before each block.
+pub trait RateController {
+    /// `bits_spent`: bits output so far. `block_bits`: estimated bits of the next block
+    /// under each candidate model. `BlockEncoder` always passes at least one candidate,
+    /// and a controller should return `RateAction::Encode` when called without any.
+    fn decide(&mut self, bits_spent: usize, block_bits: &[f64]) -> RateAction;
+}
+
+/// keeps the output within a byte budget,
+/// by falling back to the cheapest model and then skipping blocks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ByteBudget {
+    budget_bits: usize,
+}
+impl ByteBudget {
+    pub fn new(bytes: usize) -> Self {
+        Self {
+            budget_bits: bytes * 8,
+        }
+    }
+}
+impl RateController for ByteBudget {
+    fn decide(&mut self, bits_spent: usize, block_bits: &[f64]) -> RateAction {
+        // ブロックのヘッダとフラッシュの分を見込む
+        let overhead = (BLOCK_HEADER_SIZE * 8 + 64) as f64;
+        let fits = |bits: f64| bits_spent as f64 + bits + overhead <= self.budget_bits as f64;
+        let cheapest =
+            match (0..block_bits.len()).min_by(|a, b| block_bits[*a].total_cmp(&block_bits[*b])) {
+                Some(cheapest) => cheapest,
+                None => return RateAction::Encode,
+            };
+        if fits(block_bits[0]) {
+            return RateAction::Encode;
+        }
+        if fits(block_bits[cheapest]) {
+            RateAction::UseModel(cheapest)
+        } else {
+            RateAction::Skip
+        }
+    }
+}
+
+/// encodes blocks of symbols which can be decoded independently.
+pub struct BlockEncoder {
+    out: Vec<u8>,
+    encoder: ModelEncoder,
+}
+impl BlockEncoder {
+    pub fn new() -> Self {
+        Self {
+            out: Header::new(0).to_bytes().to_vec(),
+            encoder: ModelEncoder::new(),
+        }
+    }
+    /// bits output so far.
+    pub fn bits_spent(&self) -> usize {
+        self.out.len() * 8
+    }
+    /// encode a block with `models[model]`.
+    pub fn encode_block<M: PModel>(&mut self, models: &[&M], model: usize, symbols: &[usize]) {
+        assert!(model < models.len() && models.len() <= u8::MAX as usize + 1);
+        self.encoder.encode_slice(models[model], symbols);
+        let data = self.encoder.flush();
+        self.out.push(model as u8);
+        self.out
+            .extend_from_slice(&(symbols.len() as u32).to_le_bytes());
+        self.out
+            .extend_from_slice(&(data.len() as u32).to_le_bytes());
+        self.out.extend(data);
+    }
+    /// let the controller choose a model among `models` (or skip) for the block,
+    /// and return what it chose.
+    ///
+    /// # Panics
+    /// if `models` is empty.
+    pub fn encode_block_with<M: PModel, C: RateController>(
+        &mut self,
+        models: &[&M],
+        symbols: &[usize],
+        controller: &mut C,
+    ) -> RateAction {
+        assert!(!models.is_empty());
+        let block_bits: Vec<f64> = models
+            .iter()
+            .map(|m| {
+                let mut estimator = RateEstimator::new();
+                estimator.add_slice(*m, symbols);
+                estimator.bits()
+            })
+            .collect();
+        let action = controller.decide(self.bits_spent(), &block_bits);
+        match action {
+            RateAction::Encode => self.encode_block(models, 0, symbols),
+            RateAction::UseModel(model) => self.encode_block(models, model, symbols),
+            RateAction::Skip => {}
+        }
+        action
+    }
+    pub fn finish(self) -> Vec<u8> {
+        self.out
+    }
+}
+impl Default for BlockEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// a block in a stream written by `BlockEncoder`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    /// index of the model the block was coded with.
+    pub model: usize,
+    pub symbols: usize,
+    data: core::ops::Range<usize>,
+}
+
+/// decodes a stream written by `BlockEncoder`.
+pub struct BlockDecoder {
+    data: Vec<u8>,
+    blocks: Vec<Block>,
+}
+impl BlockDecoder {
+    pub fn new(data: Vec<u8>) -> Result<Self> {
+        let header = Header::from_bytes(&data)?;
+        if header.flags() != 0 {
+            return Err(Error::UnknownFlags(header.flags()));
+        }
+        let mut pos = Header::SIZE;
+        let mut blocks = Vec::new();
+        while pos < data.len() {
+            let head = data
+                .get(pos..pos + BLOCK_HEADER_SIZE)
+                .ok_or(Error::UnexpectedEof)?;
+            let model = head[0] as usize;
+            let symbols = u32::from_le_bytes([head[1], head[2], head[3], head[4]]) as usize;
+            let len = u32::from_le_bytes([head[5], head[6], head[7], head[8]]) as usize;
+            pos += BLOCK_HEADER_SIZE;
+            if pos + len > data.len() {
+                return Err(Error::UnexpectedEof);
+            }
+            blocks.push(Block {
+                model,
+                symbols,
+                data: pos..pos + len,
+            });
+            pos += len;
+        }
+        Ok(Self { data, blocks })
+    }
+    pub fn blocks(&self) -> &[Block] {
+        &self.blocks
+    }
+    /// decode the block of the index with `models`, as given to the encoder.
+    pub fn decode_block<M: PModel>(&self, index: usize, models: &[&M]) -> Result<Vec<usize>> {
+        let block = self.blocks.get(index).ok_or(Error::UnknownBlock(index))?;
+        let model = models
+            .get(block.model)
+            .ok_or(Error::UnknownModel(block.model))?;
+        // シンボル数は信頼できないデータなので，先に確保しない
+        let mut decoder = ModelDecoder::new(self.data[block.data.clone()].to_vec());
+        (0..block.symbols)
+            .map(|_| decoder.try_decode(*model))
+            .collect()
+    }
+    /// decode all blocks in order.
+    pub fn decode_all<M: PModel>(&self, models: &[&M]) -> Result<Vec<Vec<usize>>> {
+        (0..self.blocks.len())
+            .map(|i| self.decode_block(i, models))
+            .collect()
+    }
+    /// `decode_all` with the blocks spread over the threads of rayon.
+    #[cfg(feature = "parallel")]
+    pub fn decode_all_parallel<M: PModel + Sync>(&self, models: &[&M]) -> Result<Vec<Vec<usize>>> {
+        (0..self.blocks.len())
+            .into_par_iter()
+            .map(|i| self.decode_block(i, models))
+            .collect()
+    }
+}
+
+/// decode a stream written by `BlockEncoder` on multiple threads,
+/// and join the symbols of the blocks in order.
+#[cfg(feature = "parallel")]
+pub fn decode_blocks_parallel<M: PModel + Sync>(
+    data: Vec<u8>,
+    models: &[&M],
+) -> Result<Vec<usize>> {
+    let blocks = BlockDecoder::new(data)?.decode_all_parallel(models)?;
+    Ok(blocks.concat())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{simple_pmodel, GaussianDist};
+    use crate::PDFSet;
+    #[test]
+    fn blocks_roundtrip() {
+        let pm = simple_pmodel();
+        let blocks: Vec<Vec<usize>> = (0..5)
+            .map(|b| (0..40).map(|i| (i * b) % 256).collect())
+            .collect();
+        let mut encoder = BlockEncoder::new();
+        for block in &blocks {
+            encoder.encode_block(&[&pm], 0, block);
+        }
+        let decoder = BlockDecoder::new(encoder.finish()).unwrap();
+        assert_eq!(decoder.blocks().len(), blocks.len());
+        assert_eq!(decoder.decode_all(&[&pm]).unwrap(), blocks);
+        assert!(matches!(
+            decoder.decode_block(5, &[&pm]),
+            Err(Error::UnknownBlock(5))
+        ));
+    }
+    #[test]
+    fn untrusted_symbol_count() {
+        // 4バイトに2^32-1個のシンボルがあると主張するブロック
+        let mut data = Header::new(0).to_bytes().to_vec();
+        data.push(0);
+        data.extend_from_slice(&u32::MAX.to_le_bytes());
+        data.extend_from_slice(&4u32.to_le_bytes());
+        data.extend_from_slice(&[0x12, 0x34, 0x56, 0x78]);
+        let decoder = BlockDecoder::new(data).unwrap();
+        assert!(decoder.decode_block(0, &[&simple_pmodel()]).is_err());
+    }
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_decode() {
+        let pm = simple_pmodel();
+        let blocks: Vec<Vec<usize>> = (0..16)
+            .map(|b| (0..300).map(|i| (i * b + b) % 256).collect())
+            .collect();
+        let mut encoder = BlockEncoder::new();
+        for block in &blocks {
+            encoder.encode_block(&[&pm], 0, block);
+        }
+        let data = encoder.finish();
+        let decoder = BlockDecoder::new(data.clone()).unwrap();
+        assert_eq!(decoder.decode_all_parallel(&[&pm]).unwrap(), blocks);
+        assert_eq!(
+            decode_blocks_parallel(data, &[&pm]).unwrap(),
+            blocks.concat()
+        );
+    }
+    #[test]
+    fn budget_controls_rate() {
+        let fine = simple_pmodel();
+        let coarse = PDFSet::new(vec![GaussianDist {
+            h: 1.0,
+            w: 0.01,
+            m: 128,
+        }])
+        .finalize();
+        let models = [&fine, &coarse];
+        let block: Vec<usize> = (0..100).map(|i| (i * 37) % 256).collect();
+        let mut controller = ByteBudget::new(300);
+        let mut encoder = BlockEncoder::new();
+        let actions: Vec<RateAction> = (0..10)
+            .map(|_| encoder.encode_block_with(&models, &block, &mut controller))
+            .collect();
+        let data = encoder.finish();
+        assert!(data.len() <= 300);
+        assert_eq!(actions[0], RateAction::UseModel(1));
+        assert_eq!(*actions.last().unwrap(), RateAction::Skip);
+        assert_eq!(controller.decide(0, &[]), RateAction::Encode);
+        let decoder = BlockDecoder::new(data).unwrap();
+        for decoded in decoder.decode_all(&models).unwrap() {
+            assert_eq!(decoded, block);
+        }
+    }
+}
@@ -0,0 +1,68 @@
+//! 復号したシンボル列の検査に使うチェックサム
+//! CRC-32 (IEEE 802.3): Crc32
+
+const POLY: u32 = 0xEDB8_8320;
+const TABLE: [u32; 256] = make_table();
+
+const fn make_table() -> [u32; 256] {
+    let mut table = [0u32; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 == 1 { (crc >> 1) ^ POLY } else { crc >> 1 };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// streaming CRC-32.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Crc32 {
+    crc: u32,
+}
+impl Crc32 {
+    pub fn new() -> Self {
+        Self { crc: u32::MAX }
+    }
+    pub fn update(&mut self, bytes: &[u8]) {
+        for b in bytes {
+            self.crc = TABLE[((self.crc ^ *b as u32) & 0xFF) as usize] ^ (self.crc >> 8);
+        }
+    }
+    /// hash a symbol as u16 little endian.
+    pub fn update_symbol(&mut self, symbol: usize) {
+        self.update(&(symbol as u16).to_le_bytes());
+    }
+    pub fn finish(&self) -> u32 {
+        !self.crc
+    }
+}
+impl Default for Crc32 {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+/// CRC-32 of the symbols, each hashed as u16 little endian.
+pub fn symbols_crc32(symbols: &[usize]) -> u32 {
+    let mut crc = Crc32::new();
+    for symbol in symbols {
+        crc.update_symbol(*symbol);
+    }
+    crc.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn check_value() {
+        let mut crc = Crc32::new();
+        crc.update(b"123456789");
+        assert_eq!(crc.finish(), 0xCBF4_3926);
+    }
+}
@@ -0,0 +1,755 @@
+//! バックエンドに依らない高水準の符号化/復号
+//! 符号化器: ModelEncoder
+//! 復号器: ModelDecoder
+//! 終端の方式: Termination
+//! 同期の検査（enable_sentinel）: 一定数のシンボル毎に，それまでのシンボルのハッシュの数ビットを符号化し，
+//! 復号器で照合する．モデルの食い違いによるずれを，ストリームの終わりを待たずに見つけられる
+//! 厳格な符号化（enable_strict）: モデルがほとんど予測しない値の符号化を，写像の誤りとしてエラーにする: Support
+
+use crate::adaptive::Adaptive;
+use crate::backend::{EntropyBackend, RangeCoderBackend};
+use crate::prelude::*;
+use crate::rate::EncoderStats;
+use crate::sink::BitSink;
+use crate::{coded_freq, DecodeError, Error, QuantizedPDFSet, Result, EOF_SYMBOL};
+use range_coder::decoder::Decoder;
+use range_coder::pmodel::PModel;
+
+/// 同期の検査の状態
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Sentinel {
+    interval: usize,
+    bits: u32,
+    /// 前の検査値からのシンボルの数
+    count: usize,
+    /// 先頭からのシンボルのハッシュ（FNV-1a）
+    hash: u32,
+}
+impl Sentinel {
+    const HASH_INIT: u32 = 0x811c_9dc5;
+    fn new(interval: usize, bits: u32) -> Self {
+        assert!(
+            interval > 0 && (1..=16).contains(&bits),
+            "sentinel of interval {} and {} bits",
+            interval,
+            bits
+        );
+        Self {
+            interval,
+            bits,
+            count: 0,
+            hash: Self::HASH_INIT,
+        }
+    }
+    /// シンボルを数え，検査値を符号化する番なら検査値を返す
+    fn push(&mut self, symbol: usize) -> Option<usize> {
+        self.hash = (self.hash ^ symbol as u32).wrapping_mul(0x0100_0193);
+        self.count += 1;
+        if self.count < self.interval {
+            return None;
+        }
+        self.count = 0;
+        Some((self.hash >> (32 - self.bits)) as usize)
+    }
+    fn reset(&mut self) {
+        *self = Self::new(self.interval, self.bits);
+    }
+    fn model(&self) -> UniformModel {
+        UniformModel(1 << self.bits)
+    }
+}
+/// 検査値のための一様分布
+struct UniformModel(u32);
+impl PModel for UniformModel {
+    fn c_freq(&self, _index: usize) -> u32 {
+        1
+    }
+    fn cum_freq(&self, index: usize) -> u32 {
+        index as u32
+    }
+    fn total_freq(&self) -> u32 {
+        self.0
+    }
+    fn find_index(&self, decoder: &Decoder) -> usize {
+        coded_freq(decoder, self.0).min(self.0 as u64 - 1) as usize
+    }
+}
+
+/// the symbols `ModelEncoder::try_encode` accepts after `enable_strict`.
+/// a symbol outside it is coded correctly but costs many bits, which usually means
+/// a bug in mapping the data to symbols rather than unlikely data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Support {
+    min_freq: u32,
+    alphabet_size: usize,
+    symbols: core::ops::Range<usize>,
+}
+impl Support {
+    /// symbols of an alphabet of `alphabet_size` symbols
+    /// with more than the floor frequency 1 of quantized tables.
+    pub fn above_floor(alphabet_size: usize) -> Self {
+        Self {
+            min_freq: 2,
+            alphabet_size,
+            symbols: 0..alphabet_size,
+        }
+    }
+    /// only symbols whose frequency is at least `min_freq`.
+    pub fn with_min_freq(mut self, min_freq: u32) -> Self {
+        self.min_freq = min_freq;
+        self
+    }
+    /// only symbols in `symbols`, e.g. the range the data is trimmed to.
+    pub fn within(mut self, symbols: core::ops::Range<usize>) -> Self {
+        self.symbols = symbols;
+        self
+    }
+    /// the number of symbols of the models the support is for.
+    pub fn alphabet_size(&self) -> usize {
+        self.alphabet_size
+    }
+    /// whether the symbol of the model is in the support.
+    /// symbols outside the alphabet are not, without evaluating the model.
+    pub fn contains<M: PModel>(&self, model: &M, symbol: usize) -> bool {
+        symbol < self.alphabet_size
+            && self.symbols.contains(&symbol)
+            && model.c_freq(symbol) >= self.min_freq
+    }
+}
+
+/// encodes symbols with models over an `EntropyBackend`.
+pub struct ModelEncoder<B: EntropyBackend = RangeCoderBackend> {
+    backend: B,
+    stats: Option<EncoderStats>,
+    sentinel: Option<Sentinel>,
+    strict: Option<Support>,
+}
+impl ModelEncoder {
+    pub fn new() -> Self {
+        Self::from_backend(RangeCoderBackend::new_encoder())
+    }
+}
+impl Default for ModelEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+impl<B: EntropyBackend> ModelEncoder<B> {
+    pub fn from_backend(backend: B) -> Self {
+        Self {
+            backend,
+            stats: None,
+            sentinel: None,
+            strict: None,
+        }
+    }
+    /// after every `interval` symbols, code `bits` (1..=16) bits of a hash of the symbols so far.
+    /// a decoder with the same setting detects a desync within `interval` symbols,
+    /// missing it with probability `2^-bits`, for `bits / interval` extra bits per symbol.
+    ///
+    /// # Panics
+    /// if `interval` is zero or `bits` is not in 1..=16.
+    pub fn enable_sentinel(&mut self, interval: usize, bits: u32) {
+        self.sentinel = Some(Sentinel::new(interval, bits));
+    }
+    /// make `try_encode` fail with `Error::OutsideSupport` on symbols outside `support`.
+    /// `encode` does not check them.
+    pub fn enable_strict(&mut self, support: Support) {
+        self.strict = Some(support);
+    }
+    /// start collecting statistics of the coded symbols and the output.
+    pub fn enable_stats(&mut self) {
+        self.stats.get_or_insert_with(EncoderStats::new);
+    }
+    /// statistics collected since `enable_stats`.
+    /// `reset` does not roll back the symbols of the discarded message.
+    pub fn stats(&self) -> Option<&EncoderStats> {
+        self.stats.as_ref()
+    }
+    pub fn encode<M: PModel>(&mut self, model: &M, symbol: usize) {
+        if let Some(stats) = self.stats.as_mut() {
+            stats.record(model, symbol);
+        }
+        self.backend.encode_symbol(model, symbol);
+        if let Some(sentinel) = self.sentinel.as_mut() {
+            if let Some(check) = sentinel.push(symbol) {
+                self.backend.encode_symbol(&sentinel.model(), check);
+            }
+        }
+    }
+    pub fn encode_slice<M: PModel>(&mut self, model: &M, symbols: &[usize]) {
+        for symbol in symbols {
+            self.encode(model, *symbol);
+        }
+    }
+    /// `encode`, failing without coding the symbol if it is outside the support of `enable_strict`,
+    /// with `Error::SymbolOutOfRange` for a symbol outside the alphabet of the support.
+    pub fn try_encode<M: PModel>(&mut self, model: &M, symbol: usize) -> Result<()> {
+        if let Some(support) = self.strict.as_ref() {
+            if symbol >= support.alphabet_size {
+                return Err(Error::SymbolOutOfRange {
+                    symbol,
+                    alphabet_size: support.alphabet_size,
+                });
+            }
+            if !support.contains(model, symbol) {
+                return Err(Error::OutsideSupport(symbol));
+            }
+        }
+        self.encode(model, symbol);
+        Ok(())
+    }
+    /// `try_encode` of every symbol, stopping at the first one outside the support.
+    pub fn try_encode_slice<M: PModel>(&mut self, model: &M, symbols: &[usize]) -> Result<()> {
+        for symbol in symbols {
+            self.try_encode(model, *symbol)?;
+        }
+        Ok(())
+    }
+    /// encode the symbol, then update the model with it.
+    pub fn encode_adaptive<M: Adaptive>(&mut self, model: &mut M, symbol: usize) {
+        self.encode(model, symbol);
+        model.update(symbol);
+    }
+    /// finish the current message and return its bytes.
+    /// the encoder is ready to encode the next message after this.
+    pub fn flush(&mut self) -> Vec<u8> {
+        if let Some(sentinel) = self.sentinel.as_mut() {
+            sentinel.reset();
+        }
+        let data = self.backend.finish();
+        if let Some(stats) = self.stats.as_mut() {
+            stats.record_output(data.len());
+        }
+        data
+    }
+    /// finish the current message and append its bytes to `out`.
+    /// `out` can be reused over messages, but the range_coder backend still allocates
+    /// a buffer for each message, as its `Encoder` cannot be cleared.
+    pub fn flush_into(&mut self, out: &mut Vec<u8>) {
+        self.flush_to(out);
+    }
+    /// finish the current message and put its bytes to `sink` directly from the backend,
+    /// e.g. into a preallocated `FixedBuffer`, or a `CountingSink` to measure the exact size.
+    pub fn flush_to<S: BitSink>(&mut self, sink: &mut S) {
+        if let Some(sentinel) = self.sentinel.as_mut() {
+            sentinel.reset();
+        }
+        let start = sink.bytes_written();
+        self.backend.finish_to(sink);
+        if let Some(stats) = self.stats.as_mut() {
+            stats.record_output(sink.bytes_written() - start);
+        }
+    }
+    /// discard the current message.
+    pub fn reset(&mut self) {
+        if let Some(sentinel) = self.sentinel.as_mut() {
+            sentinel.reset();
+        }
+        self.backend.reset();
+    }
+    /// finish encoding and return the encoded bytes.
+    pub fn finish(mut self) -> Vec<u8> {
+        self.flush()
+    }
+}
+
+/// saved state of a `ModelDecoder`: the backend's state and the sentinel's.
+#[derive(Clone)]
+pub struct DecoderCheckpoint<C> {
+    backend: C,
+    sentinel: Option<Sentinel>,
+}
+
+/// decodes symbols with models over an `EntropyBackend`.
+pub struct ModelDecoder<B: EntropyBackend = RangeCoderBackend> {
+    backend: B,
+    sentinel: Option<Sentinel>,
+}
+impl ModelDecoder {
+    pub fn new(data: Vec<u8>) -> Self {
+        Self::from_backend(RangeCoderBackend::new_decoder(data))
+    }
+}
+impl<B: EntropyBackend> ModelDecoder<B> {
+    pub fn from_backend(backend: B) -> Self {
+        Self {
+            backend,
+            sentinel: None,
+        }
+    }
+    /// check the hashes coded by an encoder with the same `enable_sentinel`.
+    /// the `try_` methods fail with `DecodeError::Desync` on a mismatch, the others skip the hashes.
+    ///
+    /// # Panics
+    /// if `interval` is zero or `bits` is not in 1..=16.
+    pub fn enable_sentinel(&mut self, interval: usize, bits: u32) {
+        self.sentinel = Some(Sentinel::new(interval, bits));
+    }
+    pub fn decode<M: PModel>(&mut self, model: &M) -> usize {
+        let symbol = self.backend.decode_symbol(model);
+        if let Some(sentinel) = self.sentinel.as_mut() {
+            if sentinel.push(symbol).is_some() {
+                self.backend.decode_symbol(&sentinel.model());
+            }
+        }
+        symbol
+    }
+    /// decode a symbol, then update the model with it.
+    pub fn decode_adaptive<M: Adaptive>(&mut self, model: &mut M) -> usize {
+        let symbol = self.decode(model);
+        model.update(symbol);
+        symbol
+    }
+    /// fill `out` with decoded symbols.
+    pub fn decode_slice<M: PModel>(&mut self, model: &M, out: &mut [usize]) {
+        for symbol in out.iter_mut() {
+            *symbol = self.decode(model);
+        }
+    }
+    /// decode `n` symbols into `out[..n]`, failing on corrupt or truncated data
+    /// and with `Error::BufferFull` if `out` is shorter than `n`.
+    /// without sentinels the backend decodes the whole batch at once (`EntropyBackend::decode_batch`).
+    pub fn decode_batch(
+        &mut self,
+        model: &QuantizedPDFSet,
+        n: usize,
+        out: &mut [usize],
+    ) -> Result<()> {
+        let capacity = out.len();
+        let out = out.get_mut(..n).ok_or(Error::BufferFull {
+            capacity,
+            required: n,
+        })?;
+        if self.sentinel.is_some() {
+            return self.try_decode_slice(model, out);
+        }
+        Ok(self.backend.decode_batch(model, out)?)
+    }
+    /// save the decoder state, including the position between sentinel hashes,
+    /// to come back to it later by `restore`.
+    /// with `RangeCoderBackend` this copies the input, so keep checkpoints few on long streams.
+    pub fn checkpoint(&self) -> DecoderCheckpoint<B::Checkpoint> {
+        DecoderCheckpoint {
+            backend: self.backend.checkpoint(),
+            sentinel: self.sentinel,
+        }
+    }
+    /// go back to a state saved by `checkpoint` on this decoder.
+    pub fn restore(&mut self, checkpoint: &DecoderCheckpoint<B::Checkpoint>) {
+        self.backend.restore(&checkpoint.backend);
+        self.sentinel = checkpoint.sentinel;
+    }
+    /// decode one symbol, failing on corrupt or truncated data.
+    pub fn try_decode<M: PModel>(&mut self, model: &M) -> Result<usize> {
+        let symbol = self.backend.try_decode_symbol(model)?;
+        if let Some(sentinel) = self.sentinel.as_mut() {
+            if let Some(check) = sentinel.push(symbol) {
+                if self.backend.try_decode_symbol(&sentinel.model())? != check {
+                    return Err(DecodeError::Desync.into());
+                }
+            }
+        }
+        Ok(symbol)
+    }
+    /// `decode_adaptive`, failing on corrupt or truncated data.
+    pub fn try_decode_adaptive<M: Adaptive>(&mut self, model: &mut M) -> Result<usize> {
+        let symbol = self.try_decode(model)?;
+        model.update(symbol);
+        Ok(symbol)
+    }
+    /// fill `out` with decoded symbols, failing on corrupt or truncated data.
+    pub fn try_decode_slice<M: PModel>(&mut self, model: &M, out: &mut [usize]) -> Result<()> {
+        for symbol in out.iter_mut() {
+            *symbol = self.try_decode(model)?;
+        }
+        Ok(())
+    }
+}
+
+/// encode all symbols with one model.
+pub fn encode_slice<M: PModel>(model: &M, symbols: &[usize]) -> Vec<u8> {
+    let mut encoder = ModelEncoder::new();
+    encoder.encode_slice(model, symbols);
+    encoder.finish()
+}
+/// decode `len` symbols with one model.
+pub fn decode_slice<M: PModel>(model: &M, data: Vec<u8>, len: usize) -> Vec<usize> {
+    let mut decoded = vec![0; len];
+    ModelDecoder::new(data).decode_slice(model, &mut decoded);
+    decoded
+}
+/// decode `len` symbols with one model, failing on corrupt or truncated data.
+/// `len` may come from untrusted data, so the output grows as symbols are decoded
+/// instead of being allocated for `len` symbols up front.
+pub fn try_decode_slice<M: PModel>(model: &M, data: Vec<u8>, len: usize) -> Result<Vec<usize>> {
+    let mut decoded = Vec::with_capacity(len.min(8 * data.len()));
+    let mut decoder = ModelDecoder::new(data);
+    for _ in 0..len {
+        decoded.push(decoder.try_decode(model)?);
+    }
+    Ok(decoded)
+}
+
+/// how the decoder knows where a message ends.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Termination {
+    /// the end-of-stream symbol is coded after the message, with the model's `with_eof()`.
+    EndSymbol,
+    /// the number of symbols is written before the coded bytes, as u32 little endian.
+    /// longer messages fail with `Error::MessageTooLong`.
+    LengthPrefix,
+}
+/// encode a message so that `decode_terminated` can find its end without knowing its length.
+pub fn encode_terminated(
+    model: &QuantizedPDFSet,
+    symbols: &[usize],
+    termination: Termination,
+) -> Result<Vec<u8>> {
+    if let Some(symbol) = symbols.iter().find(|s| **s > u8::MAX as usize) {
+        return Err(Error::SymbolOutOfRange {
+            symbol: *symbol,
+            alphabet_size: EOF_SYMBOL,
+        });
+    }
+    Ok(match termination {
+        Termination::EndSymbol => {
+            let model = model.with_eof();
+            let mut encoder = ModelEncoder::new();
+            encoder.encode_slice(&model, symbols);
+            encoder.encode(&model, EOF_SYMBOL);
+            encoder.finish()
+        }
+        Termination::LengthPrefix => {
+            if symbols.len() > u32::MAX as usize {
+                return Err(Error::MessageTooLong(symbols.len()));
+            }
+            let mut out = (symbols.len() as u32).to_le_bytes().to_vec();
+            out.extend(encode_slice(model, symbols));
+            out
+        }
+    })
+}
+/// decode a message encoded by `encode_terminated` with the same model and termination.
+pub fn decode_terminated(
+    model: &QuantizedPDFSet,
+    data: Vec<u8>,
+    termination: Termination,
+) -> Result<Vec<usize>> {
+    Ok(match termination {
+        Termination::EndSymbol => {
+            let model = model.with_eof();
+            let mut decoder = ModelDecoder::new(data);
+            let mut decoded = Vec::new();
+            loop {
+                match decoder.try_decode(&model)? {
+                    EOF_SYMBOL => break decoded,
+                    symbol => decoded.push(symbol),
+                }
+            }
+        }
+        Termination::LengthPrefix => {
+            if data.len() < 4 {
+                return Err(Error::UnexpectedEof);
+            }
+            let len = u32::from_le_bytes([data[0], data[1], data[2], data[3]]);
+            try_decode_slice(model, data[4..].to_vec(), len as usize)?
+        }
+    })
+}
+
+/// decode a message encoded by `encode_terminated` with `old_model`
+/// and re-encode it with `new_model`, symbol by symbol in one pass.
+pub fn transcode(
+    input: Vec<u8>,
+    old_model: &QuantizedPDFSet,
+    new_model: &QuantizedPDFSet,
+    termination: Termination,
+) -> Result<Vec<u8>> {
+    let mut encoder = ModelEncoder::new();
+    match termination {
+        Termination::EndSymbol => {
+            let (old_model, new_model) = (old_model.with_eof(), new_model.with_eof());
+            let mut decoder = ModelDecoder::new(input);
+            loop {
+                let symbol = decoder.try_decode(&old_model)?;
+                encoder.encode(&new_model, symbol);
+                if symbol == EOF_SYMBOL {
+                    break Ok(encoder.finish());
+                }
+            }
+        }
+        Termination::LengthPrefix => {
+            if input.len() < 4 {
+                return Err(Error::UnexpectedEof);
+            }
+            let mut out = input[..4].to_vec();
+            let len = u32::from_le_bytes([input[0], input[1], input[2], input[3]]);
+            let mut decoder = ModelDecoder::new(input[4..].to_vec());
+            for _ in 0..len {
+                let symbol = decoder.try_decode(old_model)?;
+                if symbol >= new_model.alphabet_size() {
+                    return Err(Error::SymbolOutOfRange {
+                        symbol,
+                        alphabet_size: new_model.alphabet_size(),
+                    });
+                }
+                encoder.encode(new_model, symbol);
+            }
+            out.extend(encoder.finish());
+            Ok(out)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::simple_pmodel;
+    #[test]
+    fn slice_roundtrip() {
+        let answer = vec![34, 45, 128, 255, 0, 30, 30, 70];
+        let pm = simple_pmodel();
+        let data = encode_slice(&pm, &answer);
+        assert_eq!(answer, decode_slice(&pm, data, answer.len()));
+    }
+    #[test]
+    fn encoder_roundtrip() {
+        let answer: Vec<usize> = (0..=255).collect();
+        let pm = simple_pmodel();
+        let mut encoder = ModelEncoder::new();
+        for s in &answer {
+            encoder.encode(&pm, *s);
+        }
+        let mut decoder = ModelDecoder::new(encoder.finish());
+        let decoded: Vec<usize> = answer.iter().map(|_| decoder.decode(&pm)).collect();
+        assert_eq!(answer, decoded);
+    }
+    #[test]
+    fn reuse_encoder() {
+        let messages = vec![vec![1, 2, 3], vec![128; 10], vec![255, 0]];
+        let pm = simple_pmodel();
+        let mut encoder = ModelEncoder::new();
+        encoder.encode_slice(&pm, &[7, 7, 7]);
+        encoder.reset();
+        let mut out = Vec::new();
+        for message in &messages {
+            out.clear();
+            encoder.encode_slice(&pm, message);
+            encoder.flush_into(&mut out);
+            assert_eq!(out, encode_slice(&pm, message));
+            assert_eq!(*message, decode_slice(&pm, out.clone(), message.len()));
+        }
+    }
+    #[test]
+    fn terminated_roundtrip() {
+        let pm = simple_pmodel();
+        for termination in &[Termination::EndSymbol, Termination::LengthPrefix] {
+            for message in &[vec![], vec![0], vec![34, 45, 128, 255, 0, 255, 255]] {
+                let data = encode_terminated(&pm, message, *termination).unwrap();
+                assert_eq!(*message, decode_terminated(&pm, data, *termination).unwrap());
+            }
+        }
+        assert!(encode_terminated(&pm, &[EOF_SYMBOL], Termination::EndSymbol).is_err());
+        assert!(decode_terminated(&pm, vec![0; 3], Termination::LengthPrefix).is_err());
+    }
+    #[test]
+    fn eof_model() {
+        let pm = simple_pmodel();
+        let eof = pm.with_eof();
+        assert!(!pm.has_eof());
+        assert!(eof.has_eof());
+        assert_eq!(eof.alphabet_size(), EOF_SYMBOL + 1);
+        assert_eq!(eof.c_freq(EOF_SYMBOL), 1);
+        assert!(eof.total_freq() >= pm.total_freq());
+    }
+    #[test]
+    fn checked_decode() {
+        let answer = vec![34, 45, 128, 255, 0, 30, 30, 70];
+        let pm = simple_pmodel();
+        let data = encode_slice(&pm, &answer);
+        assert_eq!(answer, try_decode_slice(&pm, data.clone(), answer.len()).unwrap());
+        // 途切れたデータ
+        let rare = vec![0, 255, 1, 254, 2, 253];
+        let data = encode_slice(&pm, &rare);
+        assert!(try_decode_slice(&pm, data[..4].to_vec(), rare.len()).is_err());
+        // 確率の高い値ばかりのデータも，終わりの先を読んだところで失敗する
+        let likely = crate::PDFSet::new(vec![crate::test_util::GaussianDist {
+            h: 1.0,
+            w: 2.0,
+            m: 100,
+        }])
+        .finalize();
+        let message = vec![100; 4000];
+        let data = encode_slice(&likely, &message);
+        assert_eq!(
+            try_decode_slice(&likely, data.clone(), message.len()).unwrap(),
+            message
+        );
+        let truncated = data[..data.len() - 4].to_vec();
+        // 情報量は途切れたデータのビット数より少ない
+        let bits: f64 = message
+            .iter()
+            .map(|s| crate::rate::symbol_bits(&likely, *s))
+            .sum();
+        assert!(bits < 8.0 * truncated.len() as f64);
+        assert!(matches!(
+            try_decode_slice(&likely, truncated, message.len()),
+            Err(Error::Decode(DecodeError::Truncated { .. }))
+        ));
+        // 終端記号のないデータでも止まる
+        let garbage = vec![0x5a; 16];
+        assert!(decode_terminated(&pm, garbage, Termination::EndSymbol).is_err());
+    }
+    #[test]
+    fn transcode_between_models() {
+        let old = simple_pmodel();
+        let new = crate::PDFSet::new(vec![crate::test_util::GaussianDist {
+            h: 1.0,
+            w: 0.1,
+            m: 100,
+        }])
+        .finalize();
+        let message = vec![34, 45, 128, 255, 0, 100, 101];
+        for termination in &[Termination::EndSymbol, Termination::LengthPrefix] {
+            let data = encode_terminated(&old, &message, *termination).unwrap();
+            let transcoded = transcode(data, &old, &new, *termination).unwrap();
+            assert_eq!(
+                transcoded,
+                encode_terminated(&new, &message, *termination).unwrap()
+            );
+            assert_eq!(
+                message,
+                decode_terminated(&new, transcoded, *termination).unwrap()
+            );
+        }
+    }
+    #[test]
+    fn checkpoint_restore() {
+        let answer: Vec<usize> = (0..64).map(|i| i * 4).collect();
+        let pm = simple_pmodel();
+        let mut decoder = ModelDecoder::new(encode_slice(&pm, &answer));
+        let mut head = vec![0; 10];
+        decoder.decode_slice(&pm, &mut head);
+        let checkpoint = decoder.checkpoint();
+        let mut rest = vec![0; 54];
+        for _ in 0..2 {
+            decoder.decode_slice(&pm, &mut rest);
+            assert_eq!(answer[10..], rest[..]);
+            decoder.restore(&checkpoint);
+        }
+        // 検査値の間の位置も戻す
+        let mut encoder = ModelEncoder::new();
+        encoder.enable_sentinel(5, 8);
+        encoder.encode_slice(&pm, &answer);
+        let mut decoder = ModelDecoder::new(encoder.finish());
+        decoder.enable_sentinel(5, 8);
+        decoder.try_decode_slice(&pm, &mut head[..7]).unwrap();
+        let checkpoint = decoder.checkpoint();
+        for _ in 0..2 {
+            decoder.try_decode_slice(&pm, &mut rest[..20]).unwrap();
+            assert_eq!(answer[7..27], rest[..20]);
+            decoder.restore(&checkpoint);
+        }
+        let mut tail = vec![0; 57];
+        decoder.try_decode_slice(&pm, &mut tail).unwrap();
+        assert_eq!(answer[7..], tail[..]);
+    }
+    #[test]
+    fn batch_decode() {
+        let answer: Vec<usize> = (0..300).map(|i| (i * 7) % 256).collect();
+        let pm = simple_pmodel();
+        let mut decoder = ModelDecoder::new(encode_slice(&pm, &answer));
+        let mut out = vec![0; 400];
+        decoder.decode_batch(&pm, 200, &mut out).unwrap();
+        decoder.decode_batch(&pm, 100, &mut out[200..]).unwrap();
+        assert_eq!(answer[..], out[..300]);
+        assert!(matches!(
+            decoder.decode_batch(&pm, 10, &mut out[395..]),
+            Err(Error::BufferFull {
+                capacity: 5,
+                required: 10
+            })
+        ));
+        // 短いデータの大きなバッチは，途中で打ち切られたことを見つける
+        let mut long = vec![0; 5000];
+        let mut decoder = ModelDecoder::new(encode_slice(&pm, &answer[..10]));
+        assert!(decoder.decode_batch(&pm, 5000, &mut long).is_err());
+        let mut decoder = ModelDecoder::new(encode_slice(&pm, &answer));
+        decoder.enable_sentinel(16, 8);
+        assert!(decoder.decode_batch(&pm, 300, &mut long).is_err());
+    }
+    #[test]
+    fn encoder_stats() {
+        let pm = simple_pmodel();
+        let symbols: Vec<usize> = (0..500).map(|i| [128, 128, 127, 30][i % 4]).collect();
+        let mut encoder = ModelEncoder::new();
+        assert!(encoder.stats().is_none());
+        encoder.enable_stats();
+        encoder.encode_slice(&pm, &symbols);
+        let len = encoder.flush().len();
+        let stats = encoder.stats().unwrap();
+        assert_eq!(stats.symbols(), symbols.len());
+        assert_eq!(stats.output_bits(), len * 8);
+        assert!((stats.model_bits() - pm.estimate_bits(&symbols)).abs() < 1e-6);
+        assert!(stats.entropy_bits() <= stats.model_bits());
+        assert!(stats.redundancy_per_symbol() >= 0.0);
+    }
+    #[test]
+    fn sentinel_detects_desync() {
+        let pm = simple_pmodel();
+        let other = QuantizedPDFSet::from_counts(&[1; 256]).unwrap();
+        let symbols: Vec<usize> = (0..1000).map(|i| [128, 130, 30, 70][i % 4]).collect();
+        let mut encoder = ModelEncoder::new();
+        encoder.enable_sentinel(64, 8);
+        encoder.encode_slice(&pm, &symbols);
+        let data = encoder.finish();
+        assert!(data.len() > encode_slice(&pm, &symbols).len());
+        let mut decoder = ModelDecoder::new(data.clone());
+        decoder.enable_sentinel(64, 8);
+        let mut decoded = vec![0; symbols.len()];
+        decoder.try_decode_slice(&pm, &mut decoded).unwrap();
+        assert_eq!(decoded, symbols);
+        // 途中から違うモデルで復号すると，次の検査値で見つかる
+        let mut decoder = ModelDecoder::new(data);
+        decoder.enable_sentinel(64, 8);
+        let mut decoded = vec![0; 100];
+        decoder.try_decode_slice(&pm, &mut decoded).unwrap();
+        let failed = (0..64).find(|_| decoder.try_decode(&other).is_err());
+        assert!(failed.is_some());
+    }
+    #[test]
+    #[should_panic]
+    fn sentinel_bits_out_of_range() {
+        ModelDecoder::new(vec![0; 8]).enable_sentinel(16, 17);
+    }
+    #[test]
+    fn strict_encode() {
+        let pm = simple_pmodel();
+        let mut encoder = ModelEncoder::new();
+        encoder.enable_strict(Support::above_floor(pm.alphabet_size()).within(30..129));
+        encoder.try_encode_slice(&pm, &[128, 30, 70]).unwrap();
+        // 底上げの頻度しか持たない値と，範囲外の値
+        assert_eq!(pm.c_freq(5), 1);
+        assert!(matches!(
+            encoder.try_encode(&pm, 5),
+            Err(Error::OutsideSupport(5))
+        ));
+        assert!(pm.c_freq(29) > 1);
+        assert!(encoder.try_encode(&pm, 29).is_err());
+        // アルファベットの外の値はモデルを引かずに失敗する
+        let mut all = ModelEncoder::new();
+        all.enable_strict(Support::above_floor(pm.alphabet_size()));
+        assert!(matches!(
+            all.try_encode(&pm, pm.alphabet_size()),
+            Err(Error::SymbolOutOfRange {
+                symbol: 256,
+                alphabet_size: 256
+            })
+        ));
+        assert!(!Support::above_floor(256).within(0..1000).contains(&pm, 300));
+        // 失敗した値は符号化されない
+        let data = encoder.finish();
+        assert_eq!(data, encode_slice(&pm, &[128, 30, 70]));
+    }
+}
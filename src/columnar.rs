@@ -0,0 +1,186 @@
+//! Apache Arrowの列形式への書き出し (feature = "arrow")
+//! 多数の文脈のモデルや学習の統計を，データフレームやSQLのような分析の道具で調べるためのもの
+//! 表は値毎に1行の縦長の形で，モデルや文脈の名前の列で区別する
+//! モデルの表: model_record_batch / ModelBank::to_record_batch
+//!   列: model(Utf8) | symbol(UInt16) | freq(UInt32) | cum_freq(UInt32) | probability(Float64)
+//! 学習の頻度: Trainer::to_record_batch
+//!   列: context(Utf8) | symbol(UInt16) | count(UInt64) | probability(Float64)
+//! 文脈毎の学習の統計: Trainer::summary_record_batch
+//!   列: context(Utf8) | total(UInt64) | distinct_symbols(UInt32) | entropy_bits(Float64)
+
+use crate::bank::ModelBank;
+use crate::prelude::*;
+use crate::train::Trainer;
+use crate::{Error, QuantizedPDFSet, Result};
+use alloc::sync::Arc;
+use arrow::array::{ArrayRef, Float64Array, StringArray, UInt16Array, UInt32Array, UInt64Array};
+use arrow::datatypes::{DataType, Field, Schema};
+use arrow::record_batch::RecordBatch;
+use range_coder::pmodel::PModel;
+
+/// tables of the named models, one row per symbol of each model.
+pub fn model_record_batch(models: &[(&str, &QuantizedPDFSet)]) -> Result<RecordBatch> {
+    let (mut names, mut symbols, mut freq, mut cum_freq, mut probability) =
+        (Vec::new(), Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    for (name, model) in models {
+        let total = model.total_freq() as f64;
+        for symbol in 0..model.alphabet_size() {
+            names.push(*name);
+            symbols.push(symbol as u16);
+            freq.push(model.c_freq(symbol));
+            cum_freq.push(model.cum_freq(symbol));
+            probability.push(model.c_freq(symbol) as f64 / total);
+        }
+    }
+    record_batch(
+        vec![
+            Field::new("model", DataType::Utf8, false),
+            Field::new("symbol", DataType::UInt16, false),
+            Field::new("freq", DataType::UInt32, false),
+            Field::new("cum_freq", DataType::UInt32, false),
+            Field::new("probability", DataType::Float64, false),
+        ],
+        vec![
+            Arc::new(StringArray::from(names)) as ArrayRef,
+            Arc::new(UInt16Array::from(symbols)) as ArrayRef,
+            Arc::new(UInt32Array::from(freq)) as ArrayRef,
+            Arc::new(UInt32Array::from(cum_freq)) as ArrayRef,
+            Arc::new(Float64Array::from(probability)) as ArrayRef,
+        ],
+    )
+}
+
+impl ModelBank {
+    /// `model_record_batch` of the models of the bank, in the order of their ids.
+    pub fn to_record_batch(&self) -> Result<RecordBatch> {
+        let models: Vec<(&str, &QuantizedPDFSet)> = (0..self.len())
+            .map(|id| (self.name(id).unwrap(), self.get(id).unwrap()))
+            .collect();
+        model_record_batch(&models)
+    }
+}
+
+impl Trainer {
+    /// counts of every symbol of every context, with the share of the context's total count.
+    /// the probability is NaN in a context where nothing was counted.
+    pub fn to_record_batch(&self) -> Result<RecordBatch> {
+        let (mut names, mut symbols, mut counts, mut probability) =
+            (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        for context in 0..self.contexts() {
+            let total = self.counts(context).iter().sum::<u64>() as f64;
+            for (symbol, count) in self.counts(context).iter().enumerate() {
+                names.push(self.name(context));
+                symbols.push(symbol as u16);
+                counts.push(*count);
+                probability.push(*count as f64 / total);
+            }
+        }
+        record_batch(
+            vec![
+                Field::new("context", DataType::Utf8, false),
+                Field::new("symbol", DataType::UInt16, false),
+                Field::new("count", DataType::UInt64, false),
+                Field::new("probability", DataType::Float64, false),
+            ],
+            vec![
+                Arc::new(StringArray::from(names)) as ArrayRef,
+                Arc::new(UInt16Array::from(symbols)) as ArrayRef,
+                Arc::new(UInt64Array::from(counts)) as ArrayRef,
+                Arc::new(Float64Array::from(probability)) as ArrayRef,
+            ],
+        )
+    }
+    /// one row per context: the total count, the number of symbols counted at least once,
+    /// and the entropy of the counts in bits per symbol, the size a perfect model would code to.
+    pub fn summary_record_batch(&self) -> Result<RecordBatch> {
+        let (mut names, mut totals, mut distinct, mut entropy) =
+            (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        for context in 0..self.contexts() {
+            let counts = self.counts(context);
+            let total = counts.iter().sum::<u64>();
+            names.push(self.name(context));
+            totals.push(total);
+            distinct.push(counts.iter().filter(|c| **c > 0).count() as u32);
+            entropy.push(
+                counts
+                    .iter()
+                    .filter(|c| **c > 0)
+                    .map(|c| {
+                        let p = *c as f64 / total as f64;
+                        -p * p.log2()
+                    })
+                    .sum::<f64>(),
+            );
+        }
+        record_batch(
+            vec![
+                Field::new("context", DataType::Utf8, false),
+                Field::new("total", DataType::UInt64, false),
+                Field::new("distinct_symbols", DataType::UInt32, false),
+                Field::new("entropy_bits", DataType::Float64, false),
+            ],
+            vec![
+                Arc::new(StringArray::from(names)) as ArrayRef,
+                Arc::new(UInt64Array::from(totals)) as ArrayRef,
+                Arc::new(UInt32Array::from(distinct)) as ArrayRef,
+                Arc::new(Float64Array::from(entropy)) as ArrayRef,
+            ],
+        )
+    }
+}
+
+fn record_batch(fields: Vec<Field>, columns: Vec<ArrayRef>) -> Result<RecordBatch> {
+    RecordBatch::try_new(Arc::new(Schema::new(fields)), columns)
+        .map_err(|e| Error::Arrow(e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::simple_pmodel;
+    fn column<T: Clone + 'static>(batch: &RecordBatch, name: &str) -> T {
+        batch
+            .column_by_name(name)
+            .unwrap()
+            .as_any()
+            .downcast_ref::<T>()
+            .unwrap()
+            .clone()
+    }
+    #[test]
+    fn model_and_training_batches() {
+        let mut bank = ModelBank::new();
+        bank.add("mixture", simple_pmodel()).unwrap();
+        bank.add("eof", simple_pmodel().with_eof()).unwrap();
+        let batch = bank.to_record_batch().unwrap();
+        assert_eq!(batch.num_rows(), 256 + 257);
+        assert_eq!(batch.num_columns(), 5);
+        let names = column::<StringArray>(&batch, "model");
+        let freq = column::<UInt32Array>(&batch, "freq");
+        let cum_freq = column::<UInt32Array>(&batch, "cum_freq");
+        assert_eq!(
+            (names.value(255), names.value(256 + 256)),
+            ("mixture", "eof")
+        );
+        assert_eq!(freq.value(256 + 30), bank.get(1).unwrap().c_freq(30));
+        assert_eq!(cum_freq.value(128), simple_pmodel().cum_freq(128));
+
+        let mut trainer = Trainer::new();
+        let text = trainer.add_context("text");
+        trainer.add_bytes(text, b"aab");
+        let batch = trainer.to_record_batch().unwrap();
+        assert_eq!(batch.num_rows(), 2 * 256);
+        let counts = column::<UInt64Array>(&batch, "count");
+        let probability = column::<Float64Array>(&batch, "probability");
+        assert_eq!(counts.value(256 + b'a' as usize), 2);
+        assert!((probability.value(256 + b'b' as usize) - 1.0 / 3.0).abs() < 1e-12);
+        let summary = trainer.summary_record_batch().unwrap();
+        assert_eq!(column::<UInt64Array>(&summary, "total").value(1), 3);
+        assert_eq!(
+            column::<UInt32Array>(&summary, "distinct_symbols").value(1),
+            2
+        );
+        let entropy = column::<Float64Array>(&summary, "entropy_bits").value(1);
+        assert!((entropy - 0.918_295_834).abs() < 1e-6);
+    }
+}
@@ -0,0 +1,365 @@
+//! 符号化したメッセージを格納するコンテナ形式
+//! ヘッダ: マジック(4バイト) | バージョン(u8) | フラグ(u8)
+//! 本体: [モデル（フラグで指定）] | メッセージ（codec::encode_terminatedの形式）
+//! トレーラ: [復号したシンボル列のCRC-32(u32 LE)（フラグで指定）]
+//! compress_two_passはメッセージの頻度をモデルとして埋め込む
+//! compress_bytesはモデルを持たず，0次の適応モデル（AdaptiveModel）でバイト列を符号化する
+//! 適応モデルの本体: 長さ(u32 LE) | 符号化したバイト列
+
+use crate::adaptive::AdaptiveModel;
+use crate::checksum::{symbols_crc32, Crc32};
+use crate::codec::{decode_terminated, encode_terminated, ModelDecoder, ModelEncoder, Termination};
+use crate::prelude::*;
+use crate::{Error, QuantizedPDFSet, Result, EOF_SYMBOL};
+
+pub const MAGIC: [u8; 4] = *b"MGRC";
+/// current version of the container and stream formats.
+pub const FORMAT_VERSION: u8 = 1;
+/// the model is embedded after the header.
+pub const FLAG_EMBEDDED_MODEL: u8 = 1 << 0;
+/// the message ends with the end-of-stream symbol, instead of a length prefix.
+pub const FLAG_END_SYMBOL: u8 = 1 << 1;
+/// CRC-32 of the symbols is stored at the end, and verified when decoding.
+pub const FLAG_CHECKSUM: u8 = 1 << 2;
+/// the message is coded with an order-0 `AdaptiveModel` of the default rate, instead of a model.
+/// it is not combined with `FLAG_EMBEDDED_MODEL` or `FLAG_END_SYMBOL`.
+pub const FLAG_ADAPTIVE: u8 = 1 << 3;
+/// flags known to this version.
+pub const KNOWN_FLAGS: u8 = FLAG_EMBEDDED_MODEL | FLAG_END_SYMBOL | FLAG_CHECKSUM | FLAG_ADAPTIVE;
+
+/// header of the container and stream formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    version: u8,
+    flags: u8,
+}
+impl Header {
+    pub const SIZE: usize = MAGIC.len() + 2;
+    /// header of the current version.
+    pub fn new(flags: u8) -> Self {
+        Self {
+            version: FORMAT_VERSION,
+            flags,
+        }
+    }
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+    pub fn flags(&self) -> u8 {
+        self.flags
+    }
+    pub fn has_flag(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+    pub fn to_bytes(&self) -> [u8; Header::SIZE] {
+        let mut bytes = [0u8; Header::SIZE];
+        bytes[..4].copy_from_slice(&MAGIC);
+        bytes[4] = self.version;
+        bytes[5] = self.flags;
+        bytes
+    }
+    /// read and validate a header, rejecting unknown versions and flags.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        if bytes.len() < Header::SIZE {
+            return Err(Error::UnexpectedEof);
+        }
+        if bytes[..4] != MAGIC {
+            return Err(Error::BadMagic);
+        }
+        let header = Self {
+            version: bytes[4],
+            flags: bytes[5],
+        };
+        if header.version != FORMAT_VERSION {
+            return Err(Error::UnsupportedVersion(header.version));
+        }
+        if header.flags & !KNOWN_FLAGS != 0 {
+            return Err(Error::UnknownFlags(header.flags & !KNOWN_FLAGS));
+        }
+        Ok(header)
+    }
+}
+
+/// how to write a container.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ContainerOptions {
+    pub embed_model: bool,
+    pub termination: Termination,
+    /// store CRC-32 of the symbols, to detect desynchronization.
+    pub checksum: bool,
+}
+impl Default for ContainerOptions {
+    fn default() -> Self {
+        Self {
+            embed_model: true,
+            termination: Termination::LengthPrefix,
+            checksum: true,
+        }
+    }
+}
+impl ContainerOptions {
+    fn flags(&self) -> u8 {
+        let mut flags = 0;
+        if self.embed_model {
+            flags |= FLAG_EMBEDDED_MODEL;
+        }
+        if self.termination == Termination::EndSymbol {
+            flags |= FLAG_END_SYMBOL;
+        }
+        if self.checksum {
+            flags |= FLAG_CHECKSUM;
+        }
+        flags
+    }
+}
+
+/// encode the message into a container.
+pub fn compress(
+    model: &QuantizedPDFSet,
+    symbols: &[usize],
+    options: ContainerOptions,
+) -> Result<Vec<u8>> {
+    let mut out = Header::new(options.flags()).to_bytes().to_vec();
+    if options.embed_model {
+        out.extend(model.to_bytes());
+    }
+    out.extend(encode_terminated(model, symbols, options.termination)?);
+    if options.checksum {
+        out.extend_from_slice(&symbols_crc32(symbols).to_le_bytes());
+    }
+    Ok(out)
+}
+/// encode the message into a container with its own histogram as the embedded model,
+/// made by a first pass over the symbols. `options.embed_model` is ignored.
+pub fn compress_two_pass(symbols: &[usize], options: ContainerOptions) -> Result<Vec<u8>> {
+    let mut counts = vec![0u64; EOF_SYMBOL];
+    for symbol in symbols {
+        *counts.get_mut(*symbol).ok_or(Error::SymbolOutOfRange {
+            symbol: *symbol,
+            alphabet_size: EOF_SYMBOL,
+        })? += 1;
+    }
+    // 空のメッセージは一様分布で符号化する
+    if symbols.is_empty() {
+        counts.iter_mut().for_each(|c| *c = 1);
+    }
+    let model = QuantizedPDFSet::from_counts(&counts)?;
+    let options = ContainerOptions {
+        embed_model: true,
+        ..options
+    };
+    compress(&model, symbols, options)
+}
+/// compress bytes into a container, with an order-0 adaptive model and a checksum.
+/// no model is needed to decompress it; use `compress` with a trained model for better ratios.
+/// fails with `Error::MessageTooLong` for 4 GiB or more, which the length prefix cannot hold.
+pub fn compress_bytes(bytes: &[u8]) -> Result<Vec<u8>> {
+    if bytes.len() > u32::MAX as usize {
+        return Err(Error::MessageTooLong(bytes.len()));
+    }
+    let mut out = Header::new(FLAG_ADAPTIVE | FLAG_CHECKSUM)
+        .to_bytes()
+        .to_vec();
+    out.extend_from_slice(&(bytes.len() as u32).to_le_bytes());
+    let mut model = AdaptiveModel::new(EOF_SYMBOL);
+    let mut encoder = ModelEncoder::new();
+    // symbols_crc32と同じく，各バイトをu16として数える
+    let mut crc = Crc32::new();
+    for b in bytes {
+        encoder.encode_adaptive(&mut model, *b as usize);
+        crc.update_symbol(*b as usize);
+    }
+    encoder.flush_into(&mut out);
+    out.extend_from_slice(&crc.finish().to_le_bytes());
+    Ok(out)
+}
+/// decompress a container written by `compress_bytes`, or any container of byte symbols.
+pub fn decompress_bytes(data: &[u8]) -> Result<Vec<u8>> {
+    decompress(data, None)?
+        .into_iter()
+        .map(|symbol| {
+            if symbol > u8::MAX as usize {
+                return Err(Error::SymbolOutOfRange {
+                    symbol,
+                    alphabet_size: EOF_SYMBOL,
+                });
+            }
+            Ok(symbol as u8)
+        })
+        .collect()
+}
+/// 適応モデルの本体を復号する
+fn decode_adaptive(body: &[u8]) -> Result<Vec<usize>> {
+    if body.len() < 4 {
+        return Err(Error::UnexpectedEof);
+    }
+    let len = u32::from_le_bytes([body[0], body[1], body[2], body[3]]) as usize;
+    let mut model = AdaptiveModel::new(EOF_SYMBOL);
+    let mut decoder = ModelDecoder::new(body[4..].to_vec());
+    // 壊れた長さで大きな領域を確保しないように，本体の大きさで抑える
+    let mut decoded = Vec::with_capacity(len.min(8 * body.len()));
+    for _ in 0..len {
+        decoded.push(decoder.try_decode_adaptive(&mut model)?);
+    }
+    Ok(decoded)
+}
+
+/// decode a container. `model` is used when the container has no embedded model,
+/// and is not needed by an adaptive container.
+pub fn decompress(data: &[u8], model: Option<&QuantizedPDFSet>) -> Result<Vec<usize>> {
+    let header = Header::from_bytes(data)?;
+    if header.has_flag(FLAG_ADAPTIVE) {
+        let invalid = header.flags() & (FLAG_EMBEDDED_MODEL | FLAG_END_SYMBOL);
+        if invalid != 0 {
+            return Err(Error::UnknownFlags(invalid));
+        }
+    }
+    let mut pos = Header::SIZE;
+    let embedded;
+    let model = if header.has_flag(FLAG_ADAPTIVE) {
+        None
+    } else if header.has_flag(FLAG_EMBEDDED_MODEL) {
+        let (loaded, len) = QuantizedPDFSet::from_bytes(&data[pos..])?;
+        pos += len;
+        embedded = loaded;
+        Some(&embedded)
+    } else {
+        Some(model.ok_or(Error::MissingModel)?)
+    };
+    let termination = if header.has_flag(FLAG_END_SYMBOL) {
+        Termination::EndSymbol
+    } else {
+        Termination::LengthPrefix
+    };
+    let decode_body = |body: &[u8]| match model {
+        Some(model) => decode_terminated(model, body.to_vec(), termination),
+        None => decode_adaptive(body),
+    };
+    if !header.has_flag(FLAG_CHECKSUM) {
+        return decode_body(&data[pos..]);
+    }
+    if data.len() < pos + 4 {
+        return Err(Error::UnexpectedEof);
+    }
+    let (body, trailer) = data[pos..].split_at(data.len() - pos - 4);
+    let decoded = decode_body(body)?;
+    let expected = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+    let actual = symbols_crc32(&decoded);
+    if expected != actual {
+        return Err(Error::ChecksumMismatch { expected, actual });
+    }
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::simple_pmodel;
+    #[test]
+    fn container_roundtrip() {
+        let pm = simple_pmodel();
+        let message = vec![34, 45, 128, 255, 0];
+        for (embed_model, checksum) in &[(true, true), (false, true), (true, false)] {
+            for termination in &[Termination::EndSymbol, Termination::LengthPrefix] {
+                let options = ContainerOptions {
+                    embed_model: *embed_model,
+                    termination: *termination,
+                    checksum: *checksum,
+                };
+                let data = compress(&pm, &message, options).unwrap();
+                assert_eq!(message, decompress(&data, Some(&pm)).unwrap());
+                if *embed_model {
+                    assert_eq!(message, decompress(&data, None).unwrap());
+                } else {
+                    assert!(decompress(&data, None).is_err());
+                }
+            }
+        }
+    }
+    #[test]
+    fn two_pass_roundtrip() {
+        let pm = simple_pmodel();
+        let message: Vec<usize> = (0..2000).map(|i| [3, 3, 3, 250][i % 4]).collect();
+        let data = compress_two_pass(&message, ContainerOptions::default()).unwrap();
+        let static_model = compress(&pm, &message, ContainerOptions::default()).unwrap();
+        assert!(data.len() < static_model.len());
+        assert_eq!(decompress(&data, None).unwrap(), message);
+        let empty = compress_two_pass(&[], ContainerOptions::default()).unwrap();
+        assert!(decompress(&empty, None).unwrap().is_empty());
+        assert!(compress_two_pass(&[256], ContainerOptions::default()).is_err());
+    }
+    #[test]
+    fn reject_unknown_header() {
+        let pm = simple_pmodel();
+        let data = compress(&pm, &[1, 2, 3], ContainerOptions::default()).unwrap();
+        let mut version = data.clone();
+        version[4] = FORMAT_VERSION + 1;
+        assert!(matches!(
+            decompress(&version, None),
+            Err(Error::UnsupportedVersion(_))
+        ));
+        let mut flags = data.clone();
+        flags[5] |= 1 << 7;
+        assert!(matches!(
+            decompress(&flags, None),
+            Err(Error::UnknownFlags(_))
+        ));
+        let mut magic = data;
+        magic[0] = b'X';
+        assert!(matches!(decompress(&magic, None), Err(Error::BadMagic)));
+    }
+    #[test]
+    fn detect_desync() {
+        let pm = simple_pmodel();
+        let message: Vec<usize> = (0..100).map(|i| [128, 127, 30][i % 3]).collect();
+        let options = ContainerOptions {
+            embed_model: false,
+            ..ContainerOptions::default()
+        };
+        let mut data = compress(&pm, &message, options).unwrap();
+        // 本体の途中を壊す
+        data[Header::SIZE + 10] ^= 0x10;
+        assert!(matches!(
+            decompress(&data, Some(&pm)),
+            Err(Error::ChecksumMismatch { .. }) | Err(Error::Decode(_))
+        ));
+    }
+    #[test]
+    fn huge_length_prefix() {
+        let pm = simple_pmodel();
+        let options = ContainerOptions {
+            embed_model: false,
+            ..ContainerOptions::default()
+        };
+        let mut data = compress(&pm, &[1, 2, 3], options).unwrap();
+        // 長さを最大にしても，確保する前に途中で失敗する
+        data[Header::SIZE..Header::SIZE + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(decompress(&data, Some(&pm)).is_err());
+    }
+    #[test]
+    fn bytes_roundtrip() {
+        let text =
+            b"an order-0 adaptive model learns the byte frequencies as it codes. ".repeat(50);
+        let data = compress_bytes(&text).unwrap();
+        assert!(data.len() < text.len() * 3 / 4);
+        assert_eq!(decompress_bytes(&data).unwrap(), text);
+        assert_eq!(decompress(&data, None).unwrap().len(), text.len());
+        let empty = compress_bytes(&[]).unwrap();
+        assert!(decompress_bytes(&empty).unwrap().is_empty());
+        // 他のコンテナのバイト列も読める
+        let pm = simple_pmodel();
+        let other = compress(&pm, &[1, 2, 255], ContainerOptions::default()).unwrap();
+        assert_eq!(decompress_bytes(&other).unwrap(), vec![1, 2, 255]);
+
+        let mut corrupt = data.clone();
+        corrupt[Header::SIZE + 20] ^= 0x10;
+        assert!(decompress_bytes(&corrupt).is_err());
+        assert!(decompress_bytes(&data[..data.len() - 5]).is_err());
+        let mut flags = data;
+        flags[5] |= FLAG_END_SYMBOL;
+        assert!(matches!(
+            decompress_bytes(&flags),
+            Err(Error::UnknownFlags(FLAG_END_SYMBOL))
+        ));
+    }
+}
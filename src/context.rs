@@ -0,0 +1,615 @@
+//! 直前のシンボルを文脈とする文脈モデル
+//! 直前の1シンボルを文脈とするモデル: Order1Model
+//! 直前のNシンボルのハッシュを文脈とするモデル: ContextHashModel
+//! 文脈は符号化/復号したシンボルから自動的に更新する
+//! 呼び出し側が文脈の番号を与えるモデル: ContextModel
+//! 全ての文脈の表を1つの領域に持つContextModel: ArenaContextModel
+
+use crate::adaptive::{Adaptive, AdaptiveModel};
+use crate::backend::EntropyBackend;
+use crate::codec::{ModelDecoder, ModelEncoder};
+use crate::prelude::*;
+use crate::{coded_freq, debug_check_tables, search_cum_freq, QuantizedPDFSet, Result};
+use range_coder::decoder::Decoder;
+use range_coder::pmodel::PModel;
+
+const ALPHABET_SIZE: usize = u8::MAX as usize + 1;
+
+/// order-1 context model: one adaptive model per previous symbol (0..=255).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Order1Model {
+    models: Vec<AdaptiveModel>,
+    context: usize,
+}
+impl Order1Model {
+    /// all sub-models start from the uniform distribution.
+    pub fn new() -> Self {
+        Self::from_model(AdaptiveModel::new(ALPHABET_SIZE))
+    }
+    /// all sub-models start from the static model.
+    pub fn from_prior(prior: &QuantizedPDFSet) -> Self {
+        Self::from_model(AdaptiveModel::from_prior(prior))
+    }
+    /// all sub-models start as copies of `model`, which must have 256 symbols.
+    pub fn from_model(model: AdaptiveModel) -> Self {
+        assert_eq!(model.alphabet_size(), ALPHABET_SIZE);
+        Self {
+            models: vec![model; ALPHABET_SIZE],
+            context: 0,
+        }
+    }
+    /// current context, the previous symbol (0 at the start).
+    pub fn context(&self) -> usize {
+        self.context
+    }
+    /// sub-model for the context.
+    pub fn model(&self, context: usize) -> &AdaptiveModel {
+        &self.models[context]
+    }
+    /// bytes used by the models of all contexts.
+    pub fn memory_usage(&self) -> usize {
+        core::mem::size_of::<Self>() + self.models.iter().map(|m| m.memory_usage()).sum::<usize>()
+    }
+    /// go back to the context at the start of a message, keeping what was learned.
+    pub fn reset_context(&mut self) {
+        self.context = 0;
+    }
+    pub fn encode<B: EntropyBackend>(&mut self, encoder: &mut ModelEncoder<B>, symbol: usize) {
+        encoder.encode_adaptive(&mut self.models[self.context], symbol);
+        self.context = symbol;
+    }
+    pub fn decode<B: EntropyBackend>(&mut self, decoder: &mut ModelDecoder<B>) -> usize {
+        let symbol = decoder.decode_adaptive(&mut self.models[self.context]);
+        self.context = symbol;
+        symbol
+    }
+    /// `decode`, failing on corrupt or truncated data.
+    pub fn try_decode<B: EntropyBackend>(
+        &mut self,
+        decoder: &mut ModelDecoder<B>,
+    ) -> Result<usize> {
+        let symbol = decoder.try_decode_adaptive(&mut self.models[self.context])?;
+        self.context = symbol;
+        Ok(symbol)
+    }
+    /// learn from a symbol without coding it.
+    pub fn update(&mut self, symbol: usize) {
+        self.models[self.context].update(symbol);
+        self.context = symbol;
+    }
+}
+impl Default for Order1Model {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// 表の1つの要素
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Slot {
+    /// 衝突を見分けるための，ハッシュの上位ビット
+    check: u32,
+    /// この要素を使った回数．置き換える要素を選ぶのに使う
+    uses: u32,
+    /// 最後に時計の針が通ってから使われたか
+    referenced: bool,
+    model: AdaptiveModel,
+}
+
+/// order-N context model: the last `order` symbols are hashed into a table of
+/// `1 << table_bits` adaptive models, allocated when first used.
+/// each context may be in one of two slots; on a collision, the less used one is reset.
+/// with `with_budget`, the number of allocated models is bounded,
+/// and contexts not used recently are evicted by the clock algorithm.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContextHashModel {
+    order: usize,
+    table_bits: u32,
+    slots: Vec<Option<Slot>>,
+    /// 確保するモデルの数の上限
+    max_models: usize,
+    /// 確保しているモデルの数
+    allocated: usize,
+    /// 時計の針の位置
+    hand: usize,
+    /// 新しい文脈のモデルの初期状態
+    initial: AdaptiveModel,
+    /// 直前のorder個のシンボル（古い順）
+    history: Vec<u8>,
+    /// 現在の文脈のslotsの位置
+    current: usize,
+}
+impl ContextHashModel {
+    /// new contexts start from the uniform distribution.
+    pub fn new(order: usize, table_bits: u32) -> Self {
+        Self::from_model(order, table_bits, AdaptiveModel::new(ALPHABET_SIZE))
+    }
+    /// new contexts start from the static model.
+    pub fn from_prior(order: usize, table_bits: u32, prior: &QuantizedPDFSet) -> Self {
+        Self::from_model(order, table_bits, AdaptiveModel::from_prior(prior))
+    }
+    /// new contexts start as copies of `model`, which must have 256 symbols.
+    pub fn from_model(order: usize, table_bits: u32, model: AdaptiveModel) -> Self {
+        assert_eq!(model.alphabet_size(), ALPHABET_SIZE);
+        assert!(order > 0 && (1..=24).contains(&table_bits));
+        let mut context = Self {
+            order,
+            table_bits,
+            slots: vec![None; 1 << table_bits],
+            max_models: 1 << table_bits,
+            allocated: 0,
+            hand: 0,
+            initial: model,
+            history: vec![0; order],
+            current: 0,
+        };
+        context.select();
+        context
+    }
+    /// limit the memory of the context models to about `bytes`, keeping at least one model.
+    pub fn with_budget(mut self, bytes: usize) -> Self {
+        self.max_models = (bytes / Self::model_bytes()).clamp(1, self.slots.len());
+        while self.allocated > self.max_models {
+            self.evict();
+        }
+        // 現在の文脈は追い出さない
+        if self.slots[self.current].is_none() {
+            self.select();
+        }
+        self
+    }
+    pub fn order(&self) -> usize {
+        self.order
+    }
+    /// number of contexts which have a model allocated.
+    pub fn contexts_in_use(&self) -> usize {
+        self.allocated
+    }
+    /// approximate bytes used by the table and the allocated models.
+    pub fn memory_usage(&self) -> usize {
+        self.slots.len() * core::mem::size_of::<Option<Slot>>()
+            + self.allocated * (Self::model_bytes() - core::mem::size_of::<Slot>())
+    }
+    /// 1つの文脈のモデルが使うバイト数の見積もり（頻度と累積頻度）
+    fn model_bytes() -> usize {
+        core::mem::size_of::<Slot>() + 2 * (ALPHABET_SIZE + 1) * core::mem::size_of::<u32>()
+    }
+    /// go back to the context at the start of a message, keeping what was learned.
+    pub fn reset_context(&mut self) {
+        self.history.iter_mut().for_each(|h| *h = 0);
+        self.select();
+    }
+    pub fn encode<B: EntropyBackend>(&mut self, encoder: &mut ModelEncoder<B>, symbol: usize) {
+        encoder.encode_adaptive(self.model_mut(), symbol);
+        self.push(symbol);
+    }
+    pub fn decode<B: EntropyBackend>(&mut self, decoder: &mut ModelDecoder<B>) -> usize {
+        let symbol = decoder.decode_adaptive(self.model_mut());
+        self.push(symbol);
+        symbol
+    }
+    /// `decode`, failing on corrupt or truncated data.
+    pub fn try_decode<B: EntropyBackend>(
+        &mut self,
+        decoder: &mut ModelDecoder<B>,
+    ) -> Result<usize> {
+        let symbol = decoder.try_decode_adaptive(self.model_mut())?;
+        self.push(symbol);
+        Ok(symbol)
+    }
+    /// learn from a symbol without coding it.
+    pub fn update(&mut self, symbol: usize) {
+        self.model_mut().update(symbol);
+        self.push(symbol);
+    }
+    fn model_mut(&mut self) -> &mut AdaptiveModel {
+        let slot = self.slots[self.current].as_mut().unwrap();
+        slot.uses = slot.uses.saturating_add(1);
+        slot.referenced = true;
+        &mut slot.model
+    }
+    fn push(&mut self, symbol: usize) {
+        self.history.rotate_left(1);
+        *self.history.last_mut().unwrap() = symbol as u8;
+        self.select();
+    }
+    /// 現在の文脈のモデルを探し，なければ確保する
+    fn select(&mut self) {
+        // FNV-1a
+        let hash = self.history.iter().fold(0xcbf2_9ce4_8422_2325u64, |h, b| {
+            (h ^ *b as u64).wrapping_mul(0x0100_0000_01b3)
+        });
+        let mask = (1 << self.table_bits) - 1;
+        let first = (hash as usize) & mask;
+        let second = first ^ 1;
+        let check = (hash >> 32) as u32;
+        let found = [first, second]
+            .iter()
+            .copied()
+            .find(|i| matches!(&self.slots[*i], Some(slot) if slot.check == check));
+        self.current = match found {
+            Some(index) => index,
+            None => {
+                // 空いている方か，使われていない方を置き換える
+                let uses = |i: usize| self.slots[i].as_ref().map_or(0, |s| s.uses);
+                let index = if uses(second) < uses(first) {
+                    second
+                } else {
+                    first
+                };
+                if self.slots[index].is_none() {
+                    if self.allocated == self.max_models {
+                        self.evict();
+                    }
+                    self.allocated += 1;
+                }
+                self.slots[index] = Some(Slot {
+                    check,
+                    uses: 0,
+                    referenced: true,
+                    model: self.initial.clone(),
+                });
+                index
+            }
+        };
+    }
+    /// 時計の針を進め，最近使われていないモデルを1つ解放する
+    fn evict(&mut self) {
+        loop {
+            let hand = self.hand;
+            self.hand = (self.hand + 1) % self.slots.len();
+            if let Some(slot) = &mut self.slots[hand] {
+                if slot.referenced {
+                    slot.referenced = false;
+                } else {
+                    self.slots[hand] = None;
+                    self.allocated -= 1;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// one adaptive model per context id given by the caller,
+/// e.g. from `context_quant` or `image`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContextModel {
+    models: Vec<AdaptiveModel>,
+}
+impl ContextModel {
+    /// `contexts` models starting from the uniform distribution over `alphabet_size` symbols.
+    pub fn new(contexts: usize, alphabet_size: usize) -> Self {
+        Self::from_model(contexts, AdaptiveModel::new(alphabet_size))
+    }
+    /// `contexts` models starting from the static model.
+    pub fn from_prior(contexts: usize, prior: &QuantizedPDFSet) -> Self {
+        Self::from_model(contexts, AdaptiveModel::from_prior(prior))
+    }
+    /// `contexts` copies of `model`.
+    pub fn from_model(contexts: usize, model: AdaptiveModel) -> Self {
+        assert!(contexts > 0);
+        Self {
+            models: vec![model; contexts],
+        }
+    }
+    /// number of contexts.
+    pub fn contexts(&self) -> usize {
+        self.models.len()
+    }
+    pub fn model(&self, context: usize) -> &AdaptiveModel {
+        &self.models[context]
+    }
+    /// bytes used by the models of all contexts.
+    pub fn memory_usage(&self) -> usize {
+        core::mem::size_of::<Self>() + self.models.iter().map(|m| m.memory_usage()).sum::<usize>()
+    }
+    pub fn encode<B: EntropyBackend>(
+        &mut self,
+        encoder: &mut ModelEncoder<B>,
+        context: usize,
+        symbol: usize,
+    ) {
+        encoder.encode_adaptive(&mut self.models[context], symbol);
+    }
+    pub fn decode<B: EntropyBackend>(
+        &mut self,
+        decoder: &mut ModelDecoder<B>,
+        context: usize,
+    ) -> usize {
+        decoder.decode_adaptive(&mut self.models[context])
+    }
+    /// `decode`, failing on corrupt or truncated data.
+    pub fn try_decode<B: EntropyBackend>(
+        &mut self,
+        decoder: &mut ModelDecoder<B>,
+        context: usize,
+    ) -> Result<usize> {
+        decoder.try_decode_adaptive(&mut self.models[context])
+    }
+    /// learn from a symbol without coding it.
+    pub fn update(&mut self, context: usize, symbol: usize) {
+        self.models[context].update(symbol);
+    }
+}
+
+/// `ContextModel` with the tables of all contexts in one allocation:
+/// for each context, the counts, the cumulative counts and the total.
+/// contexts update exactly as `AdaptiveModel`, and `reset` starts a new frame without allocating.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArenaContextModel {
+    arena: Vec<u32>,
+    /// 1つの文脈の表（初期状態）
+    initial: Box<[u32]>,
+    alphabet_size: usize,
+    increment: u32,
+    limit: u32,
+}
+impl ArenaContextModel {
+    /// `contexts` models starting from the uniform distribution over `alphabet_size` symbols.
+    pub fn new(contexts: usize, alphabet_size: usize) -> Self {
+        Self::from_model(contexts, &AdaptiveModel::new(alphabet_size))
+    }
+    /// `contexts` models starting from the static model.
+    pub fn from_prior(contexts: usize, prior: &QuantizedPDFSet) -> Self {
+        Self::from_model(contexts, &AdaptiveModel::from_prior(prior))
+    }
+    /// `contexts` copies of `model`, with its adaptation rate.
+    pub fn from_model(contexts: usize, model: &AdaptiveModel) -> Self {
+        assert!(contexts > 0 && model.batch() == 1);
+        let n = model.alphabet_size();
+        let initial: Box<[u32]> = (0..n)
+            .map(|i| model.c_freq(i))
+            .chain((0..n).map(|i| model.cum_freq(i)))
+            .chain(core::iter::once(model.total_freq()))
+            .collect();
+        let mut arena = Vec::with_capacity(contexts * initial.len());
+        for _ in 0..contexts {
+            arena.extend_from_slice(&initial);
+        }
+        let rate = model.rate();
+        Self {
+            arena,
+            initial,
+            alphabet_size: n,
+            increment: rate.increment,
+            limit: rate.limit,
+        }
+    }
+    /// number of contexts.
+    pub fn contexts(&self) -> usize {
+        self.arena.len() / self.initial.len()
+    }
+    /// model of the context, e.g. to estimate its rate.
+    pub fn model(&self, context: usize) -> ArenaModel<'_> {
+        let n = self.alphabet_size;
+        let table = &self.arena[context * self.initial.len()..][..self.initial.len()];
+        ArenaModel {
+            freq: &table[..n],
+            cum_freq: &table[n..2 * n],
+            total: table[2 * n],
+        }
+    }
+    /// put all contexts back to the initial state, reusing the allocation.
+    pub fn reset(&mut self) {
+        for table in self.arena.chunks_exact_mut(self.initial.len()) {
+            table.copy_from_slice(&self.initial);
+        }
+    }
+    /// bytes used by the tables.
+    pub fn memory_usage(&self) -> usize {
+        core::mem::size_of::<Self>()
+            + (self.arena.capacity() + self.initial.len()) * core::mem::size_of::<u32>()
+    }
+    pub fn encode<B: EntropyBackend>(
+        &mut self,
+        encoder: &mut ModelEncoder<B>,
+        context: usize,
+        symbol: usize,
+    ) {
+        encoder.encode(&self.model(context), symbol);
+        self.update(context, symbol);
+    }
+    pub fn decode<B: EntropyBackend>(
+        &mut self,
+        decoder: &mut ModelDecoder<B>,
+        context: usize,
+    ) -> usize {
+        let symbol = decoder.decode(&self.model(context));
+        self.update(context, symbol);
+        symbol
+    }
+    /// `decode`, failing on corrupt or truncated data.
+    pub fn try_decode<B: EntropyBackend>(
+        &mut self,
+        decoder: &mut ModelDecoder<B>,
+        context: usize,
+    ) -> Result<usize> {
+        let symbol = decoder.try_decode(&self.model(context))?;
+        self.update(context, symbol);
+        Ok(symbol)
+    }
+    /// learn from a symbol without coding it.
+    pub fn update(&mut self, context: usize, symbol: usize) {
+        let n = self.alphabet_size;
+        let table = &mut self.arena[context * self.initial.len()..][..self.initial.len()];
+        let (freq, rest) = table.split_at_mut(n);
+        let (cum_freq, total) = rest.split_at_mut(n);
+        freq[symbol] += self.increment;
+        for c in cum_freq[symbol + 1..].iter_mut() {
+            *c += self.increment;
+        }
+        total[0] += self.increment;
+        while total[0] > self.limit {
+            // AdaptiveModel::rescaleと同じく，0にならないように半分にする
+            let mut cum = 0;
+            for (f, c) in freq.iter_mut().zip(cum_freq.iter_mut()) {
+                *f = (*f).div_ceil(2);
+                *c = cum;
+                cum += *f;
+            }
+            total[0] = cum;
+        }
+        debug_check_tables(freq, cum_freq, total[0]);
+    }
+}
+
+/// model of a context of `ArenaContextModel`.
+#[derive(Debug, Clone, Copy)]
+pub struct ArenaModel<'a> {
+    freq: &'a [u32],
+    cum_freq: &'a [u32],
+    total: u32,
+}
+impl PModel for ArenaModel<'_> {
+    fn c_freq(&self, index: usize) -> u32 {
+        self.freq[index]
+    }
+    fn cum_freq(&self, index: usize) -> u32 {
+        self.cum_freq[index]
+    }
+    fn total_freq(&self) -> u32 {
+        self.total
+    }
+    fn find_index(&self, decoder: &Decoder) -> usize {
+        search_cum_freq(self.cum_freq, coded_freq(decoder, self.total))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::simple_pmodel;
+    #[test]
+    fn order1_beats_order0() {
+        // 次のシンボルは直前のシンボルでほぼ決まる
+        let text = b"abracadabra abracadabra abracadabra ".repeat(30);
+        let symbols: Vec<usize> = text.iter().map(|b| *b as usize).collect();
+        let mut order0 = AdaptiveModel::new(ALPHABET_SIZE);
+        let mut encoder = ModelEncoder::new();
+        for s in &symbols {
+            encoder.encode_adaptive(&mut order0, *s);
+        }
+        let order0_len = encoder.finish().len();
+        let mut model = Order1Model::new();
+        let mut encoder = ModelEncoder::new();
+        for s in &symbols {
+            model.encode(&mut encoder, *s);
+        }
+        let data = encoder.finish();
+        assert!(data.len() < order0_len, "{} {}", data.len(), order0_len);
+        let mut model = Order1Model::new();
+        let mut decoder = ModelDecoder::new(data);
+        for s in &symbols {
+            assert_eq!(model.try_decode(&mut decoder).unwrap(), *s);
+        }
+        assert_eq!(model.context(), b' ' as usize);
+    }
+    #[test]
+    fn memory_usage_per_context() {
+        let one = AdaptiveModel::new(ALPHABET_SIZE).memory_usage();
+        assert!(one >= 2 * ALPHABET_SIZE * 4);
+        let model = ContextModel::new(10, ALPHABET_SIZE);
+        assert_eq!(
+            model.memory_usage(),
+            core::mem::size_of::<ContextModel>() + 10 * one
+        );
+        assert!(Order1Model::new().memory_usage() > ALPHABET_SIZE * one);
+    }
+    #[test]
+    fn hashed_contexts_roundtrip() {
+        let text = b"the quick brown fox jumps over the lazy dog. ".repeat(20);
+        let symbols: Vec<usize> = text.iter().map(|b| *b as usize).collect();
+        let encode = |mut model: ContextHashModel| {
+            let mut encoder = ModelEncoder::new();
+            for s in &symbols {
+                model.encode(&mut encoder, *s);
+            }
+            encoder.finish()
+        };
+        let order1 = encode(ContextHashModel::new(1, 12));
+        let order3 = encode(ContextHashModel::new(3, 12));
+        assert!(
+            order3.len() < order1.len(),
+            "{} {}",
+            order3.len(),
+            order1.len()
+        );
+        // 表が小さく衝突が多くても復号できる
+        for (order, bits) in &[(3, 12), (4, 3)] {
+            let data = encode(ContextHashModel::new(*order, *bits));
+            let mut model = ContextHashModel::new(*order, *bits);
+            let mut decoder = ModelDecoder::new(data);
+            for s in &symbols {
+                assert_eq!(model.try_decode(&mut decoder).unwrap(), *s);
+            }
+            assert!(model.contexts_in_use() <= 1 << *bits);
+        }
+    }
+    #[test]
+    fn budget_bounds_memory() {
+        let symbols: Vec<usize> = (0..3000).map(|i| (i * 7 + i / 13) % 256).collect();
+        let budget = 16 * 1024;
+        let mut model = ContextHashModel::new(2, 16).with_budget(budget);
+        let mut encoder = ModelEncoder::new();
+        for s in &symbols {
+            model.encode(&mut encoder, *s);
+            assert!(model.contexts_in_use() * 2 * 257 * 4 <= budget);
+        }
+        let unbounded = ContextHashModel::new(2, 16);
+        assert!(model.memory_usage() < unbounded.memory_usage() + budget + 1024);
+        let data = encoder.finish();
+        let mut model = ContextHashModel::new(2, 16).with_budget(budget);
+        let mut decoder = ModelDecoder::new(data);
+        for s in &symbols {
+            assert_eq!(model.try_decode(&mut decoder).unwrap(), *s);
+        }
+    }
+    #[test]
+    fn explicit_contexts() {
+        // 偶数番目と奇数番目で分布が異なる
+        let symbols: Vec<usize> = (0..1000).map(|i| if i % 2 == 0 { 3 } else { 9 }).collect();
+        let mut model = ContextModel::new(2, 16);
+        let mut encoder = ModelEncoder::new();
+        for (i, s) in symbols.iter().enumerate() {
+            model.encode(&mut encoder, i % 2, *s);
+        }
+        let data = encoder.finish();
+        assert!(data.len() < 20, "{}", data.len());
+        let mut model = ContextModel::new(2, 16);
+        let mut decoder = ModelDecoder::new(data);
+        for (i, s) in symbols.iter().enumerate() {
+            assert_eq!(model.try_decode(&mut decoder, i % 2).unwrap(), *s);
+        }
+    }
+    #[test]
+    fn arena_matches_context_model() {
+        let symbols: Vec<usize> = (0..3000).map(|i| (i * i / 7) % 16).collect();
+        let prior = simple_pmodel();
+        let mut boxed = ContextModel::from_model(4, AdaptiveModel::new(16).with_limit(256));
+        let mut arena = ArenaContextModel::from_model(4, &AdaptiveModel::new(16).with_limit(256));
+        assert_eq!(arena.contexts(), 4);
+        let (mut a, mut b) = (ModelEncoder::new(), ModelEncoder::new());
+        for (i, s) in symbols.iter().enumerate() {
+            boxed.encode(&mut a, i % 4, *s);
+            arena.encode(&mut b, i % 4, *s);
+        }
+        let data = b.finish();
+        assert_eq!(a.finish(), data);
+        arena.reset();
+        assert_eq!(
+            arena,
+            ArenaContextModel::from_model(4, &AdaptiveModel::new(16).with_limit(256))
+        );
+        let mut decoder = ModelDecoder::new(data);
+        for (i, s) in symbols.iter().enumerate() {
+            assert_eq!(arena.try_decode(&mut decoder, i % 4).unwrap(), *s);
+        }
+        let from_prior = ArenaContextModel::from_prior(2, &prior);
+        assert_eq!(
+            from_prior.model(1).c_freq(128),
+            AdaptiveModel::from_prior(&prior).c_freq(128)
+        );
+        assert!(from_prior.memory_usage() >= 2 * (2 * 256 + 1) * 4);
+    }
+}
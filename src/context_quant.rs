@@ -0,0 +1,165 @@
+//! 文脈の量子化
+//! 多次元の文脈（周囲の残差の大きさなど）を，少数の文脈の番号へ写す
+//! 番号はcontext::ContextModelの文脈として使う
+//! 閾値による1次元の量子化: ThresholdQuantizer
+//! 次元毎の量子化の組み合わせ: ContextQuantizer
+//! k-meansで学習した代表点による量子化: KMeansQuantizer
+
+use crate::prelude::*;
+use crate::{Error, Result};
+
+/// maps a value to a bucket by ascending thresholds:
+/// bucket `i` holds values in `thresholds[i - 1]..thresholds[i]`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThresholdQuantizer {
+    thresholds: Vec<i64>,
+}
+impl ThresholdQuantizer {
+    /// fails if the thresholds are not strictly ascending.
+    pub fn new(thresholds: Vec<i64>) -> Result<Self> {
+        if thresholds.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(Error::InvalidThresholds);
+        }
+        Ok(Self { thresholds })
+    }
+    /// number of buckets, one more than the thresholds.
+    pub fn buckets(&self) -> usize {
+        self.thresholds.len() + 1
+    }
+    pub fn bucket(&self, value: i64) -> usize {
+        self.thresholds.partition_point(|t| *t <= value)
+    }
+}
+
+/// quantizes each dimension of a context and combines the buckets into one id.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContextQuantizer {
+    dims: Vec<ThresholdQuantizer>,
+}
+impl ContextQuantizer {
+    pub fn new(dims: Vec<ThresholdQuantizer>) -> Self {
+        Self { dims }
+    }
+    /// number of context ids, the product of the buckets of the dimensions.
+    pub fn contexts(&self) -> usize {
+        self.dims.iter().map(|d| d.buckets()).product()
+    }
+    /// context id of the values, one per dimension.
+    pub fn context(&self, values: &[i64]) -> usize {
+        assert_eq!(values.len(), self.dims.len());
+        self.dims
+            .iter()
+            .zip(values)
+            .fold(0, |id, (d, v)| id * d.buckets() + d.bucket(*v))
+    }
+}
+
+/// maps a context to its nearest centroid, learned from samples by k-means.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KMeansQuantizer {
+    centroids: Vec<Vec<f64>>,
+}
+impl KMeansQuantizer {
+    /// learn `k` centroids from the samples with `iterations` rounds of Lloyd's algorithm.
+    /// centroids start from the first sample and then the farthest ones, so the result is deterministic.
+    pub fn train(samples: &[Vec<f64>], k: usize, iterations: usize) -> Result<Self> {
+        if k == 0 || samples.len() < k {
+            return Err(Error::NotEnoughSamples {
+                samples: samples.len(),
+                required: k.max(1),
+            });
+        }
+        let dim = samples[0].len();
+        let mut quantizer = Self {
+            centroids: vec![samples[0].clone()],
+        };
+        // 代表点から最も遠い標本を，次の代表点にする
+        while quantizer.centroids.len() < k {
+            let farthest = samples
+                .iter()
+                .max_by(|a, b| quantizer.distance(a).total_cmp(&quantizer.distance(b)))
+                .unwrap();
+            quantizer.centroids.push(farthest.clone());
+        }
+        for _ in 0..iterations {
+            let mut sums = vec![vec![0.0; dim]; k];
+            let mut counts = vec![0usize; k];
+            for sample in samples {
+                let c = quantizer.context(sample);
+                counts[c] += 1;
+                for (s, v) in sums[c].iter_mut().zip(sample) {
+                    *s += v;
+                }
+            }
+            for ((centroid, sum), count) in quantizer.centroids.iter_mut().zip(sums).zip(counts) {
+                // 空になった代表点はそのまま残す
+                if count > 0 {
+                    *centroid = sum.iter().map(|s| s / count as f64).collect();
+                }
+            }
+        }
+        Ok(quantizer)
+    }
+    pub fn from_centroids(centroids: Vec<Vec<f64>>) -> Self {
+        assert!(!centroids.is_empty());
+        Self { centroids }
+    }
+    pub fn centroids(&self) -> &[Vec<f64>] {
+        &self.centroids
+    }
+    /// number of context ids.
+    pub fn contexts(&self) -> usize {
+        self.centroids.len()
+    }
+    /// index of the nearest centroid. ties go to the smaller index.
+    pub fn context(&self, values: &[f64]) -> usize {
+        (0..self.centroids.len())
+            .min_by(|a, b| {
+                squared_distance(&self.centroids[*a], values)
+                    .total_cmp(&squared_distance(&self.centroids[*b], values))
+            })
+            .unwrap()
+    }
+    /// 最も近い代表点までの距離の2乗
+    fn distance(&self, values: &[f64]) -> f64 {
+        squared_distance(&self.centroids[self.context(values)], values)
+    }
+}
+fn squared_distance(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| (a - b) * (a - b)).sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn threshold_contexts() {
+        let magnitude = ThresholdQuantizer::new(vec![1, 3, 7]).unwrap();
+        assert_eq!(
+            [0, 1, 2, 3, 6, 7, 100].map(|v| magnitude.bucket(v)),
+            [0, 1, 1, 2, 2, 3, 3]
+        );
+        assert!(ThresholdQuantizer::new(vec![3, 3]).is_err());
+        let sign = ThresholdQuantizer::new(vec![0]).unwrap();
+        let quantizer = ContextQuantizer::new(vec![magnitude, sign]);
+        assert_eq!(quantizer.contexts(), 8);
+        assert_eq!(quantizer.context(&[5, -1]), 4);
+        assert_eq!(quantizer.context(&[100, 0]), 7);
+    }
+    #[test]
+    fn kmeans_finds_clusters() {
+        let samples: Vec<Vec<f64>> = (0..90)
+            .map(|i| {
+                let center = [0.0, 10.0, 20.0][i % 3];
+                vec![center + (i % 5) as f64 * 0.1, center]
+            })
+            .collect();
+        let quantizer = KMeansQuantizer::train(&samples, 3, 10).unwrap();
+        let ids: Vec<usize> = [0.0, 10.0, 20.0]
+            .iter()
+            .map(|c| quantizer.context(&[*c, *c]))
+            .collect();
+        assert!(ids[0] != ids[1] && ids[1] != ids[2] && ids[0] != ids[2]);
+        assert!(KMeansQuantizer::train(&samples[..2], 3, 10).is_err());
+    }
+}
@@ -0,0 +1,222 @@
+//! 文脈木重み付け（CTW）
+//! 8ビットのシンボルを二分木（255節点）で二値に分解し，節点毎に文脈木を持つ
+//! 文脈は直前のシンボルのビット列（新しい順）の長さdepthまでの全ての接尾辞で，
+//! 各長さのKT推定を重み付けして足し合わせる
+//! 文脈木重み付けのモデル: CtwModel
+//! 予測したシンボルの分布: CtwPrediction
+//! 浮動小数点演算を使うので，符号化と復号は同じ環境で行う
+
+use crate::prelude::*;
+use crate::QuantizedPDFSet;
+#[cfg(not(feature = "std"))]
+use alloc::collections::BTreeMap as HashMap;
+use range_coder::decoder::Decoder;
+use range_coder::pmodel::PModel;
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+
+const ALPHABET_SIZE: usize = u8::MAX as usize + 1;
+const PREDICTION_TOTAL: f64 = (1u32 << 24) as f64;
+/// maximum depth of the context trees in bits.
+pub const MAX_DEPTH: u32 = 48;
+
+/// 文脈木の1つの節点
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Node {
+    zeros: u32,
+    ones: u32,
+    /// KT推定による確率の対数
+    log_pe: f64,
+    /// 重み付けした確率の対数
+    log_pw: f64,
+}
+impl Node {
+    /// KT推定による次のビットの確率の対数
+    fn log_kt(&self, bit: bool) -> f64 {
+        let count = if bit { self.ones } else { self.zeros };
+        ((count as f64 + 0.5) / ((self.zeros + self.ones) as f64 + 1.0)).ln()
+    }
+}
+/// ln(e^a / 2 + e^b / 2)
+fn log_half_sum(a: f64, b: f64) -> f64 {
+    let max = a.max(b);
+    max + (0.5 * ((a - max).exp() + (b - max).exp())).ln()
+}
+
+/// context-tree weighting over the binary decomposition of 8-bit symbols,
+/// with contexts of up to `depth` previous bits.
+#[derive(Debug, Clone)]
+pub struct CtwModel {
+    depth: u32,
+    /// (分解の節点, 文脈の長さ, 文脈) から文脈木の節点への表
+    nodes: HashMap<(u16, u32, u64), Node>,
+    /// 直前のビット列（最も新しいビットが最下位）
+    history: u64,
+}
+impl CtwModel {
+    pub fn new(depth: u32) -> Self {
+        assert!(depth <= MAX_DEPTH);
+        Self {
+            depth,
+            nodes: HashMap::new(),
+            history: 0,
+        }
+    }
+    pub fn depth(&self) -> u32 {
+        self.depth
+    }
+    /// number of context tree nodes allocated so far.
+    pub fn node_count(&self) -> usize {
+        self.nodes.len()
+    }
+    /// distribution of the next symbol.
+    pub fn predict(&self) -> CtwPrediction {
+        // 分解の節点毎の，ビットが0になる確率
+        let mut p0 = vec![0.0; ALPHABET_SIZE];
+        for (node, p) in p0.iter_mut().enumerate().skip(1) {
+            let zero = self.weighted(node as u16, false).0;
+            let one = self.weighted(node as u16, true).0;
+            *p = 1.0 / (1.0 + (one - zero).exp());
+        }
+        let probs: Vec<f64> = (0..ALPHABET_SIZE)
+            .map(|symbol| {
+                let mut node = 1;
+                let mut p = 1.0;
+                for i in (0..8).rev() {
+                    let bit = (symbol >> i) & 1 == 1;
+                    p *= if bit { 1.0 - p0[node] } else { p0[node] };
+                    node = (node << 1) | bit as usize;
+                }
+                p
+            })
+            .collect();
+        let freq = probs
+            .iter()
+            .map(|p| (p * PREDICTION_TOTAL) as u32 + 1)
+            .collect();
+        CtwPrediction {
+            table: QuantizedPDFSet::from_freq(freq),
+        }
+    }
+    /// learn from the coded symbol.
+    pub fn update(&mut self, symbol: usize) {
+        assert!(symbol < ALPHABET_SIZE);
+        let mut node = 1;
+        for i in (0..8).rev() {
+            let bit = (symbol >> i) & 1 == 1;
+            let (_, path) = self.weighted(node as u16, bit);
+            for (len, updated) in path.into_iter().enumerate() {
+                let key = (node as u16, len as u32, self.context(len as u32));
+                self.nodes.insert(key, updated);
+            }
+            node = (node << 1) | bit as usize;
+        }
+        for i in (0..8).rev() {
+            self.history = (self.history << 1) | ((symbol >> i) & 1) as u64;
+        }
+    }
+    /// 直前のlenビットの文脈
+    fn context(&self, len: u32) -> u64 {
+        if len == 0 {
+            0
+        } else {
+            self.history & (u64::MAX >> (64 - len))
+        }
+    }
+    /// 分解の節点nodeでbitが出たときの，根の重み付けした確率の対数と，経路上の節点の新しい状態
+    fn weighted(&self, node: u16, bit: bool) -> (f64, Vec<Node>) {
+        let get = |len: u32, context: u64| {
+            self.nodes
+                .get(&(node, len, context))
+                .copied()
+                .unwrap_or_default()
+        };
+        let mut path = vec![Node::default(); self.depth as usize + 1];
+        let mut child_pw = 0.0;
+        for len in (0..=self.depth).rev() {
+            let context = self.context(len);
+            let mut updated = get(len, context);
+            updated.log_pe += updated.log_kt(bit);
+            if bit {
+                updated.ones += 1;
+            } else {
+                updated.zeros += 1;
+            }
+            updated.log_pw = if len == self.depth {
+                updated.log_pe
+            } else {
+                // 経路から外れた子の確率は変わらない
+                let sibling = context | ((!(self.history >> len) & 1) << len);
+                log_half_sum(updated.log_pe, child_pw + get(len + 1, sibling).log_pw)
+            };
+            child_pw = updated.log_pw;
+            path[len as usize] = updated;
+        }
+        let before = get(0, 0).log_pw;
+        (child_pw - before, path)
+    }
+}
+
+/// distribution predicted by `CtwModel::predict`.
+#[derive(Debug)]
+pub struct CtwPrediction {
+    table: QuantizedPDFSet,
+}
+impl PModel for CtwPrediction {
+    fn c_freq(&self, index: usize) -> u32 {
+        self.table.c_freq(index)
+    }
+    fn cum_freq(&self, index: usize) -> u32 {
+        self.table.cum_freq(index)
+    }
+    fn total_freq(&self) -> u32 {
+        self.table.total_freq()
+    }
+    fn find_index(&self, decoder: &Decoder) -> usize {
+        self.table.find_index(decoder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{ModelDecoder, ModelEncoder};
+    fn roundtrip(depth: u32, symbols: &[usize]) -> usize {
+        let mut model = CtwModel::new(depth);
+        let mut encoder = ModelEncoder::new();
+        for s in symbols {
+            encoder.encode(&model.predict(), *s);
+            model.update(*s);
+        }
+        let data = encoder.finish();
+        let len = data.len();
+        let mut model = CtwModel::new(depth);
+        let mut decoder = ModelDecoder::new(data);
+        for s in symbols {
+            let decoded = decoder.try_decode(&model.predict()).unwrap();
+            assert_eq!(decoded, *s);
+            model.update(decoded);
+        }
+        len
+    }
+    #[test]
+    fn deeper_contexts_help() {
+        let text = b"abcabcabdabcabcabd".repeat(10);
+        let symbols: Vec<usize> = text.iter().map(|b| *b as usize).collect();
+        let shallow = roundtrip(0, &symbols);
+        let deep = roundtrip(16, &symbols);
+        assert!(deep < shallow, "{} {}", deep, shallow);
+    }
+    #[test]
+    fn prediction_is_normalized() {
+        let mut model = CtwModel::new(8);
+        for s in &[1, 2, 3, 1, 2, 3] {
+            model.update(*s);
+        }
+        let prediction = model.predict();
+        let total = prediction.total_freq() as f64;
+        // 3の次は1
+        assert!(prediction.c_freq(1) > prediction.c_freq(4) * 4);
+        assert!(total > PREDICTION_TOTAL * 0.99 && total < PREDICTION_TOTAL + 257.0);
+    }
+}
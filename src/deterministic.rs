@@ -0,0 +1,167 @@
+//! 整数演算のみによる量子化
+//! 浮動小数点演算は環境によって結果が異なりうるので，符号化器と復号器で
+//! 同一の頻度表が必要な場合は，有理数のパラメータから整数演算のみで頻度表を作る
+//! 整数の重みを返す確率分布のトレイト: IntegerPDF
+//! トレイトIntegerPDFの集合: IntegerPDFSet
+//! 組み込みの分布: DiscreteLaplace / DiscreteGaussian
+
+use crate::prelude::*;
+use crate::{Error, QuantizedPDFSet, Result};
+
+/// 固定小数点の小数部のビット数
+const FRAC_BITS: u32 = 32;
+const ONE: u64 = 1 << FRAC_BITS;
+
+/// probability mass function evaluated with integer arithmetic only.
+pub trait IntegerPDF {
+    /// unnormalized weight of the value.
+    fn weight(&self, v: usize) -> u64;
+}
+
+/// two-sided geometric distribution: `height * (num / den) ^ |v - center|`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiscreteLaplace {
+    height: u32,
+    center: u8,
+    num: u32,
+    den: u32,
+}
+impl DiscreteLaplace {
+    /// panics unless `0 < den` and `num <= den`.
+    pub fn new(height: u32, center: u8, num: u32, den: u32) -> Self {
+        assert!(den > 0 && num <= den);
+        Self {
+            height,
+            center,
+            num,
+            den,
+        }
+    }
+}
+impl IntegerPDF for DiscreteLaplace {
+    fn weight(&self, v: usize) -> u64 {
+        let d = distance(v, self.center);
+        self.height as u64 * pow_ratio(self.num, self.den, d)
+    }
+}
+
+/// discretized gaussian: `height * (num / den) ^ ((v - center) ^ 2)`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiscreteGaussian {
+    height: u32,
+    center: u8,
+    num: u32,
+    den: u32,
+}
+impl DiscreteGaussian {
+    /// panics unless `0 < den` and `num <= den`.
+    pub fn new(height: u32, center: u8, num: u32, den: u32) -> Self {
+        assert!(den > 0 && num <= den);
+        Self {
+            height,
+            center,
+            num,
+            den,
+        }
+    }
+}
+impl IntegerPDF for DiscreteGaussian {
+    fn weight(&self, v: usize) -> u64 {
+        let d = distance(v, self.center);
+        self.height as u64 * pow_ratio(self.num, self.den, d * d)
+    }
+}
+
+/// a set of integer probability mass functions, quantized bit-exactly on every platform.
+pub struct IntegerPDFSet<T: IntegerPDF> {
+    pdf_list: Vec<T>,
+}
+impl<T: IntegerPDF> IntegerPDFSet<T> {
+    pub fn new(vec: Vec<T>) -> Self {
+        Self { pdf_list: vec }
+    }
+    pub fn add_pdf(&mut self, pdf: T) {
+        self.pdf_list.push(pdf);
+    }
+    /// fails when all weights are zero.
+    pub fn finalize(self) -> Result<QuantizedPDFSet> {
+        /// 各値に底上げとして1ずつ割り振るので，maxから引いておく
+        const MAX_TOT_FREQ: u128 = (u32::MAX - (u8::MAX as u32 + 1)) as u128;
+        let weights: Vec<u128> = (0..=u8::MAX as usize)
+            .map(|v| self.pdf_list.iter().map(|p| p.weight(v) as u128).sum())
+            .collect();
+        let total: u128 = weights.iter().sum();
+        if total == 0 {
+            return Err(Error::InvalidMass(0.0));
+        }
+        let freq = weights
+            .iter()
+            .map(|w| (MAX_TOT_FREQ * w / total) as u32 + 1)
+            .collect();
+        Ok(QuantizedPDFSet::from_freq(freq))
+    }
+}
+
+fn distance(v: usize, center: u8) -> u64 {
+    (v as i64 - center as i64).unsigned_abs()
+}
+/// (num / den) ^ exp をQ32の固定小数点で計算する
+fn pow_ratio(num: u32, den: u32, mut exp: u64) -> u64 {
+    let mut base = ((num as u64) << FRAC_BITS) / den as u64;
+    let mut acc = ONE;
+    while exp > 0 && acc > 0 {
+        if exp & 1 == 1 {
+            acc = mul_fixed(acc, base);
+        }
+        base = mul_fixed(base, base);
+        exp >>= 1;
+    }
+    acc
+}
+fn mul_fixed(a: u64, b: u64) -> u64 {
+    ((a as u128 * b as u128) >> FRAC_BITS) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use range_coder::pmodel::PModel;
+    /// FNV-1a
+    fn hash(bytes: &[u8]) -> u64 {
+        bytes.iter().fold(0xcbf2_9ce4_8422_2325, |h, b| {
+            (h ^ *b as u64).wrapping_mul(0x0100_0000_01b3)
+        })
+    }
+    #[test]
+    fn pow_ratio_values() {
+        assert_eq!(pow_ratio(1, 2, 0), ONE);
+        assert_eq!(pow_ratio(1, 2, 3), ONE / 8);
+        assert_eq!(pow_ratio(3, 3, 1000), ONE);
+        assert_eq!(pow_ratio(0, 3, 2), 0);
+    }
+    const GAUSSIAN_TABLE_HASH: u64 = 17176623922092806772;
+    const LAPLACE_TABLE_HASH: u64 = 2308222443435812555;
+    #[test]
+    fn identical_tables() {
+        let build = || {
+            let mut set = IntegerPDFSet::new(vec![DiscreteGaussian::new(1000, 128, 9, 10)]);
+            set.add_pdf(DiscreteGaussian::new(300, 30, 1, 2));
+            set.finalize().unwrap()
+        };
+        let laplace = IntegerPDFSet::new(vec![DiscreteLaplace::new(u32::MAX, 0, 7, 8)])
+            .finalize()
+            .unwrap();
+        // 異なる環境でも同じ頻度表になることを，固定の値と比べて確かめる
+        // （直列化した表の先頭の，大きさと量子化のバージョンは除く）
+        assert_eq!(hash(&build().to_bytes()), hash(&build().to_bytes()));
+        assert_eq!(hash(&build().to_bytes()[2..]), GAUSSIAN_TABLE_HASH);
+        assert_eq!(hash(&laplace.to_bytes()[2..]), LAPLACE_TABLE_HASH);
+        assert!(laplace.c_freq(0) > laplace.c_freq(1));
+        assert_eq!(laplace.c_freq(255), 1);
+    }
+    #[test]
+    fn zero_weights() {
+        let set = IntegerPDFSet::new(vec![DiscreteLaplace::new(0, 0, 1, 2)]);
+        assert!(set.finalize().is_err());
+    }
+}
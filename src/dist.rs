@@ -0,0 +1,179 @@
+//! 組み込みの確率分布
+//! PDFSetの成分として使う，重み付きの正規分布とラプラス分布
+//! 正規分布: Gaussian
+//! ラプラス分布: Laplace
+//! 全ての値での評価（PDF::freq_range）は係数を一度だけ計算する
+//! feature = "simd" のときは，さらに複数の値ずつまとめて計算し，コンパイラがベクトル命令にできるようにする
+//! このときexpは多項式による近似で，freqの結果とは最下位のビットが異なりうる
+
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
+use crate::PDF;
+
+/// normal distribution with total mass `weight`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Gaussian {
+    pub weight: f64,
+    pub mean: f64,
+    pub sigma: f64,
+}
+impl Gaussian {
+    pub fn new(weight: f64, mean: f64, sigma: f64) -> Self {
+        assert!(sigma > 0.0);
+        Self {
+            weight,
+            mean,
+            sigma,
+        }
+    }
+    /// 係数と，指数の係数
+    fn coefficients(&self) -> (f64, f64) {
+        let scale = self.weight / (self.sigma * (2.0 * core::f64::consts::PI).sqrt());
+        (scale, -0.5 / (self.sigma * self.sigma))
+    }
+}
+impl PDF for Gaussian {
+    fn freq(&self, v: usize) -> f64 {
+        let (scale, k) = self.coefficients();
+        let d = v as f64 - self.mean;
+        scale * (k * d * d).exp()
+    }
+    fn freq_range(&self, out: &mut [f64]) {
+        let (scale, k) = self.coefficients();
+        let mean = self.mean;
+        fill_exp(out, scale, |v| {
+            let d = v - mean;
+            k * d * d
+        });
+    }
+}
+
+/// laplace distribution with total mass `weight`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Laplace {
+    pub weight: f64,
+    pub mean: f64,
+    pub scale: f64,
+}
+impl Laplace {
+    pub fn new(weight: f64, mean: f64, scale: f64) -> Self {
+        assert!(scale > 0.0);
+        Self {
+            weight,
+            mean,
+            scale,
+        }
+    }
+}
+impl PDF for Laplace {
+    fn freq(&self, v: usize) -> f64 {
+        let d = (v as f64 - self.mean).abs();
+        self.weight / (2.0 * self.scale) * (-d / self.scale).exp()
+    }
+    fn freq_range(&self, out: &mut [f64]) {
+        let (mean, scale) = (self.mean, self.scale);
+        fill_exp(out, self.weight / (2.0 * scale), |v| {
+            -(v - mean).abs() / scale
+        });
+    }
+}
+
+/// out[v] = scale * exp(exponent(v))
+#[cfg(not(feature = "simd"))]
+fn fill_exp<F: Fn(f64) -> f64>(out: &mut [f64], scale: f64, exponent: F) {
+    for (v, o) in out.iter_mut().enumerate() {
+        *o = scale * exponent(v as f64).exp();
+    }
+}
+#[cfg(feature = "simd")]
+use simd::fill_exp;
+
+#[cfg(feature = "simd")]
+mod simd {
+    #[cfg(not(feature = "std"))]
+    use crate::math::Float;
+
+    /// 一度に計算する値の数
+    const LANES: usize = 8;
+    const LN2_HI: f64 = 0.693_147_180_369_123_8;
+    const LN2_LO: f64 = 1.908_214_929_270_587_7e-10;
+
+    /// out[v] = scale * exp(exponent(v))
+    pub(super) fn fill_exp<F: Fn(f64) -> f64>(out: &mut [f64], scale: f64, exponent: F) {
+        let mut chunks = out.chunks_exact_mut(LANES);
+        let mut base = 0;
+        for chunk in &mut chunks {
+            let mut x = [0.0; LANES];
+            for (i, x) in x.iter_mut().enumerate() {
+                *x = exponent((base + i) as f64);
+            }
+            let y = exp(x);
+            for (o, y) in chunk.iter_mut().zip(&y) {
+                *o = scale * y;
+            }
+            base += LANES;
+        }
+        for (i, o) in chunks.into_remainder().iter_mut().enumerate() {
+            *o = scale * exponent((base + i) as f64).exp();
+        }
+    }
+    /// 分岐のないexp．x = n ln2 + r に分け，e^rを多項式で近似して2^nを掛ける
+    fn exp(x: [f64; LANES]) -> [f64; LANES] {
+        // テイラー展開（13次）の係数 1 / k!
+        let mut coefficients = [1.0; 14];
+        for k in 1..coefficients.len() {
+            coefficients[k] = coefficients[k - 1] / k as f64;
+        }
+        let mut y = [0.0; LANES];
+        for (y, x) in y.iter_mut().zip(&x) {
+            // 下位の方はアンダーフローして0になる
+            let x = x.clamp(-745.0, 709.0);
+            let n = (x * core::f64::consts::LOG2_E).round();
+            // |r| <= ln2 / 2
+            let r = x - n * LN2_HI - n * LN2_LO;
+            let p = coefficients.iter().rev().fold(0.0, |p, c| p * r + c);
+            // 2^nを2回に分けて掛け，非正規化数になる場合も扱う
+            let half = (n * 0.5).floor();
+            *y = p * pow2(half) * pow2(n - half);
+        }
+        y
+    }
+    fn pow2(n: f64) -> f64 {
+        f64::from_bits(((n as i64 + 1023).max(0) as u64) << 52)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PDFSet;
+    use range_coder::pmodel::PModel;
+    #[test]
+    fn range_matches_freq() {
+        let gaussian = Gaussian::new(3.0, 100.5, 7.0);
+        let laplace = Laplace::new(1.0, 20.0, 4.0);
+        let mut g = vec![0.0; 256];
+        gaussian.freq_range(&mut g);
+        let mut l = vec![0.0; 256];
+        laplace.freq_range(&mut l);
+        for v in 0..256 {
+            assert!((g[v] - gaussian.freq(v)).abs() <= gaussian.freq(v) * 1e-12);
+            assert!((l[v] - laplace.freq(v)).abs() <= laplace.freq(v) * 1e-12);
+            // ベクトル化しなければfreqと同じ値
+            #[cfg(not(feature = "simd"))]
+            assert_eq!((g[v], l[v]), (gaussian.freq(v), laplace.freq(v)));
+        }
+        let mass: f64 = g.iter().chain(&l).sum();
+        assert!((mass - 4.0).abs() < 1e-2);
+    }
+    #[test]
+    fn finalize_builtin() {
+        let set = PDFSet::new(vec![
+            Gaussian::new(1.0, 128.0, 3.0),
+            Gaussian::new(1.0, 30.0, 1.0),
+        ]);
+        let model = set.finalize();
+        assert!(model.c_freq(128) > model.c_freq(100) * 1000);
+        assert!(model.c_freq(30) > model.c_freq(128));
+    }
+}
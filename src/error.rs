@@ -0,0 +1,121 @@
+//! クレート全体のエラー型
+
+use crate::prelude::*;
+use thiserror::Error;
+
+/// errors of this crate.
+#[derive(Debug, Error)]
+pub enum Error {
+    #[error("total mass of the pdf set is not a positive finite number: {0}")]
+    InvalidMass(f64),
+    #[error("the pdf set has no component")]
+    EmptySet,
+    #[error("component {component} has density {value} at symbol {symbol}")]
+    InvalidDensity {
+        component: usize,
+        symbol: usize,
+        value: f64,
+    },
+    #[error(
+        "symbol {symbol} has probability {probability}, quantized to {quantized} beyond the allowed ratio"
+    )]
+    InexactQuantization {
+        symbol: usize,
+        probability: f64,
+        quantized: f64,
+    },
+    #[error("quantization loses {redundancy} bits per symbol, more than {max}")]
+    RedundancyExceeded { redundancy: f64, max: f64 },
+    #[error("component {component} has weight {weight}")]
+    InvalidWeight { component: usize, weight: f64 },
+    #[error("unexpected end of data")]
+    UnexpectedEof,
+    #[error("invalid alphabet size: {0}")]
+    InvalidAlphabetSize(usize),
+    #[error("frequency of symbol {0} is zero")]
+    ZeroFrequency(usize),
+    #[error("total frequency overflows u32")]
+    FrequencyOverflow,
+    #[error("cumulative frequency of symbol {0} is not the sum of the frequencies before it")]
+    InconsistentCumFreq(usize),
+    #[error("symbol {symbol} is out of the alphabet of size {alphabet_size}")]
+    SymbolOutOfRange { symbol: usize, alphabet_size: usize },
+    #[error("message of {0} symbols is too long for a u32 length prefix")]
+    MessageTooLong(usize),
+    #[error("symbol {0} is outside the support of the strict encoder")]
+    OutsideSupport(usize),
+    #[error("the data does not start with the format's magic bytes")]
+    BadMagic,
+    #[error("unsupported format version: {0}")]
+    UnsupportedVersion(u8),
+    #[error("unsupported quantizer version: {0}")]
+    UnsupportedQuantizer(u8),
+    #[error("invalid .npy file: {0}")]
+    InvalidNpy(&'static str),
+    #[error("invalid protobuf message: {0}")]
+    InvalidProtobuf(&'static str),
+    #[error("unknown format flags: {0:#04x}")]
+    UnknownFlags(u8),
+    #[error("the data has no embedded model and no model was given")]
+    MissingModel,
+    #[error("no model with id {0}")]
+    UnknownModel(usize),
+    #[error("no block with index {0}")]
+    UnknownBlock(usize),
+    #[error("invalid model bank")]
+    InvalidBank,
+    #[error("invalid sync point index")]
+    InvalidIndex,
+    #[error("the stream is too large for the u32 fields of its sync point index")]
+    IndexOverflow,
+    #[error("position {position} is beyond the end of the stream of {len} symbols")]
+    PositionOutOfRange { position: usize, len: usize },
+    #[error("checksum mismatch: stored {expected:#010x}, decoded {actual:#010x}")]
+    ChecksumMismatch { expected: u32, actual: u32 },
+    #[error("output buffer of {capacity} bytes is too small, {required} bytes are needed")]
+    BufferFull { capacity: usize, required: usize },
+    #[error("{models} models are given for {rows} rows")]
+    ModelCountMismatch { models: usize, rows: usize },
+    #[error("thresholds are not strictly ascending")]
+    InvalidThresholds,
+    #[error("{samples} samples are given, {required} are needed")]
+    NotEnoughSamples { samples: usize, required: usize },
+    #[error("conversion of the model failed: {0}")]
+    Interop(&'static str),
+    #[error("plotting failed: {0}")]
+    Plot(String),
+    #[error("arrow export failed: {0}")]
+    Arrow(String),
+    #[cfg(feature = "std")]
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Decode(#[from] DecodeError),
+    #[error(transparent)]
+    Verify(#[from] VerifyError),
+}
+/// errors detected while decoding untrusted data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum DecodeError {
+    #[error("coded value is out of the model's frequency table")]
+    Corrupt,
+    #[error("decoding read {consumed} bytes of data of {len} bytes")]
+    Truncated { consumed: usize, len: usize },
+    #[error("the hash of the decoded symbols does not match the coded one")]
+    Desync,
+}
+/// why a model failed `QuantizedPDFSet::verify_roundtrip`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Error)]
+pub enum VerifyError {
+    #[error("symbol {symbol} is out of the alphabet of size {alphabet_size}")]
+    SymbolOutOfRange { symbol: usize, alphabet_size: usize },
+    #[error("decoding failed: {0}")]
+    Decode(DecodeError),
+    #[error("symbol {position} was {expected} but decoded as {decoded}")]
+    Mismatch {
+        position: usize,
+        expected: usize,
+        decoded: usize,
+    },
+}
+pub type Result<T> = core::result::Result<T, Error>;
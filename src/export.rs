@@ -0,0 +1,83 @@
+//! モデルの書き出し
+//! 表計算ソフトや外部の描画ツールでモデルを見るための，JSONとCSVの形式
+//! 値毎にシンボル，頻度，確率，累積頻度を書く: QuantizedPDFSet::to_json / QuantizedPDFSet::to_csv
+//! 読み込みには使わない（直列化には to_bytes を使う）
+
+use crate::prelude::*;
+use crate::QuantizedPDFSet;
+use core::fmt::Write;
+use range_coder::pmodel::PModel;
+
+impl QuantizedPDFSet {
+    /// the table as a JSON object,
+    /// `{"alphabet_size": n, "total_freq": t, "symbols": [{"symbol", "freq", "probability", "cumulative"}, ...]}`,
+    /// where cumulative is the sum of the frequencies before the symbol.
+    pub fn to_json(&self) -> String {
+        let mut json = format!(
+            "{{\"alphabet_size\":{},\"total_freq\":{},\"symbols\":[",
+            self.alphabet_size(),
+            self.total_freq()
+        );
+        for symbol in 0..self.alphabet_size() {
+            if symbol > 0 {
+                json.push(',');
+            }
+            write!(
+                json,
+                "{{\"symbol\":{},\"freq\":{},\"probability\":{},\"cumulative\":{}}}",
+                symbol,
+                self.c_freq(symbol),
+                self.probability(symbol),
+                self.cum_freq(symbol)
+            )
+            .unwrap();
+        }
+        json.push_str("]}");
+        json
+    }
+    /// the table as CSV with the header `symbol,freq,probability,cumulative`, one symbol per line.
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("symbol,freq,probability,cumulative\n");
+        for symbol in 0..self.alphabet_size() {
+            writeln!(
+                csv,
+                "{},{},{},{}",
+                symbol,
+                self.c_freq(symbol),
+                self.probability(symbol),
+                self.cum_freq(symbol)
+            )
+            .unwrap();
+        }
+        csv
+    }
+    /// 量子化した確率
+    fn probability(&self, symbol: usize) -> f64 {
+        self.c_freq(symbol) as f64 / self.total_freq() as f64
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::simple_pmodel;
+    use range_coder::pmodel::PModel;
+    #[test]
+    fn csv_and_json() {
+        let pm = simple_pmodel().with_eof();
+        let csv = pm.to_csv();
+        let rows: Vec<Vec<&str>> = csv.lines().map(|l| l.split(',').collect()).collect();
+        assert_eq!(rows.len(), 258);
+        assert_eq!(rows[0], ["symbol", "freq", "probability", "cumulative"]);
+        let row = &rows[1 + 128];
+        assert_eq!(row[1].parse::<u32>().unwrap(), pm.c_freq(128));
+        assert_eq!(row[3].parse::<u32>().unwrap(), pm.cum_freq(128));
+        let probability: f64 = rows[1..].iter().map(|r| r[2].parse::<f64>().unwrap()).sum();
+        assert!((probability - 1.0).abs() < 1e-9);
+        let json = pm.to_json();
+        assert!(json.starts_with("{\"alphabet_size\":257,"));
+        assert!(json.ends_with("}]}"));
+        let eof = format!("{{\"symbol\":256,\"freq\":{},", pm.c_freq(256));
+        assert!(json.contains(&eof));
+        assert_eq!(json.matches("\"symbol\":").count(), 257);
+    }
+}
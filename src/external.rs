@@ -0,0 +1,95 @@
+//! 外部の頻度表を借りるモデル
+//! 他のツールが作った表や，メモリに写したファイルの表を，QuantizedPDFSetに写さずに符号化/復号に使う
+//! 作るときに表を一度だけ検査し，検査した後は表を読むだけ: ExternalTableModel
+
+use crate::{
+    check_freq, checked_coded_freq, coded_freq, search_cum_freq, DecodeError, Error, Result,
+};
+use range_coder::decoder::Decoder;
+use range_coder::pmodel::PModel;
+
+/// model over borrowed frequency and cumulative frequency tables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExternalTableModel<'a> {
+    freq: &'a [u32],
+    cum_freq: &'a [u32],
+    total: u32,
+}
+impl<'a> ExternalTableModel<'a> {
+    /// model of the tables, of any nonzero alphabet size.
+    /// fails unless the tables have the same length, every frequency is nonzero,
+    /// every cumulative frequency is the sum of the frequencies before it,
+    /// and the total fits in u32.
+    pub fn new(freq: &'a [u32], cum_freq: &'a [u32]) -> Result<Self> {
+        if freq.is_empty() || cum_freq.len() != freq.len() {
+            return Err(Error::InvalidAlphabetSize(cum_freq.len()));
+        }
+        check_freq(freq)?;
+        let mut cum = 0;
+        for (i, (f, c)) in freq.iter().zip(cum_freq).enumerate() {
+            if *c != cum {
+                return Err(Error::InconsistentCumFreq(i));
+            }
+            cum += f;
+        }
+        Ok(Self {
+            freq,
+            cum_freq,
+            total: cum,
+        })
+    }
+    pub fn alphabet_size(&self) -> usize {
+        self.freq.len()
+    }
+    /// `find_index`, failing when the coded value is beyond the total frequency.
+    pub fn try_find_index(&self, decoder: &Decoder) -> core::result::Result<usize, DecodeError> {
+        let rfreq = checked_coded_freq(decoder, self.total)?;
+        Ok(search_cum_freq(self.cum_freq, rfreq))
+    }
+}
+impl PModel for ExternalTableModel<'_> {
+    fn c_freq(&self, index: usize) -> u32 {
+        self.freq[index]
+    }
+    fn cum_freq(&self, index: usize) -> u32 {
+        self.cum_freq[index]
+    }
+    fn total_freq(&self) -> u32 {
+        self.total
+    }
+    fn find_index(&self, decoder: &Decoder) -> usize {
+        search_cum_freq(self.cum_freq, coded_freq(decoder, self.total))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{encode_slice, try_decode_slice};
+    use crate::test_util::simple_pmodel;
+    #[test]
+    fn borrowed_tables() {
+        let pm = simple_pmodel();
+        let freq: Vec<u32> = (0..256).map(|s| pm.c_freq(s)).collect();
+        let cum_freq: Vec<u32> = (0..256).map(|s| pm.cum_freq(s)).collect();
+        let model = ExternalTableModel::new(&freq, &cum_freq).unwrap();
+        assert_eq!(model.total_freq(), pm.total_freq());
+        let symbols: Vec<usize> = (0..500).map(|i| [128, 30, 70, 1][i % 4]).collect();
+        let data = encode_slice(&model, &symbols);
+        assert_eq!(data, encode_slice(&pm, &symbols));
+        assert_eq!(
+            try_decode_slice(&model, data, symbols.len()).unwrap(),
+            symbols
+        );
+        // 小さなアルファベットも使える
+        let model = ExternalTableModel::new(&[3, 1, 4], &[0, 3, 4]).unwrap();
+        let data = encode_slice(&model, &[2, 0, 1, 2]);
+        assert_eq!(try_decode_slice(&model, data, 4).unwrap(), [2, 0, 1, 2]);
+        assert!(matches!(
+            ExternalTableModel::new(&[3, 1, 4], &[0, 3, 5]),
+            Err(Error::InconsistentCumFreq(2))
+        ));
+        assert!(ExternalTableModel::new(&[3, 0], &[0, 3]).is_err());
+        assert!(ExternalTableModel::new(&[3, 1], &[0]).is_err());
+    }
+}
@@ -0,0 +1,176 @@
+//! C言語からの利用のための関数 (feature = "ffi")
+//! モデルはQuantizedPDFSetへのポインタで，mgfrc_model_freeで解放する
+//! 符号化したバイト列はクレートが確保し，mgfrc_buffer_freeで解放する
+//! 失敗はNULLか負の値で返し，パニックはしない
+//! ヘッダ（include/mgfrc.h）は cbindgen --config cbindgen.toml --output include/mgfrc.h で生成する
+//! C/C++から使うライブラリは cargo rustc --release --features ffi --crate-type staticlib（またはcdylib）で作る
+
+use crate::codec::{encode_slice, try_decode_slice};
+use crate::dist::Gaussian;
+use crate::{PDFSet, QuantizedPDFSet, RawModel};
+
+/// 長さlenの配列へのポインタを，スライスにする（lenが0ならポインタはNULLでもよい）
+unsafe fn slice<'a, T>(ptr: *const T, len: usize) -> Option<&'a [T]> {
+    if len == 0 {
+        Some(&[])
+    } else if ptr.is_null() {
+        None
+    } else {
+        Some(std::slice::from_raw_parts(ptr, len))
+    }
+}
+fn into_handle(model: crate::Result<QuantizedPDFSet>) -> *mut QuantizedPDFSet {
+    match model {
+        Ok(model) => Box::into_raw(Box::new(model)),
+        Err(_) => std::ptr::null_mut(),
+    }
+}
+
+/// model from `len` frequencies (256, or 257 with the end-of-stream symbol).
+/// returns NULL unless every frequency is nonzero and the total fits in 32 bits.
+///
+/// # Safety
+/// `freq` must point to `len` readable values.
+#[no_mangle]
+pub unsafe extern "C" fn mgfrc_model_from_freq(
+    freq: *const u32,
+    len: usize,
+) -> *mut QuantizedPDFSet {
+    match slice(freq, len) {
+        Some(freq) => into_handle(RawModel::from_freq(freq.to_vec()).validate()),
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// model of the mixture of `count` gaussians over the 256 values,
+/// given as `weight, mean, sigma` triples in `params`.
+/// returns NULL if a sigma is not a positive finite number or the mixture has no mass.
+///
+/// # Safety
+/// `params` must point to `3 * count` readable values.
+#[no_mangle]
+pub unsafe extern "C" fn mgfrc_model_from_gaussians(
+    params: *const f64,
+    count: usize,
+) -> *mut QuantizedPDFSet {
+    let params = match count.checked_mul(3).and_then(|len| slice(params, len)) {
+        Some(params) => params,
+        None => return std::ptr::null_mut(),
+    };
+    let mut set = PDFSet::new(Vec::with_capacity(count));
+    for p in params.chunks_exact(3) {
+        if !(p[2].is_finite() && p[2] > 0.0) {
+            return std::ptr::null_mut();
+        }
+        set.add_pdf(Gaussian::new(p[0], p[1], p[2]));
+    }
+    into_handle(set.try_finalize())
+}
+
+/// free a model made by this library. NULL is ignored.
+///
+/// # Safety
+/// `model` must be NULL or a model from this library, not freed before.
+#[no_mangle]
+pub unsafe extern "C" fn mgfrc_model_free(model: *mut QuantizedPDFSet) {
+    if !model.is_null() {
+        drop(Box::from_raw(model));
+    }
+}
+
+/// encode `len` symbols with the model, and write the number of bytes to `out_len`.
+/// returns the bytes, to be freed by `mgfrc_buffer_free`,
+/// or NULL if a symbol is outside the model's alphabet.
+///
+/// # Safety
+/// `model` must be a model from this library, `symbols` must point to `len` readable values,
+/// and `out_len` must be writable.
+#[no_mangle]
+pub unsafe extern "C" fn mgfrc_encode(
+    model: *const QuantizedPDFSet,
+    symbols: *const u32,
+    len: usize,
+    out_len: *mut usize,
+) -> *mut u8 {
+    let (model, symbols) = match (model.as_ref(), slice(symbols, len)) {
+        (Some(model), Some(symbols)) if !out_len.is_null() => (model, symbols),
+        _ => return std::ptr::null_mut(),
+    };
+    let symbols: Vec<usize> = symbols.iter().map(|s| *s as usize).collect();
+    if symbols.iter().any(|s| *s >= model.alphabet_size()) {
+        return std::ptr::null_mut();
+    }
+    let data = encode_slice(model, &symbols).into_boxed_slice();
+    *out_len = data.len();
+    Box::into_raw(data) as *mut u8
+}
+
+/// free bytes returned by `mgfrc_encode`, with the length written with them. NULL is ignored.
+///
+/// # Safety
+/// `data` must be NULL or bytes from `mgfrc_encode` of length `len`, not freed before.
+#[no_mangle]
+pub unsafe extern "C" fn mgfrc_buffer_free(data: *mut u8, len: usize) {
+    if !data.is_null() {
+        drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(data, len)));
+    }
+}
+
+/// decode `len` symbols from `data_len` bytes into `out`.
+/// returns 0, or -1 if the data is corrupt or truncated.
+///
+/// # Safety
+/// `model` must be a model from this library, `data` must point to `data_len` readable bytes,
+/// and `out` to `len` writable values.
+#[no_mangle]
+pub unsafe extern "C" fn mgfrc_decode(
+    model: *const QuantizedPDFSet,
+    data: *const u8,
+    data_len: usize,
+    out: *mut u32,
+    len: usize,
+) -> i32 {
+    let (model, data) = match (model.as_ref(), slice(data, data_len)) {
+        (Some(model), Some(data)) if len == 0 || !out.is_null() => (model, data),
+        _ => return -1,
+    };
+    match try_decode_slice(model, data.to_vec(), len) {
+        Ok(symbols) => {
+            for (i, s) in symbols.iter().enumerate() {
+                *out.add(i) = *s as u32;
+            }
+            0
+        }
+        Err(_) => -1,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn roundtrip_through_c_functions() {
+        let params = [1.0, 40.0, 3.0, 0.5, 200.0, 10.0];
+        let symbols: Vec<u32> = (0..300).map(|i| [40, 41, 200, 7][i % 4]).collect();
+        unsafe {
+            let model = mgfrc_model_from_gaussians(params.as_ptr(), 2);
+            assert!(!model.is_null());
+            let mut len = 0;
+            let data = mgfrc_encode(model, symbols.as_ptr(), symbols.len(), &mut len);
+            assert!(!data.is_null());
+            let mut decoded = vec![0u32; symbols.len()];
+            let result = mgfrc_decode(model, data, len, decoded.as_mut_ptr(), decoded.len());
+            assert_eq!((result, &decoded), (0, &symbols));
+            mgfrc_buffer_free(data, len);
+            // 範囲外のシンボル
+            assert!(mgfrc_encode(model, [256u32].as_ptr(), 1, &mut len).is_null());
+            mgfrc_model_free(model);
+            let freq = vec![1u32; 257];
+            let model = mgfrc_model_from_freq(freq.as_ptr(), freq.len());
+            assert_eq!((*model).alphabet_size(), 257);
+            mgfrc_model_free(model);
+            assert!(mgfrc_model_from_freq([0u32; 256].as_ptr(), 256).is_null());
+            assert!(mgfrc_model_from_gaussians([1.0, 0.0, 0.0].as_ptr(), 1).is_null());
+        }
+    }
+}
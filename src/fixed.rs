@@ -0,0 +1,139 @@
+//! 固定長の表の量子化した確率分布
+//! マイコンのような小さなRAMの環境のためのもので，表は[u32; N]の配列で持ち，作るのにも使うのにもヒープを使わない
+//! 値の数Nは任意なので，256値より小さなアルファベットでは表も小さくなる
+//! 構築はconst fnなので，一つの固定のモデルをstaticに置ける: FixedPDFSet::from_freq
+//! 成分の確率はf32で足し合わせて量子化する（f64の演算がソフトウェアで行われる環境のため）: FixedPDFSet::quantize
+//! feature = "embedded" はstdを無効にして，この量子化とf32の積和演算（feature = "f32"）とlibmを使う構成
+
+#[cfg(not(feature = "std"))]
+use crate::math::Float;
+use crate::{
+    checked_coded_freq, coded_freq, fit_budget, search_cum_freq, DecodeError, Error, Result, PDF,
+};
+use range_coder::decoder::Decoder;
+use range_coder::pmodel::PModel;
+
+/// quantized model of `N` symbols stored in fixed-size arrays, needing no allocation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixedPDFSet<const N: usize> {
+    freq: [u32; N],
+    cum_freq: [u32; N],
+    total: u32,
+}
+impl<const N: usize> FixedPDFSet<N> {
+    /// model from nonzero frequencies whose sum fits in u32.
+    /// in a const context, invalid frequencies are a compile error.
+    pub const fn from_freq(freq: [u32; N]) -> Self {
+        assert!(N > 0, "empty alphabet");
+        let mut cum_freq = [0; N];
+        let mut cum = 0u32;
+        let mut i = 0;
+        while i < N {
+            assert!(freq[i] > 0, "zero frequency");
+            cum_freq[i] = cum;
+            cum = match cum.checked_add(freq[i]) {
+                Some(cum) => cum,
+                None => panic!("total frequency overflows u32"),
+            };
+            i += 1;
+        }
+        Self {
+            freq,
+            cum_freq,
+            total: cum,
+        }
+    }
+    /// model of the weighted sum of the components over the values `0..N`,
+    /// summed in f32 and quantized with the rounding of `PDFSet::finalize`, without allocating.
+    /// fails with `Error::InvalidMass` when the total mass is not a positive finite number.
+    pub fn quantize<T: PDF>(components: &[(T, f32)]) -> Result<Self> {
+        // 各値に底上げとして1ずつ割り振るので，maxから引いておく
+        let max_tot_freq = u32::MAX - N as u32;
+        let mut mass = [0f32; N];
+        for (pdf, weight) in components {
+            for (v, m) in mass.iter_mut().enumerate() {
+                let p = pdf.freq(v) as f32;
+                if !(p * weight).is_subnormal() {
+                    *m = p.mul_add(*weight, *m);
+                }
+            }
+        }
+        let total = mass.iter().fold(0f32, |cum, m| cum + m);
+        if !(total.is_finite() && total > 0.0) {
+            return Err(Error::InvalidMass(total as f64));
+        }
+        let mut freq = [0; N];
+        for (f, m) in freq.iter_mut().zip(&mass) {
+            // NaNや負の割合は0に，1を超える割合は1にする
+            let share = m / total;
+            let share = if share.is_nan() {
+                0.0
+            } else {
+                share.clamp(0.0, 1.0)
+            };
+            // f32ではmax_tot_freqが切り上がるので，丸めた頻度をmax_tot_freqで抑える
+            *f = ((max_tot_freq as f32 * share) as u32).min(max_tot_freq) + 1;
+        }
+        fit_budget(&mut freq, u32::MAX as u64);
+        Ok(Self::from_freq(freq))
+    }
+    pub const fn alphabet_size(&self) -> usize {
+        N
+    }
+    pub fn freq(&self) -> &[u32; N] {
+        &self.freq
+    }
+    /// `find_index`, failing when the coded value is beyond the total frequency.
+    pub fn try_find_index(&self, decoder: &Decoder) -> core::result::Result<usize, DecodeError> {
+        let rfreq = checked_coded_freq(decoder, self.total)?;
+        Ok(search_cum_freq(&self.cum_freq, rfreq))
+    }
+}
+impl<const N: usize> PModel for FixedPDFSet<N> {
+    fn c_freq(&self, index: usize) -> u32 {
+        self.freq[index]
+    }
+    fn cum_freq(&self, index: usize) -> u32 {
+        self.cum_freq[index]
+    }
+    fn total_freq(&self) -> u32 {
+        self.total
+    }
+    fn find_index(&self, decoder: &Decoder) -> usize {
+        search_cum_freq(&self.cum_freq, coded_freq(decoder, self.total))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{encode_slice, try_decode_slice};
+    use crate::dist::Gaussian;
+    static MODEL: FixedPDFSet<4> = FixedPDFSet::from_freq([5, 1, 1, 9]);
+    #[test]
+    fn static_and_quantized_models() {
+        assert_eq!(MODEL.total_freq(), 16);
+        assert_eq!(MODEL.cum_freq(3), 7);
+        let data = encode_slice(&MODEL, &[3, 0, 1, 3, 2]);
+        assert_eq!(try_decode_slice(&MODEL, data, 5).unwrap(), [3, 0, 1, 3, 2]);
+
+        let model = FixedPDFSet::<16>::quantize(&[
+            (Gaussian::new(1.0, 4.0, 1.5), 0.75),
+            (Gaussian::new(1.0, 11.0, 2.0), 0.25),
+        ])
+        .unwrap();
+        assert!(model.total_freq() as f64 > u32::MAX as f64 * 0.999);
+        assert!(model.c_freq(4) > model.c_freq(11));
+        assert!(model.c_freq(11) > model.c_freq(15));
+        let symbols: Vec<usize> = (0..300).map(|i| [4, 11, 0, 5][i % 4]).collect();
+        let data = encode_slice(&model, &symbols);
+        assert_eq!(
+            try_decode_slice(&model, data, symbols.len()).unwrap(),
+            symbols
+        );
+        assert!(matches!(
+            FixedPDFSet::<16>::quantize(&[(Gaussian::new(1.0, 4.0, 1.5), 0.0)]),
+            Err(Error::InvalidMass(_))
+        ));
+    }
+}
@@ -0,0 +1,93 @@
+//! ファジングのための入口 (feature = "fuzz")
+//! QuantizedPDFSetのarbitrary::Arbitraryの実装は，常に正しい頻度表を作る
+//! 任意のバイト列を復号する: fuzz_decode
+//! fuzz_decodeはパニックせず，復号できたシンボルは符号化し直しても同じに復号されなければならない
+
+use crate::adaptive::AdaptiveModel;
+use crate::codec::{encode_slice, try_decode_slice, ModelDecoder};
+use crate::{QuantizedPDFSet, EOF_SYMBOL};
+use arbitrary::{Arbitrary, Unstructured};
+
+/// 1回の入力で復号するシンボルの数の上限
+const MAX_SYMBOLS: usize = 4096;
+
+impl<'a> Arbitrary<'a> for QuantizedPDFSet {
+    /// a valid table of 256 symbols, or 257 with `EOF_SYMBOL`.
+    fn arbitrary(u: &mut Unstructured<'a>) -> arbitrary::Result<Self> {
+        let alphabet_size = if u.arbitrary()? {
+            EOF_SYMBOL + 1
+        } else {
+            EOF_SYMBOL
+        };
+        // 総頻度がu32に収まるようにする
+        let max = u32::MAX / alphabet_size as u32;
+        let freq = (0..alphabet_size)
+            .map(|_| u.int_in_range(1..=max))
+            .collect::<arbitrary::Result<Vec<u32>>>()?;
+        Ok(Self::from_freq(freq))
+    }
+}
+
+/// decode arbitrary bytes: a model from the first bytes and coded data from the rest,
+/// with the checked decoders of static and adaptive models, and the model parser.
+/// panics only on a bug: a panic in the crate, or symbols which do not survive encoding again.
+pub fn fuzz_decode(bytes: &[u8]) {
+    let _ = QuantizedPDFSet::from_bytes(bytes);
+    let mut u = Unstructured::new(bytes);
+    let (model, len) = match (
+        QuantizedPDFSet::arbitrary(&mut u),
+        u.int_in_range(0..=MAX_SYMBOLS),
+    ) {
+        (Ok(model), Ok(len)) => (model, len),
+        _ => return,
+    };
+    let data = u.take_rest().to_vec();
+    if let Ok(symbols) = try_decode_slice(&model, data.clone(), len) {
+        let again = try_decode_slice(&model, encode_slice(&model, &symbols), len);
+        assert_eq!(
+            again.ok().as_ref(),
+            Some(&symbols),
+            "desync after re-encoding"
+        );
+    }
+    let mut adaptive = AdaptiveModel::new(model.alphabet_size());
+    let mut decoder = ModelDecoder::new(data);
+    for _ in 0..len {
+        if decoder.try_decode_adaptive(&mut adaptive).is_err() {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use range_coder::pmodel::PModel;
+    #[test]
+    fn arbitrary_tables_are_valid() {
+        let bytes: Vec<u8> = (0..4096u32)
+            .map(|i| (i.wrapping_mul(2_654_435_761) >> 13) as u8)
+            .collect();
+        let mut u = Unstructured::new(&bytes);
+        let model = QuantizedPDFSet::arbitrary(&mut u).unwrap();
+        let total: u64 = (0..model.alphabet_size())
+            .map(|i| model.c_freq(i) as u64)
+            .sum();
+        assert_eq!(total, model.total_freq() as u64);
+        let symbols: Vec<usize> = (0..100).collect();
+        assert_eq!(model.verify_roundtrip(&symbols), Ok(()));
+    }
+    #[test]
+    fn fuzz_decode_does_not_panic() {
+        let mut state = 1u32;
+        for len in 0..200 {
+            let bytes: Vec<u8> = (0..len * 13)
+                .map(|_| {
+                    state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                    (state >> 16) as u8
+                })
+                .collect();
+            fuzz_decode(&bytes);
+        }
+    }
+}
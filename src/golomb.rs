@@ -0,0 +1,210 @@
+//! Golomb–Rice符号
+//! パラメータkのRice符号化器/復号器: GolombRice
+//! ビット単位の入出力: BitWriter / BitReader
+
+use crate::prelude::*;
+use crate::{DecodeError, Error, Result, PDF};
+
+/// MSB-first bit writer.
+#[derive(Debug, Clone, Default)]
+pub struct BitWriter {
+    data: Vec<u8>,
+    acc: u8,
+    len: u32,
+}
+impl BitWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn write_bit(&mut self, bit: bool) {
+        self.acc = (self.acc << 1) | bit as u8;
+        self.len += 1;
+        if self.len == 8 {
+            self.data.push(self.acc);
+            self.acc = 0;
+            self.len = 0;
+        }
+    }
+    /// write lower `n` bits of `value`, MSB first.
+    pub fn write_bits(&mut self, value: usize, n: u32) {
+        for i in (0..n).rev() {
+            self.write_bit((value >> i) & 1 == 1);
+        }
+    }
+    /// number of bits written so far.
+    pub fn bit_len(&self) -> usize {
+        self.data.len() * 8 + self.len as usize
+    }
+    /// pad the last byte with zeros and return the bytes.
+    pub fn finish(mut self) -> Vec<u8> {
+        if self.len > 0 {
+            self.data.push(self.acc << (8 - self.len));
+        }
+        self.data
+    }
+}
+
+/// MSB-first bit reader. reads zeros past the end of data.
+#[derive(Debug, Clone)]
+pub struct BitReader {
+    data: Vec<u8>,
+    pos: usize,
+}
+impl BitReader {
+    pub fn new(data: Vec<u8>) -> Self {
+        Self { data, pos: 0 }
+    }
+    pub fn read_bit(&mut self) -> bool {
+        let bit = match self.data.get(self.pos / 8) {
+            Some(byte) => (byte >> (7 - self.pos % 8)) & 1 == 1,
+            None => false,
+        };
+        self.pos += 1;
+        bit
+    }
+    pub fn read_bits(&mut self, n: u32) -> usize {
+        (0..n).fold(0, |acc, _| (acc << 1) | self.read_bit() as usize)
+    }
+    /// number of bits read so far, with the zeros read past the end.
+    pub fn bit_pos(&self) -> usize {
+        self.pos
+    }
+    /// number of bits of the data.
+    pub fn bit_len(&self) -> usize {
+        self.data.len() * 8
+    }
+}
+
+/// Golomb–Rice code with parameter `k`:
+/// quotient `v >> k` in unary (ones terminated by a zero), then `k` raw bits.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GolombRice {
+    k: u32,
+}
+impl GolombRice {
+    /// # Panics
+    /// if `k` is not less than `usize::BITS`.
+    pub fn new(k: u32) -> Self {
+        assert!(k < usize::BITS, "Rice parameter {} is too large", k);
+        Self { k }
+    }
+    pub fn k(&self) -> u32 {
+        self.k
+    }
+    /// pick `k` for a geometric/Laplace-like pdf over 0..=255, from its mean.
+    pub fn from_pdf<T: PDF>(pdf: &T) -> Self {
+        let (sum, weighted) = (0..=u8::MAX as usize)
+            .map(|v| (v, pdf.freq(v)))
+            .fold((0f64, 0f64), |(sum, weighted), (v, f)| {
+                (sum + f, weighted + v as f64 * f)
+            });
+        if sum.is_nan() || sum <= 0.0 {
+            return Self::new(0);
+        }
+        Self::from_mean(weighted / sum)
+    }
+    /// pick `k` for a geometric source with the given mean.
+    pub fn from_mean(mean: f64) -> Self {
+        if mean.is_nan() || mean <= 0.0 {
+            return Self::new(0);
+        }
+        // 幾何分布 P(v) = (1-θ)θ^v の最適なGolombパラメータ m から k = log2(m)
+        let theta = mean / (1.0 + mean);
+        let m = (-(1.0 + theta).ln() / theta.ln()).ceil().max(1.0);
+        // 32ビットの環境では，非常に大きい平均のkがusizeのビット数を超える
+        Self::new((m.log2().round() as u32).min(usize::BITS - 1))
+    }
+    /// code length in bits of `value`.
+    pub fn code_length(&self, value: usize) -> usize {
+        (value >> self.k) + 1 + self.k as usize
+    }
+    pub fn encode(&self, writer: &mut BitWriter, value: usize) {
+        for _ in 0..value >> self.k {
+            writer.write_bit(true);
+        }
+        writer.write_bit(false);
+        writer.write_bits(value, self.k);
+    }
+    /// fails with `Error::UnexpectedEof` when the code runs past the end of the data,
+    /// and with `DecodeError::Corrupt` when the value does not fit a `usize`.
+    pub fn decode(&self, reader: &mut BitReader) -> Result<usize> {
+        let max_quotient = usize::MAX >> self.k;
+        let mut quotient: usize = 0;
+        while reader.read_bit() {
+            quotient = quotient
+                .checked_add(1)
+                .filter(|q| *q <= max_quotient)
+                .ok_or(Error::Decode(DecodeError::Corrupt))?;
+        }
+        let remainder = reader.read_bits(self.k);
+        if reader.bit_pos() > reader.bit_len() {
+            return Err(Error::UnexpectedEof);
+        }
+        Ok((quotient << self.k) | remainder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    struct Geometric(f64);
+    impl PDF for Geometric {
+        fn freq(&self, v: usize) -> f64 {
+            (1.0 - self.0) * self.0.powi(v as i32)
+        }
+    }
+    #[test]
+    fn roundtrip() {
+        let values: Vec<usize> = (0..=255).collect();
+        for k in 0..8 {
+            let rice = GolombRice::new(k);
+            let mut writer = BitWriter::new();
+            for v in &values {
+                rice.encode(&mut writer, *v);
+            }
+            assert_eq!(
+                writer.bit_len(),
+                values.iter().map(|v| rice.code_length(*v)).sum::<usize>()
+            );
+            let mut reader = BitReader::new(writer.finish());
+            let decoded: Vec<usize> = values
+                .iter()
+                .map(|_| rice.decode(&mut reader).unwrap())
+                .collect();
+            assert_eq!(values, decoded);
+        }
+    }
+    #[test]
+    fn decode_errors() {
+        let rice = GolombRice::new(3);
+        let mut writer = BitWriter::new();
+        rice.encode(&mut writer, 200);
+        let mut data = writer.finish();
+        data.pop();
+        // 途切れたデータ
+        let mut reader = BitReader::new(data);
+        assert!(matches!(
+            rice.decode(&mut reader),
+            Err(Error::UnexpectedEof)
+        ));
+        // usizeに収まらない商
+        let wide = GolombRice::new(usize::BITS - 4);
+        let mut reader = BitReader::new(vec![0xff; 4]);
+        assert!(matches!(
+            wide.decode(&mut reader),
+            Err(Error::Decode(DecodeError::Corrupt))
+        ));
+        assert_eq!(reader.bit_pos(), 16);
+    }
+    #[test]
+    #[should_panic]
+    fn parameter_too_large() {
+        GolombRice::new(usize::BITS);
+    }
+    #[test]
+    fn pick_k_from_pdf() {
+        assert_eq!(GolombRice::from_pdf(&Geometric(0.3)).k(), 0);
+        assert_eq!(GolombRice::from_pdf(&Geometric(0.9)).k(), 3);
+        assert!(GolombRice::from_pdf(&Geometric(0.99)).k() >= 5);
+    }
+}
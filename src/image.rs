@@ -0,0 +1,192 @@
+//! 画像の符号化のための文脈
+//! 復号済みの左・上・左上の画素から，勾配とアクティビティで文脈の番号を作る
+//! 画像の外の画素は，左がなければ上，上がなければ左，どちらもなければ0とする
+//! 周囲の画素: Neighbors
+//! 文脈の番号を作るもの: ImageContext
+//! MED予測の残差を文脈モデルで符号化する: ImageCoder
+
+use crate::backend::EntropyBackend;
+use crate::codec::{ModelDecoder, ModelEncoder};
+use crate::context::ContextModel;
+use crate::context_quant::ThresholdQuantizer;
+use crate::prelude::*;
+use crate::Result;
+
+const ALPHABET_SIZE: usize = u8::MAX as usize + 1;
+
+/// already coded neighbors of a pixel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Neighbors {
+    pub left: i64,
+    pub top: i64,
+    pub top_left: i64,
+}
+impl Neighbors {
+    /// neighbors of `(x, y)` in a row-major plane of `width` pixels,
+    /// of which only the pixels before `(x, y)` are read.
+    pub fn of<T: Copy + Into<i64>>(plane: &[T], width: usize, x: usize, y: usize) -> Self {
+        let at = |x: usize, y: usize| plane[y * width + x].into();
+        match (x > 0, y > 0) {
+            (true, true) => Self {
+                left: at(x - 1, y),
+                top: at(x, y - 1),
+                top_left: at(x - 1, y - 1),
+            },
+            (true, false) => {
+                let left = at(x - 1, y);
+                Self {
+                    left,
+                    top: left,
+                    top_left: left,
+                }
+            }
+            (false, true) => {
+                let top = at(x, y - 1);
+                Self {
+                    left: top,
+                    top,
+                    top_left: top,
+                }
+            }
+            (false, false) => Self {
+                left: 0,
+                top: 0,
+                top_left: 0,
+            },
+        }
+    }
+    /// median edge detector prediction (LOCO-I).
+    pub fn med(&self) -> i64 {
+        let (a, b, c) = (self.left, self.top, self.top_left);
+        if c >= a.max(b) {
+            a.min(b)
+        } else if c <= a.min(b) {
+            a.max(b)
+        } else {
+            a + b - c
+        }
+    }
+}
+
+/// derives context ids from neighbors:
+/// activity class of `|left - top_left| + |top - top_left|`,
+/// times direction class of `|left - top_left| - |top - top_left|`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageContext {
+    activity: ThresholdQuantizer,
+    direction: ThresholdQuantizer,
+}
+impl ImageContext {
+    pub fn new(activity: ThresholdQuantizer, direction: ThresholdQuantizer) -> Self {
+        Self {
+            activity,
+            direction,
+        }
+    }
+    /// number of context ids.
+    pub fn contexts(&self) -> usize {
+        self.activity.buckets() * self.direction.buckets()
+    }
+    pub fn context(&self, neighbors: &Neighbors) -> usize {
+        let horizontal = (neighbors.left - neighbors.top_left).abs();
+        let vertical = (neighbors.top - neighbors.top_left).abs();
+        self.activity.bucket(horizontal + vertical) * self.direction.buckets()
+            + self.direction.bucket(horizontal - vertical)
+    }
+}
+impl Default for ImageContext {
+    /// 7 activity classes and 3 direction classes.
+    fn default() -> Self {
+        Self::new(
+            ThresholdQuantizer::new(vec![1, 3, 7, 15, 31, 63]).unwrap(),
+            ThresholdQuantizer::new(vec![-2, 3]).unwrap(),
+        )
+    }
+}
+
+/// codes 8-bit planes as MED prediction residuals (mod 256),
+/// with one adaptive model per context of `ImageContext`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ImageCoder {
+    context: ImageContext,
+    models: ContextModel,
+}
+impl ImageCoder {
+    pub fn new(context: ImageContext) -> Self {
+        let models = ContextModel::new(context.contexts(), ALPHABET_SIZE);
+        Self { context, models }
+    }
+    /// encode a row-major plane of `width` pixels.
+    pub fn encode_plane<B: EntropyBackend>(
+        &mut self,
+        encoder: &mut ModelEncoder<B>,
+        plane: &[u8],
+        width: usize,
+    ) {
+        assert!(width > 0 && plane.len() % width == 0);
+        for (i, pixel) in plane.iter().enumerate() {
+            let neighbors = Neighbors::of(plane, width, i % width, i / width);
+            let residual = (*pixel as i64 - neighbors.med()).rem_euclid(ALPHABET_SIZE as i64);
+            let context = self.context.context(&neighbors);
+            self.models.encode(encoder, context, residual as usize);
+        }
+    }
+    /// decode a plane of `width` x `height` pixels.
+    pub fn decode_plane<B: EntropyBackend>(
+        &mut self,
+        decoder: &mut ModelDecoder<B>,
+        width: usize,
+        height: usize,
+    ) -> Result<Vec<u8>> {
+        let mut plane = vec![0u8; width * height];
+        for i in 0..plane.len() {
+            let neighbors = Neighbors::of(&plane, width, i % width, i / width);
+            let context = self.context.context(&neighbors);
+            let residual = self.models.try_decode(decoder, context)? as i64;
+            plane[i] = (neighbors.med() + residual).rem_euclid(ALPHABET_SIZE as i64) as u8;
+        }
+        Ok(plane)
+    }
+}
+impl Default for ImageCoder {
+    fn default() -> Self {
+        Self::new(ImageContext::default())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn neighbors_and_contexts() {
+        let plane = [10u8, 20, 30, 40];
+        let n = Neighbors::of(&plane, 2, 1, 1);
+        assert_eq!((n.left, n.top, n.top_left), (30, 20, 10));
+        assert_eq!(n.med(), 30);
+        assert_eq!(Neighbors::of(&plane, 2, 0, 1).left, 10);
+        let context = ImageContext::default();
+        assert_eq!(context.contexts(), 21);
+        let flat = Neighbors::of(&[5u8; 4], 2, 1, 1);
+        assert_eq!(context.context(&flat), 1);
+    }
+    #[test]
+    fn plane_roundtrip() {
+        let (width, height) = (32, 24);
+        // なめらかな勾配と縦縞
+        let plane: Vec<u8> = (0..width * height)
+            .map(|i| {
+                let (x, y) = (i % width, i / width);
+                ((x * 3 + y * 2) as u8).wrapping_add(if x % 8 < 4 { 0 } else { 40 })
+            })
+            .collect();
+        let mut encoder = ModelEncoder::new();
+        ImageCoder::default().encode_plane(&mut encoder, &plane, width);
+        let data = encoder.finish();
+        assert!(data.len() < plane.len() / 2, "{}", data.len());
+        let mut decoder = ModelDecoder::new(data);
+        let decoded = ImageCoder::default()
+            .decode_plane(&mut decoder, width, height)
+            .unwrap();
+        assert_eq!(decoded, plane);
+    }
+}
@@ -0,0 +1,134 @@
+//! 配列で持つ量子化した確率分布
+//! 頻度表と累積頻度表をヒープではなく固定長の配列で持つので，
+//! 多数の文脈のモデルを持つときに確保の回数が減り，メモリ上で連続する
+//! 256値の量子化した確率分布: InlinePDFSet
+//! 構築はconst fnなので，固定のモデルをコンパイル時に作れる
+
+use crate::{coded_freq, count_freq, counts_total, search_cum_freq, Error, QuantizedPDFSet};
+use core::convert::TryFrom;
+use range_coder::decoder::Decoder;
+use range_coder::pmodel::PModel;
+
+const ALPHABET_SIZE: usize = u8::MAX as usize + 1;
+
+/// `QuantizedPDFSet` of 256 symbols stored in inline arrays.
+/// it can be put in a `Box` or a `Vec` of models without further allocations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InlinePDFSet {
+    freq: [u32; ALPHABET_SIZE],
+    cum_freq: [u32; ALPHABET_SIZE],
+    total: u32,
+}
+impl InlinePDFSet {
+    /// model from nonzero frequencies whose sum fits in u32.
+    /// in a const context, invalid frequencies are a compile error.
+    pub const fn from_freq(freq: [u32; ALPHABET_SIZE]) -> Self {
+        let mut cum_freq = [0; ALPHABET_SIZE];
+        let mut cum = 0u32;
+        let mut i = 0;
+        while i < ALPHABET_SIZE {
+            assert!(freq[i] > 0, "zero frequency");
+            cum_freq[i] = cum;
+            cum = match cum.checked_add(freq[i]) {
+                Some(cum) => cum,
+                None => panic!("total frequency overflows u32"),
+            };
+            i += 1;
+        }
+        Self {
+            freq,
+            cum_freq,
+            total: cum,
+        }
+    }
+    /// model from symbol counts, quantized as `QuantizedPDFSet::from_counts`.
+    /// in a const context, counts which are all zero are a compile error.
+    pub const fn from_counts(counts: &[u64; ALPHABET_SIZE]) -> Self {
+        let total = counts_total(counts);
+        assert!(total > 0, "no symbol counted");
+        let mut freq = [0; ALPHABET_SIZE];
+        let mut i = 0;
+        while i < ALPHABET_SIZE {
+            freq[i] = count_freq(counts[i], total, ALPHABET_SIZE);
+            i += 1;
+        }
+        Self::from_freq(freq)
+    }
+}
+impl TryFrom<&QuantizedPDFSet> for InlinePDFSet {
+    type Error = Error;
+    /// fails for models with `EOF_SYMBOL`.
+    fn try_from(model: &QuantizedPDFSet) -> Result<Self, Error> {
+        if model.alphabet_size() != ALPHABET_SIZE {
+            return Err(Error::InvalidAlphabetSize(model.alphabet_size()));
+        }
+        let mut freq = [0; ALPHABET_SIZE];
+        freq.copy_from_slice(&model.freq);
+        let mut cum_freq = [0; ALPHABET_SIZE];
+        cum_freq.copy_from_slice(&model.cum_freq);
+        Ok(Self {
+            freq,
+            cum_freq,
+            total: model.total_freq(),
+        })
+    }
+}
+impl From<&InlinePDFSet> for QuantizedPDFSet {
+    fn from(model: &InlinePDFSet) -> Self {
+        QuantizedPDFSet::from_freq(model.freq.to_vec())
+    }
+}
+impl PModel for InlinePDFSet {
+    fn c_freq(&self, index: usize) -> u32 {
+        self.freq[index]
+    }
+    fn cum_freq(&self, index: usize) -> u32 {
+        self.cum_freq[index]
+    }
+    fn total_freq(&self) -> u32 {
+        self.total
+    }
+    fn find_index(&self, decoder: &Decoder) -> usize {
+        search_cum_freq(&self.cum_freq, coded_freq(decoder, self.total))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{encode_slice, try_decode_slice};
+    use crate::test_util::simple_pmodel;
+    #[test]
+    fn same_code_as_vec_model() {
+        let pm = simple_pmodel();
+        let inline = InlinePDFSet::try_from(&pm).unwrap();
+        assert_eq!(
+            core::mem::size_of::<InlinePDFSet>(),
+            2 * 4 * ALPHABET_SIZE + 4
+        );
+        let symbols: Vec<usize> = (0..500).map(|i| (i * 13) % 256).collect();
+        let data = encode_slice(&inline, &symbols);
+        assert_eq!(data, encode_slice(&pm, &symbols));
+        assert_eq!(
+            try_decode_slice(&inline, data, symbols.len()).unwrap(),
+            symbols
+        );
+        let back = QuantizedPDFSet::from(&inline);
+        assert_eq!(back.to_bytes(), pm.to_bytes());
+        assert!(InlinePDFSet::try_from(&pm.with_eof()).is_err());
+    }
+    #[test]
+    fn built_at_compile_time() {
+        const COUNTS: [u64; ALPHABET_SIZE] = {
+            let mut counts = [0; ALPHABET_SIZE];
+            counts[b'e' as usize] = 12;
+            counts[b't' as usize] = 9;
+            counts[b' ' as usize] = 20;
+            counts
+        };
+        const MODEL: InlinePDFSet = InlinePDFSet::from_counts(&COUNTS);
+        let runtime = QuantizedPDFSet::from_counts(&COUNTS).unwrap();
+        assert_eq!(MODEL, InlinePDFSet::try_from(&runtime).unwrap());
+        assert!(MODEL.c_freq(b' ' as usize) > MODEL.c_freq(b'e' as usize));
+    }
+}
@@ -0,0 +1,55 @@
+//! 他のエントロピー符号化のクレートのモデルとの相互変換 (feature = "constriction")
+//! constrictionの範囲符号やANSで，同じモデルを作り直さずに符号化を比べるためのもの
+//! constrictionの既定の精度は24ビットなので，全体の頻度が2^24になるように確率を量子化し直す
+//! このため変換したモデルの符号量は，元のモデルとわずかに異なる
+//! QuantizedPDFSet::to_constriction / QuantizedPDFSet::from_constriction
+
+use crate::prelude::*;
+use crate::{Error, QuantizedPDFSet, RawModel, Result};
+use constriction::stream::model::{DefaultContiguousCategoricalEntropyModel, IterableEntropyModel};
+use range_coder::pmodel::PModel;
+
+impl QuantizedPDFSet {
+    /// constriction's categorical model of the probabilities, requantized by constriction
+    /// to its precision of 24 bits with every symbol kept nonzero.
+    pub fn to_constriction(&self) -> Result<DefaultContiguousCategoricalEntropyModel> {
+        let total = self.total_freq() as f64;
+        let probabilities: Vec<f64> = (0..self.alphabet_size())
+            .map(|s| self.c_freq(s) as f64 / total)
+            .collect();
+        DefaultContiguousCategoricalEntropyModel::from_floating_point_probabilities(&probabilities)
+            .map_err(|()| Error::Interop("constriction rejected the probabilities"))
+    }
+    /// model of the frequencies of constriction's categorical model, with total frequency 2^24.
+    /// the alphabet needs 256 symbols, or 257 with the end-of-stream symbol.
+    pub fn from_constriction(model: &DefaultContiguousCategoricalEntropyModel) -> Result<Self> {
+        let freq = model.symbol_table().map(|(_, _, p)| p.get()).collect();
+        RawModel::from_freq(freq).validate()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_util::simple_pmodel;
+    use crate::QuantizedPDFSet;
+    use constriction::stream::model::EncoderModel;
+    use range_coder::pmodel::PModel;
+    #[test]
+    fn constriction_roundtrip() {
+        let pm = simple_pmodel().with_eof();
+        let model = pm.to_constriction().unwrap();
+        let converted = QuantizedPDFSet::from_constriction(&model).unwrap();
+        assert_eq!(converted.alphabet_size(), 257);
+        assert_eq!(converted.total_freq(), 1 << 24);
+        for s in [0, 30, 70, 128, 256].iter() {
+            let (left, p) = model.left_cumulative_and_probability(*s).unwrap();
+            assert_eq!(
+                (converted.cum_freq(*s), converted.c_freq(*s)),
+                (left, p.get())
+            );
+            let expected = pm.c_freq(*s) as f64 / pm.total_freq() as f64;
+            let probability = p.get() as f64 / (1 << 24) as f64;
+            assert!((probability - expected).abs() < 1e-4);
+        }
+    }
+}
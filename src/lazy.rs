@@ -0,0 +1,76 @@
+//! 初めて使うときに量子化するモデル
+//! 候補のモデルを多く作っても，符号化/復号に使ったものだけが量子化の計算をする
+//! 遅延して量子化するモデル: LazyModel
+
+use crate::{PDFSet, QuantizedPDFSet, PDF};
+use core::cell::{OnceCell, RefCell};
+use range_coder::decoder::Decoder;
+use range_coder::pmodel::PModel;
+
+/// `PDFSet` which is finalized the first time the model is used.
+pub struct LazyModel<T: PDF> {
+    /// 量子化の前の集合．量子化すると空になる
+    set: RefCell<Option<PDFSet<T>>>,
+    model: OnceCell<QuantizedPDFSet>,
+}
+impl<T: PDF> LazyModel<T> {
+    pub fn new(set: PDFSet<T>) -> Self {
+        Self {
+            set: RefCell::new(Some(set)),
+            model: OnceCell::new(),
+        }
+    }
+    /// whether the set has been finalized.
+    pub fn is_quantized(&self) -> bool {
+        self.model.get().is_some()
+    }
+    /// the finalized model, finalizing the set if it was not yet.
+    pub fn model(&self) -> &QuantizedPDFSet {
+        self.model
+            .get_or_init(|| self.set.borrow_mut().take().unwrap().finalize())
+    }
+    pub fn into_model(self) -> QuantizedPDFSet {
+        self.model();
+        self.model.into_inner().unwrap()
+    }
+}
+impl<T: PDF> From<PDFSet<T>> for LazyModel<T> {
+    fn from(set: PDFSet<T>) -> Self {
+        Self::new(set)
+    }
+}
+impl<T: PDF> PModel for LazyModel<T> {
+    fn c_freq(&self, index: usize) -> u32 {
+        self.model().c_freq(index)
+    }
+    fn cum_freq(&self, index: usize) -> u32 {
+        self.model().cum_freq(index)
+    }
+    fn total_freq(&self) -> u32 {
+        self.model().total_freq()
+    }
+    fn find_index(&self, decoder: &Decoder) -> usize {
+        self.model().find_index(decoder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{encode_slice, try_decode_slice};
+    use crate::dist::Gaussian;
+    #[test]
+    fn quantized_on_first_use() {
+        let candidates: Vec<LazyModel<Gaussian>> = (0..8)
+            .map(|i| PDFSet::new(vec![Gaussian::new(1.0, i as f64 * 30.0, 4.0)]).into())
+            .collect();
+        assert!(candidates.iter().all(|c| !c.is_quantized()));
+        let symbols = vec![60, 61, 59, 62];
+        let data = encode_slice(&candidates[2], &symbols);
+        assert!(candidates[2].is_quantized());
+        assert_eq!(candidates.iter().filter(|c| c.is_quantized()).count(), 1);
+        let direct = PDFSet::new(vec![Gaussian::new(1.0, 60.0, 4.0)]).finalize();
+        assert_eq!(data, encode_slice(&direct, &symbols));
+        assert_eq!(try_decode_slice(&candidates[2], data, 4).unwrap(), symbols);
+    }
+}
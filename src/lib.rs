@@ -2,78 +2,890 @@
 //! 確率密度関数を表すトレイト: PDF  
 //! トレイトPDFの集合: PDFSet  
 //! PDFSetを量子化した確率密度関数: QuantizedPDFSet  
+//! 検証していない頻度表: RawModel（validateでQuantizedPDFSetになる）  
+//! 外部の頻度表を借りるモデル: external  
 //! QuantizedPDFSetはRangeCoderのPModelを実装  
+//! 配列で持つ量子化した確率分布: inline  
+//! ヒープを使わない固定長の表の確率分布: fixed  
+//! 組み込みの確率分布: dist  
+//! 初めて使うときに量子化するモデル: lazy  
+//! 多数の混合分布の一括評価（評価の実装を差し替えられる）: batch  
+//! 適応モデル: adaptive  
+//! 文脈モデル: context  
+//! 文脈の量子化: context_quant  
+//! 画像の符号化のための文脈: image  
+//! ランを予測するモデル: run  
+//! 文脈木重み付け: ctw  
+//! PPM: ppm  
+//! モデルの混合: mix  
+//! 二次的な確率の推定: sse  
+//! データからの静的なモデルの学習: train  
+//! 二値算術符号化: binary  
+//! Golomb–Rice符号: golomb  
+//! 整数演算のみによる量子化: deterministic  
+//! エントロピー符号化のバックエンド: backend  
+//! 高水準の符号化/復号: codec  
+//! 符号量の見積もり: rate  
+//! JSONとCSVへの書き出し: export  
+//! 端末に表示する頻度表の図: ascii  
+//! numpyの.npyからのヒストグラムの読み込み: npy  
+//! コンテナ形式: container  
+//! 0次の適応モデルによるバイト列の圧縮/展開: container::compress_bytes / container::decompress_bytes  
+//! 他の言語の実装のためのテストベクタ: vectors  
+//! チェックサム: checksum  
+//! 名前付きのモデルの集まり: bank  
+//! モデルバンクのファイル形式: bank_file  
+//! 途中から復号できるストリーム: seek  
+//! ブロック単位の符号化と符号量の制御: block  
+//! 符号化器の出力先: sink  
+//! 非同期入出力での符号化/復号: async_io (feature = "async")  
+//! 速度と圧縮率の計測: bench (feature = "bench")  
+//! ファジングのための入口: fuzz (feature = "fuzz")  
+//! C言語からの利用: ffi (feature = "ffi")  
+//! JavaScriptからの利用: wasm (feature = "wasm-bindgen")  
+//! Pythonからの利用: python (feature = "python")  
+//! WebAssemblyのベクトル命令による混合と探索: simd128 (target_feature = "simd128")  
+//! モデルの図示: plot (feature = "plot")  
+//! モデルからの乱数のシンボルの生成: sampling (feature = "rand")  
+//! ndarrayの行列の行単位の符号化: array (feature = "ndarray")  
+//! constrictionのモデルとの相互変換: interop (feature = "constriction")  
+//! Arrowの列形式でのモデルと学習の統計の書き出し: columnar (feature = "arrow")  
+//! protobufの形式でのモデルの交換: proto (feature = "protobuf")  
+//! プロパティテストのための生成器: strategy (feature = "testing")  
+//! エラー型: Error  
+//! 信頼できないデータを読む関数（from_bytes，try_decode，各Decoderのnewなど）はパニックせずErrorを返す  
+//! 量子化する確率は，値毎に成分の順に足し，総和は値の順に足す  
+//! この順序は並列化しても変えないので，同じ成分の列からはどの環境でも同じ足し算で表が作られる  
+//! ただし成分の評価（PDF::freq）自体の結果は実装に依り，環境に依らない表には deterministic を使う  
+//! 非正規化数になる確率と寄与は0として足すので，極端に鋭い成分があってもflush-to-zeroの設定に依らない  
+//! 量子化した確率の忠実さの方針: Quantization  
+//! 量子化の方法にはバージョン（QUANTIZER_VERSION）があり，直列化した表に記録され，古いバージョンでも量子化できる  
+//! feature = "std" を無効にするとno_stdとallocで動く．入出力，ファイル，時刻の計測を使うものは除かれる  
+//! このとき浮動小数点数の関数には feature = "libm" が必要  
+//! マイコン向けの構成は feature = "embedded"（default-featuresを無効にして使う）  
 
+#![cfg_attr(not(feature = "std"), no_std)]
+#[cfg(not(any(feature = "std", feature = "libm")))]
+compile_error!("without the std feature, the libm feature is needed for floating point functions");
+extern crate alloc;
+
+pub mod adaptive;
+#[cfg(feature = "ndarray")]
+pub mod array;
+pub mod ascii;
+#[cfg(feature = "async")]
+pub mod async_io;
+pub mod backend;
+pub mod bank;
+pub mod bank_file;
+pub mod batch;
+#[cfg(feature = "bench")]
+pub mod bench;
+pub mod binary;
+pub mod block;
+pub mod checksum;
+pub mod codec;
+#[cfg(feature = "arrow")]
+pub mod columnar;
+pub mod container;
+pub mod context;
+pub mod context_quant;
+pub mod ctw;
+pub mod deterministic;
+pub mod dist;
+mod error;
+pub mod export;
+pub mod external;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod fixed;
+#[cfg(feature = "fuzz")]
+pub mod fuzz;
+pub mod golomb;
+pub mod image;
+pub mod inline;
+#[cfg(feature = "constriction")]
+pub mod interop;
+pub mod lazy;
+#[cfg(not(feature = "std"))]
+mod math;
+pub mod mix;
+pub mod npy;
+#[cfg(feature = "plot")]
+pub mod plot;
+pub mod ppm;
+#[cfg(feature = "protobuf")]
+pub mod proto;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod rate;
+pub mod run;
+#[cfg(feature = "rand")]
+pub mod sampling;
+pub mod seek;
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+mod simd128;
+pub mod sink;
+pub mod sse;
+#[cfg(feature = "testing")]
+pub mod strategy;
+pub mod train;
+pub mod vectors;
+#[cfg(test)]
+mod test_util;
+#[cfg(feature = "wasm-bindgen")]
+pub mod wasm;
+
+/// stdがなくても使う，allocの型とマクロ
+mod prelude {
+    #[cfg(not(feature = "std"))]
+    pub(crate) use crate::math::Float;
+    pub(crate) use alloc::boxed::Box;
+    pub(crate) use alloc::string::{String, ToString};
+    pub(crate) use alloc::vec::Vec;
+    pub(crate) use alloc::{format, vec};
+}
+
+use crate::prelude::*;
+use core::cell::RefCell;
+pub use error::{DecodeError, Error, Result, VerifyError};
 pub use range_coder;
 use range_coder::decoder::Decoder;
 use range_coder::pmodel::PModel;
 /// a set of probability density functions.
+/// the densities are summed per value in the order of the components, by every way of finalizing,
+/// so the same components evaluated to the same densities always give the same table.
+#[derive(Debug)]
 pub struct PDFSet<T: PDF> {
     pdf_list: Vec<T>,
+    /// 成分毎の重み
+    weights: Vec<f64>,
+    /// finalize_refで評価した確率の行
+    rows: RefCell<RowCache>,
+}
+/// 成分毎の，全ての値での確率の行
+#[derive(Debug, Default)]
+struct RowCache {
+    rows: Vec<Vec<f64>>,
+    /// 成分が使う行の番号（評価した成分の分だけ）
+    of: Vec<usize>,
 }
 impl<T: PDF> PDFSet<T> {
     pub fn new(vec: Vec<T>) -> Self {
-        Self { pdf_list: vec }
+        let weights = vec![1.0; vec.len()];
+        Self {
+            pdf_list: vec,
+            weights,
+            rows: RefCell::default(),
+        }
     }
     pub fn add_pdf(&mut self, pdf: T) {
+        self.add_weighted_pdf(pdf, 1.0);
+    }
+    /// add a component whose density is multiplied by `weight`.
+    pub fn add_weighted_pdf(&mut self, pdf: T, weight: f64) {
         self.pdf_list.push(pdf);
+        self.weights.push(weight);
     }
+    /// number of components.
+    pub fn len(&self) -> usize {
+        self.pdf_list.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.pdf_list.is_empty()
+    }
+    pub fn weight(&self, index: usize) -> f64 {
+        self.weights[index]
+    }
+    /// change the weight of a component, keeping its cached densities.
+    pub fn set_weight(&mut self, index: usize, weight: f64) {
+        self.weights[index] = weight;
+    }
+    /// quantize the set. a set with no positive finite mass gives the uniform distribution.
+    /// panics if the set is empty; `QuantizedPDFSet::uniform` is the model of no information.
     pub fn finalize(self) -> QuantizedPDFSet {
-        const RANGE_MAX: usize = std::u8::MAX as usize;
-        const RANGE_SIZE: usize = RANGE_MAX + 1;
-        const RANGE: std::ops::RangeInclusive<usize> = 0..=RANGE_MAX;
-        let (freq_src, tot_freq_src) = {
-            let mut freq_src = Vec::with_capacity(RANGE_SIZE);
-            let tot_freq = RANGE
-                .into_iter()
-                // 確率質量関数の確率の合計を計算する
-                .map(|x| {
-                    self.pdf_list
-                        .iter()
-                        .map(|p| p.freq(x as usize))
-                        .sum::<f64>()
-                })
-                // 累積確率を計算する
-                .fold(0f64, |cum, freq| {
-                    // 頻度表に登録する
-                    freq_src.push(freq);
-                    cum + freq
-                });
-            (freq_src, tot_freq)
-        };
-        // 量子化
-        let (freq, cum_freq) = {
-            /// 各値に底上げとして1ずつ割り振るので，maxから引いておく
-            const MAX_TOT_FREQ: u32 = std::u32::MAX - (std::u8::MAX as u32 + 1);
-            let mut freq = Vec::with_capacity(RANGE_SIZE);
-            let mut cum_freq = Vec::with_capacity(RANGE_SIZE);
-            RANGE
-                .into_iter()
-                // 整数へ丸めた頻度を計算（1の底上げもする）
-                .map(|x| (MAX_TOT_FREQ as f64 * (freq_src[x as usize] / tot_freq_src)) as u32 + 1)
-                // 累積頻度の計算
-                .scan(0, |cum, freq| {
-                    let cum_clone = cum.clone();
-                    *cum += freq;
-                    Some((freq, cum_clone))
-                })
-                // 頻度表に登録
-                .for_each(|(f, cum)| {
-                    freq.push(f);
-                    cum_freq.push(cum);
+        self.assert_not_empty();
+        let (freq_src, tot_freq_src) = self.mixture();
+        quantize_mass(&freq_src, tot_freq_src, ZeroMass::Uniform).unwrap()
+    }
+    /// same as `finalize`, but fails on a NaN, negative or infinite density or weight of a component,
+    /// and when the total mass of the set is not a positive finite number.
+    pub fn try_finalize(self) -> Result<QuantizedPDFSet> {
+        self.try_finalize_with(ZeroMass::Fail)
+    }
+    /// `finalize` with the quantizer of `version`, from 1 to `QUANTIZER_VERSION`,
+    /// to make again the tables of a crate version whose rounding was different.
+    pub fn finalize_version(self, version: u8) -> Result<QuantizedPDFSet> {
+        self.assert_not_empty();
+        let add = row_adder(version)?;
+        let (freq_src, tot_freq_src) = self.mixture_with(add, |_, _, _| Ok(())).unwrap();
+        quantize_mass(&freq_src, tot_freq_src, ZeroMass::Uniform)
+    }
+    /// `try_finalize`, choosing what a set with no positive finite mass gives.
+    /// an empty set always fails with `Error::EmptySet`.
+    pub fn try_finalize_with(self, zero_mass: ZeroMass) -> Result<QuantizedPDFSet> {
+        if self.is_empty() {
+            return Err(Error::EmptySet);
+        }
+        let (freq_src, tot_freq_src) = self.checked_mixture()?;
+        quantize_mass(&freq_src, tot_freq_src, zero_mass)
+    }
+    /// `try_finalize`, then check the quantized table against the policy.
+    pub fn try_finalize_with_policy(self, policy: Quantization) -> Result<QuantizedPDFSet> {
+        if self.is_empty() {
+            return Err(Error::EmptySet);
+        }
+        let (freq_src, tot_freq_src) = self.checked_mixture()?;
+        let model = quantize_mass(&freq_src, tot_freq_src, ZeroMass::Fail)?;
+        match policy {
+            Quantization::Lossy => {}
+            Quantization::Strict { max_ratio } => {
+                check_ratio(&model, &freq_src, tot_freq_src, max_ratio)?
+            }
+            Quantization::MaxRedundancy { bits } => {
+                let redundancy = model.report_of(&freq_src, tot_freq_src).kl_divergence;
+                if redundancy > bits {
+                    return Err(Error::RedundancyExceeded {
+                        redundancy,
+                        max: bits,
+                    });
+                }
+            }
+        }
+        Ok(model)
+    }
+    /// check every density and weight of the components,
+    /// reporting the first component and symbol with a NaN, negative or infinite value.
+    pub fn validate(&self) -> Result<()> {
+        self.checked_mixture().map(|_| ())
+    }
+    fn assert_not_empty(&self) {
+        assert!(
+            !self.is_empty(),
+            "finalize of an empty PDFSet; QuantizedPDFSet::uniform gives the uniform model"
+        );
+    }
+    /// 各値の確率の合計と，全体の合計
+    fn mixture(&self) -> (Vec<f64>, f64) {
+        self.mixture_with(add_weighted_row, |_, _, _| Ok(()))
+            .unwrap()
+    }
+    /// 不正な確率や重みを見つけたら失敗するmixture
+    fn checked_mixture(&self) -> Result<(Vec<f64>, f64)> {
+        self.mixture_with(add_weighted_row, |component, w, row| {
+            if !w.is_finite() || w < 0.0 {
+                return Err(Error::InvalidWeight {
+                    component,
+                    weight: w,
                 });
-            (freq, cum_freq)
-        };
-        QuantizedPDFSet { freq, cum_freq }
+            }
+            match row.iter().position(|v| !v.is_finite() || *v < 0.0) {
+                Some(symbol) => Err(Error::InvalidDensity {
+                    component,
+                    symbol,
+                    value: row[symbol],
+                }),
+                None => Ok(()),
+            }
+        })
+    }
+    /// 成分毎に，重みと評価した行をcheckに渡しながらaddで足し合わせる
+    fn mixture_with<F>(&self, add: RowAdder, mut check: F) -> Result<(Vec<f64>, f64)>
+    where
+        F: FnMut(usize, f64, &[f64]) -> Result<()>,
+    {
+        // 確率質量関数の確率の合計を計算する（成分の順に足すので，値毎に足すのと同じ結果になる）
+        let mut acc = vec![0.0; RANGE_SIZE];
+        let mut row = vec![0f64; RANGE_SIZE];
+        for (i, (p, w)) in self.pdf_list.iter().zip(&self.weights).enumerate() {
+            p.freq_range(&mut row);
+            check(i, *w, &row)?;
+            add(&mut acc, &row, *w);
+        }
+        let freq_src = accumulated(acc);
+        let tot_freq = total_mass(&freq_src);
+        Ok((freq_src, tot_freq))
+    }
+}
+impl<T: PDF + PartialEq> PDFSet<T> {
+    /// `finalize` without consuming the set.
+    /// the densities of each component are evaluated once and kept,
+    /// and equal components share them, so calls after `set_weight` only sum the rows.
+    pub fn finalize_ref(&self) -> QuantizedPDFSet {
+        self.assert_not_empty();
+        let mut cache = self.rows.borrow_mut();
+        while cache.of.len() < self.pdf_list.len() {
+            let i = cache.of.len();
+            let same = (0..i).find(|j| self.pdf_list[*j] == self.pdf_list[i]);
+            let row = match same {
+                Some(j) => cache.of[j],
+                None => {
+                    let mut row = vec![0f64; RANGE_SIZE];
+                    self.pdf_list[i].freq_range(&mut row);
+                    cache.rows.push(row);
+                    cache.rows.len() - 1
+                }
+            };
+            cache.of.push(row);
+        }
+        let rows = cache.of.iter().map(|row| &cache.rows[*row][..]);
+        quantize_rows(rows, &self.weights)
+    }
+}
+#[cfg(feature = "parallel")]
+impl<T: PDF + Sync> PDFSet<T> {
+    /// `finalize` evaluating the components on multiple threads.
+    /// the densities are still summed in the order of the components, so the table is the same.
+    /// keeps the densities of all components in memory at once.
+    pub fn finalize_parallel(self) -> QuantizedPDFSet {
+        use rayon::prelude::*;
+        self.assert_not_empty();
+        let rows: Vec<Vec<f64>> = self
+            .pdf_list
+            .par_iter()
+            .map(|p| {
+                let mut row = vec![0f64; RANGE_SIZE];
+                p.freq_range(&mut row);
+                row
+            })
+            .collect();
+        quantize_rows(rows.iter().map(|row| &row[..]), &self.weights)
+    }
+}
+/// 成分の順に行を足して量子化する
+fn quantize_rows<'a, I: Iterator<Item = &'a [f64]>>(rows: I, weights: &[f64]) -> QuantizedPDFSet {
+    let mut acc = vec![0.0; RANGE_SIZE];
+    for (row, w) in rows.zip(weights) {
+        add_weighted_row(&mut acc, row, *w);
+    }
+    let freq_src = accumulated(acc);
+    quantize_mass(&freq_src, total_mass(&freq_src), ZeroMass::Uniform).unwrap()
+}
+/// what quantizing a set gives when its total mass is not a positive finite number,
+/// e.g. when every component is a narrow peak centered far outside 0..=255.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ZeroMass {
+    /// fail with `Error::InvalidMass`.
+    Fail,
+    /// give the uniform distribution.
+    Uniform,
+}
+/// how faithful a quantized table must be to the densities, chosen by `PDFSet::try_finalize_with_policy`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Quantization {
+    /// always succeed, as `finalize`.
+    Lossy,
+    /// fail with `Error::InexactQuantization` if the quantized probability of a symbol
+    /// is more than `max_ratio` times, or less than `1 / max_ratio` times, its real probability.
+    /// symbols of zero probability are not checked, as they get the floor frequency.
+    Strict { max_ratio: f64 },
+    /// fail with `Error::RedundancyExceeded` if the KL divergence from the real to the quantized
+    /// distribution, the expected extra bits per symbol, is more than `bits`.
+    /// tables always use the full 32 bit range, so there is no higher precision to fall back to.
+    MaxRedundancy { bits: f64 },
+}
+impl Quantization {
+    /// `Quantization::MaxRedundancy`: a bound on the bits per symbol lost to quantization.
+    pub fn max_redundancy_bits(bits: f64) -> Self {
+        Self::MaxRedundancy { bits }
+    }
+}
+/// 確率が0でない値の，量子化した確率との比がmax_ratio以内かを確かめる
+fn check_ratio(
+    model: &QuantizedPDFSet,
+    freq_src: &[f64],
+    tot_freq_src: f64,
+    max_ratio: f64,
+) -> Result<()> {
+    let total = model.total_freq() as f64;
+    for (symbol, f) in freq_src.iter().enumerate() {
+        let probability = f / tot_freq_src;
+        if probability <= 0.0 {
+            continue;
+        }
+        let quantized = model.c_freq(symbol) as f64 / total;
+        let ratio = quantized / probability;
+        if ratio > max_ratio || ratio * max_ratio < 1.0 {
+            return Err(Error::InexactQuantization {
+                symbol,
+                probability,
+                quantized,
+            });
+        }
+    }
+    Ok(())
+}
+/// difference between a mixture and its quantized model, by `QuantizedPDFSet::quantization_report`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct QuantizationReport {
+    /// `(quantized - real) / real` probability of each of the 256 values,
+    /// infinite for values of zero real probability.
+    pub relative_error: Vec<f64>,
+    /// half the sum of the absolute differences of the probabilities.
+    pub total_variation: f64,
+    /// KL divergence from the real to the quantized distribution in bits,
+    /// the expected extra code length per symbol.
+    pub kl_divergence: f64,
+}
+/// 合計が正の有限の数でなければzero_massに従う量子化
+pub(crate) fn quantize_mass(
+    freq_src: &[f64],
+    tot_freq_src: f64,
+    zero_mass: ZeroMass,
+) -> Result<QuantizedPDFSet> {
+    if tot_freq_src.is_finite() && tot_freq_src > 0.0 {
+        return Ok(quantize(freq_src, tot_freq_src));
+    }
+    match zero_mass {
+        ZeroMass::Fail => Err(Error::InvalidMass(tot_freq_src)),
+        ZeroMass::Uniform => Ok(QuantizedPDFSet::uniform()),
+    }
+}
+/// 成分の確率を足し合わせる型
+/// feature = "f32" のときは，f64の演算が遅い環境のためにf32で積和演算をする
+#[cfg(not(feature = "f32"))]
+type Accumulator = f64;
+#[cfg(feature = "f32")]
+type Accumulator = f32;
+/// 行に重みを掛けて足す
+/// 非正規化数の確率と寄与は0にして，環境のflush-to-zeroの設定に依らない結果にする
+#[cfg(all(not(feature = "f32"), not(all(target_arch = "wasm32", target_feature = "simd128"))))]
+pub(crate) fn add_weighted_row(acc: &mut [Accumulator], row: &[f64], weight: f64) {
+    let weight = flush_subnormal(weight);
+    for (a, r) in acc.iter_mut().zip(row) {
+        *a += flush_subnormal(weight * flush_subnormal(*r));
+    }
+}
+#[cfg(all(not(feature = "f32"), all(target_arch = "wasm32", target_feature = "simd128")))]
+pub(crate) fn add_weighted_row(acc: &mut [Accumulator], row: &[f64], weight: f64) {
+    simd128::add_weighted_row(acc, row, flush_subnormal(weight));
+}
+#[cfg(feature = "f32")]
+pub(crate) fn add_weighted_row(acc: &mut [Accumulator], row: &[f64], weight: f64) {
+    let weight = flush_subnormal_f32(weight as f32);
+    for (a, r) in acc.iter_mut().zip(row) {
+        let r = flush_subnormal_f32(*r as f32);
+        if !(r * weight).is_subnormal() {
+            *a = r.mul_add(weight, *a);
+        }
+    }
+}
+/// バージョン1の足し合わせ（非正規化数もそのまま足す）
+#[cfg(not(feature = "f32"))]
+fn add_weighted_row_v1(acc: &mut [Accumulator], row: &[f64], weight: f64) {
+    for (a, r) in acc.iter_mut().zip(row) {
+        *a += weight * r;
+    }
+}
+#[cfg(feature = "f32")]
+fn add_weighted_row_v1(acc: &mut [Accumulator], row: &[f64], weight: f64) {
+    let weight = weight as f32;
+    for (a, r) in acc.iter_mut().zip(row) {
+        *a = (*r as f32).mul_add(weight, *a);
+    }
+}
+#[cfg(not(feature = "f32"))]
+fn flush_subnormal(x: f64) -> f64 {
+    if x.is_subnormal() {
+        0.0
+    } else {
+        x
+    }
+}
+#[cfg(feature = "f32")]
+fn flush_subnormal_f32(x: f32) -> f32 {
+    if x.is_subnormal() {
+        0.0
+    } else {
+        x
+    }
+}
+/// 足し合わせた確率を量子化のためにf64にする
+#[cfg(not(feature = "f32"))]
+pub(crate) fn accumulated(acc: Vec<Accumulator>) -> Vec<f64> {
+    acc
+}
+#[cfg(feature = "f32")]
+pub(crate) fn accumulated(acc: Vec<Accumulator>) -> Vec<f64> {
+    acc.into_iter().map(f64::from).collect()
+}
+/// accumulatedを確保済みの領域に書き込む
+#[cfg(not(feature = "f32"))]
+pub(crate) fn accumulated_into(acc: &[Accumulator], out: &mut [f64]) {
+    out.copy_from_slice(acc);
+}
+#[cfg(feature = "f32")]
+pub(crate) fn accumulated_into(acc: &[Accumulator], out: &mut [f64]) {
+    for (o, a) in out.iter_mut().zip(acc) {
+        *o = f64::from(*a);
+    }
+}
+/// 累積確率を計算する
+pub(crate) fn total_mass(freq_src: &[f64]) -> f64 {
+    freq_src.iter().fold(0f64, |cum, freq| cum + freq)
+}
+/// version of the quantization algorithm of `PDFSet::finalize`, recorded by `QuantizedPDFSet::to_bytes`.
+/// a change of the rounding gets a new version, and `PDFSet::finalize_version` keeps the old ones.
+pub const QUANTIZER_VERSION: u8 = 2;
+/// 成分の行を足し合わせる関数
+type RowAdder = fn(&mut [Accumulator], &[f64], f64);
+/// 量子化のバージョンの足し合わせを選ぶ（丸めはどのバージョンも quantize）
+/// 1: 割合を[0,1]に丸め，MAX_TOT_FREQ倍して切り捨て，1の底上げをして総頻度がu32を超えたら縮める
+/// 2: 1の丸めで，非正規化数の確率と寄与を0として足す
+fn row_adder(version: u8) -> Result<RowAdder> {
+    match version {
+        1 => Ok(add_weighted_row_v1),
+        2 => Ok(add_weighted_row),
+        _ => Err(Error::UnsupportedQuantizer(version)),
+    }
+}
+/// 直列化した表の最初のu16のうち，量子化のバージョンを入れる上位ビット
+const QUANTIZER_VERSION_SHIFT: u32 = 12;
+const RANGE_MAX: usize = u8::MAX as usize;
+const RANGE_SIZE: usize = RANGE_MAX + 1;
+const RANGE: core::ops::RangeInclusive<usize> = 0..=RANGE_MAX;
+/// 量子化（現在のバージョン）
+/// どんな入力でも，全ての頻度は1以上，総頻度はu32::MAX以下になる
+pub(crate) fn quantize(freq_src: &[f64], tot_freq_src: f64) -> QuantizedPDFSet {
+    /// 各値に底上げとして1ずつ割り振るので，maxから引いておく
+    const MAX_TOT_FREQ: u32 = u32::MAX - (u8::MAX as u32 + 1);
+    let mut freq: Vec<u32> = RANGE
+        .map(|x| {
+            // NaNや負の割合は0に，1を超える割合は1にする
+            let share = freq_src[x] / tot_freq_src;
+            let share = if share.is_nan() {
+                0.0
+            } else {
+                share.clamp(0.0, 1.0)
+            };
+            // 整数へ丸めた頻度を計算（1の底上げもする）
+            (MAX_TOT_FREQ as f64 * share) as u32 + 1
+        })
+        .collect();
+    fit_budget(&mut freq, u32::MAX as u64);
+    // 累積頻度の計算
+    let cum_freq = freq
+        .iter()
+        .scan(0, |cum, freq| {
+            let cum_clone = *cum;
+            *cum += freq;
+            Some(cum_clone)
+        })
+        .collect();
+    QuantizedPDFSet::from_tables(freq, cum_freq)
+}
+/// 総頻度がbudgetを超えていれば，底上げの1を除いた頻度を比例して縮める
+/// 割合の合計が丸めで1を超えたときのためのもので，budgetは頻度の数以上であること
+fn fit_budget(freq: &mut [u32], budget: u64) {
+    let n = freq.len() as u64;
+    debug_assert!(budget >= n);
+    let total: u64 = freq.iter().map(|f| *f as u64).sum();
+    if total <= budget {
+        return;
+    }
+    // 縮めた頻度の合計は (total - n) * (budget - n) / (total - n) + n <= budget
+    for f in freq.iter_mut() {
+        *f = ((*f as u64 - 1) as u128 * (budget - n) as u128 / (total - n) as u128) as u32 + 1;
     }
 }
 /// probability density function
 pub trait PDF {
     fn freq(&self, v: usize) -> f64;
+    /// set `out[v]` to `freq(v)` for every value of `out`, used by `finalize`.
+    /// implement it when all values can be evaluated faster at once,
+    /// e.g. with subexpressions shared between the values.
+    fn freq_range(&self, out: &mut [f64]) {
+        for (v, o) in out.iter_mut().enumerate() {
+            *o = self.freq(v);
+        }
+    }
 }
+/// index of the end-of-stream symbol in a model made by `QuantizedPDFSet::with_eof`.
+pub const EOF_SYMBOL: usize = u8::MAX as usize + 1;
 pub struct QuantizedPDFSet {
-    freq: Vec<u32>,
-    cum_freq: Vec<u32>,
+    freq: Box<[u32]>,
+    cum_freq: Box<[u32]>,
+    /// 総頻度（符号化/復号の度に計算しないように持っておく）
+    total: u32,
+    /// find_indexを速くするための表（with_decode_table）
+    decode_table: Option<DecodeTable>,
+    /// 有効な値が少ないときの，線形に探すための表
+    small_alphabet: Option<SmallAlphabet>,
+}
+/// sizes of decode tables tried by `QuantizedPDFSet::with_decode_table_for`, from 256 to 64K entries.
+pub const DECODE_TABLE_BITS: core::ops::RangeInclusive<u32> = 8..=16;
+/// default of `QuantizedPDFSet::with_small_alphabet_threshold`.
+pub const SMALL_ALPHABET_THRESHOLD: usize = 16;
+/// 底上げの1より大きい頻度を持つ値と，その累積頻度
+struct SmallAlphabet {
+    symbols: Box<[u16]>,
+    cum_freq: Box<[u32]>,
+}
+impl SmallAlphabet {
+    /// 有効な値がthreshold個以下なら作る
+    fn build(freq: &[u32], cum_freq: &[u32], threshold: usize) -> Option<Self> {
+        let symbols: Vec<u16> = (0..freq.len())
+            .filter(|i| freq[*i] > 1)
+            .map(|i| i as u16)
+            .collect();
+        if symbols.is_empty() || symbols.len() > threshold {
+            return None;
+        }
+        let cum_freq = symbols.iter().map(|s| cum_freq[*s as usize]).collect();
+        Some(Self {
+            symbols: symbols.into_boxed_slice(),
+            cum_freq,
+        })
+    }
+}
+/// 累積頻度の上位ビットから，その位置を含む値への表
+struct DecodeTable {
+    /// 累積頻度を右シフトするビット数
+    shift: u32,
+    /// 表の位置iの先頭（i << shift）を含む値
+    symbols: Box<[u16]>,
+}
+impl QuantizedPDFSet {
+    /// 頻度表から累積頻度表を作る
+    fn from_freq(freq: Vec<u32>) -> Self {
+        let cum_freq = freq
+            .iter()
+            .scan(0, |cum, f| {
+                let cum_clone = *cum;
+                *cum += f;
+                Some(cum_clone)
+            })
+            .collect();
+        Self::from_tables(freq, cum_freq)
+    }
+    /// 頻度表と累積頻度表から作る．総頻度はここでだけ計算し，表と合っているかをデバッグビルドで確かめる
+    fn from_tables(freq: Vec<u32>, cum_freq: Vec<u32>) -> Self {
+        let total = *cum_freq.last().unwrap() + *freq.last().unwrap();
+        debug_check_tables(&freq, &cum_freq, total);
+        let small_alphabet = SmallAlphabet::build(&freq, &cum_freq, SMALL_ALPHABET_THRESHOLD);
+        Self {
+            freq: freq.into_boxed_slice(),
+            cum_freq: cum_freq.into_boxed_slice(),
+            total,
+            decode_table: None,
+            small_alphabet,
+        }
+    }
+    /// when at most `threshold` symbols have more than the floor frequency 1,
+    /// `find_index` scans them linearly before falling back to the binary search.
+    /// models are made with `SMALL_ALPHABET_THRESHOLD`; 0 disables the scan.
+    pub fn with_small_alphabet_threshold(mut self, threshold: usize) -> Self {
+        self.small_alphabet = SmallAlphabet::build(&self.freq, &self.cum_freq, threshold);
+        self
+    }
+    /// number of symbols scanned linearly by `find_index`, 0 when the binary search is used.
+    pub fn small_alphabet_size(&self) -> usize {
+        self.small_alphabet.as_ref().map_or(0, |s| s.symbols.len())
+    }
+    /// build a table of `2^bits` entries (2 bytes each) from the top bits of the cumulative frequency
+    /// to the symbol, so `find_index` looks up the table and scans a few symbols
+    /// instead of a binary search. more bits use more memory and scan less.
+    /// the table only speeds up decoding, and the encoder does not need the same one.
+    ///
+    /// # Panics
+    /// if `bits` is not in `1..=24`. `with_decode_table_for` chooses from `DECODE_TABLE_BITS` instead.
+    pub fn with_decode_table(mut self, bits: u32) -> Self {
+        assert!((1..=24).contains(&bits));
+        let total = self.total_freq() as u64;
+        let shift = (64 - total.leading_zeros()).saturating_sub(bits);
+        let entries = ((total - 1) >> shift) as usize + 1;
+        let mut symbols = Vec::with_capacity(entries);
+        let mut symbol = 0;
+        for i in 0..entries as u64 {
+            let head = i << shift;
+            while symbol + 1 < self.cum_freq.len() && self.cum_freq[symbol + 1] as u64 <= head {
+                symbol += 1;
+            }
+            symbols.push(symbol as u16);
+        }
+        self.decode_table = Some(DecodeTable {
+            shift,
+            symbols: symbols.into_boxed_slice(),
+        });
+        self
+    }
+    /// the smallest decode table in `DECODE_TABLE_BITS` with which this machine decodes
+    /// at least `symbols_per_second` symbols of the model, measured by decoding a sample.
+    /// the largest table if none is fast enough.
+    #[cfg(feature = "std")]
+    pub fn with_decode_table_for(self, symbols_per_second: f64) -> Self {
+        // 分布に従う標本を，実際に復号して測る
+        let sample: Vec<usize> = (0..4096u64)
+            .map(|i| search_cum_freq(&self.cum_freq, i * self.total as u64 / 4096))
+            .collect();
+        let data = codec::encode_slice(&self, &sample);
+        self.with_decode_table_rated(symbols_per_second, |model| {
+            let start = std::time::Instant::now();
+            codec::decode_slice(model, data.clone(), sample.len());
+            sample.len() as f64 / start.elapsed().as_secs_f64()
+        })
+    }
+    /// 表の大きさを小さい順に試し，rateで測った速さがsymbols_per_second以上になる最小の表
+    #[cfg(feature = "std")]
+    fn with_decode_table_rated<F>(self, symbols_per_second: f64, mut rate: F) -> Self
+    where
+        F: FnMut(&Self) -> f64,
+    {
+        let mut model = self;
+        for bits in DECODE_TABLE_BITS {
+            model = model.with_decode_table(bits);
+            if rate(&model) >= symbols_per_second {
+                break;
+            }
+        }
+        model
+    }
+    /// bytes used by the model, including the tables for decoding.
+    pub fn memory_usage(&self) -> usize {
+        let small = self.small_alphabet.as_ref().map_or(0, |s| {
+            s.symbols.len() * core::mem::size_of::<u16>()
+                + s.cum_freq.len() * core::mem::size_of::<u32>()
+        });
+        core::mem::size_of::<Self>()
+            + (self.freq.len() + self.cum_freq.len()) * core::mem::size_of::<u32>()
+            + self.decode_table_size()
+            + small
+    }
+    /// bytes used by the decode table of `with_decode_table`, 0 without it.
+    pub fn decode_table_size(&self) -> usize {
+        self.decode_table
+            .as_ref()
+            .map_or(0, |t| t.symbols.len() * core::mem::size_of::<u16>())
+    }
+    /// number of symbols in the model.
+    pub fn alphabet_size(&self) -> usize {
+        self.freq.len()
+    }
+    /// whether the model has the end-of-stream symbol `EOF_SYMBOL`.
+    pub fn has_eof(&self) -> bool {
+        self.alphabet_size() > EOF_SYMBOL
+    }
+    /// estimated code length in bits of the symbols, without running the coder.
+    pub fn estimate_bits(&self, symbols: &[usize]) -> f64 {
+        let mut estimator = rate::RateEstimator::new();
+        estimator.add_slice(self, symbols);
+        estimator.bits()
+    }
+    /// how far the model is from the mixture it was quantized from,
+    /// i.e. what the integer rounding and the floor frequency 1 cost.
+    /// `original` should have a positive finite mass.
+    pub fn quantization_report<T: PDF>(&self, original: &PDFSet<T>) -> QuantizationReport {
+        let (freq_src, tot_freq_src) = original.mixture();
+        self.report_of(&freq_src, tot_freq_src)
+    }
+    /// 量子化する前の確率とのずれ
+    fn report_of(&self, freq_src: &[f64], tot_freq_src: f64) -> QuantizationReport {
+        let total = self.total as f64;
+        let mut report = QuantizationReport {
+            relative_error: Vec::with_capacity(RANGE_SIZE),
+            total_variation: 0.0,
+            kl_divergence: 0.0,
+        };
+        for (f, q) in freq_src.iter().zip(self.freq.iter()) {
+            let p = f / tot_freq_src;
+            let q = *q as f64 / total;
+            report.relative_error.push((q - p) / p);
+            report.total_variation += (q - p).abs() / 2.0;
+            if p > 0.0 {
+                report.kl_divergence += p * (p / q).log2();
+            }
+        }
+        report
+    }
+    /// encode and decode the symbols with the model, and check that they come back.
+    /// a one-call sanity check of a model and the coder before using them.
+    pub fn verify_roundtrip(&self, samples: &[usize]) -> core::result::Result<(), VerifyError> {
+        if let Some(symbol) = samples.iter().find(|s| **s >= self.alphabet_size()) {
+            return Err(VerifyError::SymbolOutOfRange {
+                symbol: *symbol,
+                alphabet_size: self.alphabet_size(),
+            });
+        }
+        let data = codec::encode_slice(self, samples);
+        let decoded = match codec::try_decode_slice(self, data, samples.len()) {
+            Ok(decoded) => decoded,
+            Err(Error::Decode(e)) => return Err(VerifyError::Decode(e)),
+            Err(_) => unreachable!("decoding a slice only fails with DecodeError"),
+        };
+        match samples.iter().zip(&decoded).position(|(s, d)| s != d) {
+            Some(position) => Err(VerifyError::Mismatch {
+                position,
+                expected: samples[position],
+                decoded: decoded[position],
+            }),
+            None => Ok(()),
+        }
+    }
+    /// serialize the frequency table.
+    /// format: alphabet size (low 12 bits of a u16 LE) with `QUANTIZER_VERSION` in the top 4 bits,
+    /// then frequency of each symbol (u32 LE).
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(2 + 4 * self.freq.len());
+        let head = self.freq.len() as u16 | (QUANTIZER_VERSION as u16) << QUANTIZER_VERSION_SHIFT;
+        bytes.extend_from_slice(&head.to_le_bytes());
+        for f in &self.freq {
+            bytes.extend_from_slice(&f.to_le_bytes());
+        }
+        bytes
+    }
+    /// load a model serialized by `to_bytes`.
+    /// returns the model and the number of bytes read.
+    pub fn from_bytes(bytes: &[u8]) -> Result<(Self, usize)> {
+        let (raw, len) = RawModel::from_bytes(bytes)?;
+        Ok((raw.validate()?, len))
+    }
+    /// `QUANTIZER_VERSION` of the crate which serialized the model with `to_bytes`,
+    /// the version to give `PDFSet::finalize_version` to make its tables again from the same components.
+    /// models serialized before the version was recorded have the rounding of version 1.
+    pub fn quantizer_version(bytes: &[u8]) -> Result<u8> {
+        if bytes.len() < 2 {
+            return Err(Error::UnexpectedEof);
+        }
+        let version = bytes[1] >> (QUANTIZER_VERSION_SHIFT - 8);
+        Ok(version.max(1))
+    }
+    /// model from a frequency table and a cumulative frequency table made elsewhere,
+    /// checked as `RawModel::validate`.
+    pub fn from_tables_checked(freq: Vec<u32>, cum_freq: Vec<u32>) -> Result<Self> {
+        RawModel::from_tables(freq, cum_freq).validate()
+    }
+    /// the uniform distribution over 256 symbols, scaled to the full frequency range.
+    pub fn uniform() -> Self {
+        quantize(&[1.0; RANGE_SIZE], RANGE_SIZE as f64)
+    }
+    /// model from symbol counts (of 256 symbols, or 257 with `EOF_SYMBOL`),
+    /// scaled to the full frequency range. symbols never counted get frequency 1.
+    /// `inline::InlinePDFSet::from_counts` makes the same model in a const context.
+    pub fn from_counts(counts: &[u64]) -> Result<Self> {
+        if counts.len() != RANGE_SIZE && counts.len() != EOF_SYMBOL + 1 {
+            return Err(Error::InvalidAlphabetSize(counts.len()));
+        }
+        let total = counts_total(counts);
+        if total == 0 {
+            return Err(Error::InvalidMass(0.0));
+        }
+        let freq = counts
+            .iter()
+            .map(|c| count_freq(*c, total, counts.len()))
+            .collect();
+        Ok(Self::from_freq(freq))
+    }
+    /// copy of the model with the end-of-stream symbol `EOF_SYMBOL` appended with frequency 1.
+    pub fn with_eof(&self) -> QuantizedPDFSet {
+        let mut freq = self.freq[..EOF_SYMBOL].to_vec();
+        // 総頻度に余裕がなければ，最も頻度の高い値から1を譲る
+        if freq.iter().map(|f| *f as u64).sum::<u64>() >= u32::MAX as u64 {
+            let max = (0..freq.len()).max_by_key(|i| freq[*i]).unwrap();
+            freq[max] -= 1;
+        }
+        freq.push(1);
+        Self::from_freq(freq)
+    }
 }
 impl PModel for QuantizedPDFSet {
     fn c_freq(&self, index: usize) -> u32 {
@@ -83,35 +895,224 @@ impl PModel for QuantizedPDFSet {
         self.cum_freq[index]
     }
     fn total_freq(&self) -> u32 {
-        *self.cum_freq.last().unwrap() + *self.freq.last().unwrap()
+        self.total
     }
     fn find_index(&self, decoder: &Decoder) -> usize {
-        let mut left = 0;
-        let mut right = std::u8::MAX as usize;
-        let rfreq = (decoder.data() - decoder.range_coder().lower_bound())
-            / decoder.range_coder().range_par_total(self.total_freq());
-        while left < right {
-            let mid = (left + right) / 2;
-            let mid_cum = self.cum_freq(mid + 1);
-            if mid_cum as u64 <= rfreq {
-                left = mid + 1;
-            } else {
-                right = mid;
+        self.index_of(coded_freq(decoder, self.total))
+    }
+}
+impl QuantizedPDFSet {
+    /// `find_index`, failing when the coded value is beyond the total frequency,
+    /// which happens only on corrupt data, instead of returning the last symbol.
+    pub fn try_find_index(&self, decoder: &Decoder) -> core::result::Result<usize, DecodeError> {
+        Ok(self.index_of(checked_coded_freq(decoder, self.total)?))
+    }
+    /// 累積頻度がrfreqを含む値
+    fn index_of(&self, rfreq: u64) -> usize {
+        match &self.decode_table {
+            Some(table) => {
+                let i = ((rfreq >> table.shift) as usize).min(table.symbols.len() - 1);
+                // 表の次の位置を含む値までを線形に探す
+                let last = table
+                    .symbols
+                    .get(i + 1)
+                    .map_or(self.cum_freq.len() - 1, |s| *s as usize);
+                let symbol = table.symbols[i] as usize;
+                symbol + count_at_most(&self.cum_freq[symbol + 1..=last], rfreq)
             }
+            None => self
+                .search_small_alphabet(rfreq)
+                .unwrap_or_else(|| search_cum_freq(&self.cum_freq, rfreq)),
+        }
+    }
+    /// 有効な値の中でrfreqを含むものを線形に探す．底上げだけの値なら失敗する
+    fn search_small_alphabet(&self, rfreq: u64) -> Option<usize> {
+        let small = self.small_alphabet.as_ref()?;
+        let count = count_at_most(&small.cum_freq, rfreq);
+        let symbol = *small.symbols.get(count.checked_sub(1)?)? as usize;
+        if rfreq < (self.cum_freq[symbol] + self.freq[symbol]) as u64 {
+            Some(symbol)
+        } else {
+            None
+        }
+    }
+}
+/// a frequency table loaded or made outside the crate, not yet checked.
+/// the coder only takes a `QuantizedPDFSet`, so a table reaches it only through `validate`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawModel {
+    freq: Vec<u32>,
+    /// 累積頻度表（与えられなければ頻度表から作る）
+    cum_freq: Option<Vec<u32>>,
+}
+impl RawModel {
+    /// table of frequencies; the cumulative frequencies are made from them.
+    pub fn from_freq(freq: Vec<u32>) -> Self {
+        Self {
+            freq,
+            cum_freq: None,
+        }
+    }
+    /// table of frequencies and cumulative frequencies, which must agree.
+    pub fn from_tables(freq: Vec<u32>, cum_freq: Vec<u32>) -> Self {
+        Self {
+            freq,
+            cum_freq: Some(cum_freq),
+        }
+    }
+    /// parse the format of `QuantizedPDFSet::to_bytes` without checking the frequencies.
+    /// returns the table and the number of bytes read.
+    pub fn from_bytes(bytes: &[u8]) -> Result<(Self, usize)> {
+        if bytes.len() < 2 {
+            return Err(Error::UnexpectedEof);
+        }
+        let head = u16::from_le_bytes([bytes[0], bytes[1]]);
+        let size = (head & ((1 << QUANTIZER_VERSION_SHIFT) - 1)) as usize;
+        if size != RANGE_SIZE && size != EOF_SYMBOL + 1 {
+            return Err(Error::InvalidAlphabetSize(size));
+        }
+        let len = 2 + 4 * size;
+        if bytes.len() < len {
+            return Err(Error::UnexpectedEof);
+        }
+        let freq = bytes[2..len]
+            .chunks(4)
+            .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .collect();
+        Ok((Self::from_freq(freq), len))
+    }
+    pub fn freq(&self) -> &[u32] {
+        &self.freq
+    }
+    /// the model for the coder.
+    /// fails unless the alphabet has 256 or 257 symbols, every frequency is nonzero,
+    /// every cumulative frequency is the sum of the frequencies before it,
+    /// and the total fits in u32.
+    pub fn validate(self) -> Result<QuantizedPDFSet> {
+        if self.freq.len() != RANGE_SIZE && self.freq.len() != EOF_SYMBOL + 1 {
+            return Err(Error::InvalidAlphabetSize(self.freq.len()));
+        }
+        check_freq(&self.freq)?;
+        let cum_freq = match self.cum_freq {
+            None => return Ok(QuantizedPDFSet::from_freq(self.freq)),
+            Some(cum_freq) => cum_freq,
+        };
+        if cum_freq.len() != self.freq.len() {
+            return Err(Error::InvalidAlphabetSize(cum_freq.len()));
+        }
+        let mut cum = 0;
+        for (i, (f, c)) in self.freq.iter().zip(&cum_freq).enumerate() {
+            if *c != cum {
+                return Err(Error::InconsistentCumFreq(i));
+            }
+            cum += f;
+        }
+        Ok(QuantizedPDFSet::from_tables(self.freq, cum_freq))
+    }
+}
+/// 全ての頻度が1以上で，総頻度がu32に収まるか
+/// 頻度0の値は符号化できず，復号でもその値の範囲が空になるので拒否する
+fn check_freq(freq: &[u32]) -> Result<()> {
+    if let Some(zero) = freq.iter().position(|f| *f == 0) {
+        return Err(Error::ZeroFrequency(zero));
+    }
+    if freq.iter().map(|f| *f as u64).sum::<u64>() > u32::MAX as u64 {
+        return Err(Error::FrequencyOverflow);
+    }
+    Ok(())
+}
+/// デバッグビルドでのみ，累積頻度表が頻度表の累積で，総頻度がその合計であることを確かめる
+/// 表を変更する全ての経路（量子化，読み込み，適応的な更新）の後で呼ぶ
+pub(crate) fn debug_check_tables(freq: &[u32], cum_freq: &[u32], total: u32) {
+    if !cfg!(debug_assertions) {
+        return;
+    }
+    assert_eq!(freq.len(), cum_freq.len());
+    let mut cum = 0u64;
+    for (i, (f, c)) in freq.iter().zip(cum_freq).enumerate() {
+        assert_eq!(
+            *c as u64, cum,
+            "cum_freq[{}] is not the sum of freq[..{}]",
+            i, i
+        );
+        cum += *f as u64;
+    }
+    assert_eq!(total as u64, cum, "total_freq is not the sum of freq");
+}
+/// 復号器の現在の値を，総頻度totalの頻度空間へ写す
+/// 数えた頻度の合計（from_countsとInlinePDFSet::from_countsで共有する）
+pub(crate) const fn counts_total(counts: &[u64]) -> u128 {
+    let mut total = 0u128;
+    let mut i = 0;
+    while i < counts.len() {
+        total += counts[i] as u128;
+        i += 1;
+    }
+    total
+}
+/// alphabet_size個の値の，合計がtotal（0でない）のうちcountだけ数えた値の頻度
+pub(crate) const fn count_freq(count: u64, total: u128, alphabet_size: usize) -> u32 {
+    // 各値に底上げとして1ずつ割り振るので，maxから引いておく
+    let max_tot_freq = (u32::MAX as usize - alphabet_size) as u128;
+    (max_tot_freq * count as u128 / total) as u32 + 1
+}
+pub(crate) fn coded_freq(decoder: &Decoder, total: u32) -> u64 {
+    (decoder.data() - decoder.range_coder().lower_bound())
+        / decoder.range_coder().range_par_total(total)
+}
+/// coded_freqで，値が総頻度の範囲の外なら（壊れたデータなら）失敗する
+pub(crate) fn checked_coded_freq(
+    decoder: &Decoder,
+    total: u32,
+) -> core::result::Result<u64, DecodeError> {
+    let rc = decoder.range_coder();
+    let range_par_total = rc.range_par_total(total);
+    match decoder.data().checked_sub(rc.lower_bound()) {
+        Some(offset) if range_par_total > 0 && offset / range_par_total < total as u64 => {
+            Ok(offset / range_par_total)
         }
-        left
+        _ => Err(DecodeError::Corrupt),
     }
 }
-impl std::fmt::Debug for QuantizedPDFSet {
-    fn fmt(&self, _f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        for i in 0..=std::u8::MAX {
-            println!("{:03}: {}", i, self.c_freq(i as usize));
+/// 累積頻度がrfreq以下の値の数
+/// 分岐なしで数えるので，ベクトル命令にできる（wasmではsimd128で4値ずつ数える）
+#[cfg(not(all(target_arch = "wasm32", target_feature = "simd128")))]
+fn count_at_most(cum_freq: &[u32], rfreq: u64) -> usize {
+    cum_freq
+        .iter()
+        .map(|c| (*c as u64 <= rfreq) as usize)
+        .sum()
+}
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+use simd128::count_at_most;
+/// 累積頻度がrfreqを含む値を二分探索する
+/// 比較の結果で分岐せずに範囲を半分にしていくので，256値なら常に8回で終わる
+pub(crate) fn search_cum_freq(cum_freq: &[u32], rfreq: u64) -> usize {
+    let mut base = 0;
+    let mut len = cum_freq.len();
+    while len > 1 {
+        let half = len / 2;
+        // 条件付きの移動にコンパイルされる
+        base = if cum_freq[base + half] as u64 <= rfreq {
+            base + half
+        } else {
+            base
+        };
+        len -= half;
+    }
+    base
+}
+impl core::fmt::Debug for QuantizedPDFSet {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        for i in 0..=u8::MAX {
+            writeln!(f, "{:03}: {}", i, self.c_freq(i as usize))?;
         }
         Ok(())
     }
 }
 #[cfg(test)]
 mod tests {
+    use crate::Error;
     use crate::PDFSet;
     use crate::QuantizedPDFSet;
     use crate::PDF;
@@ -128,9 +1129,9 @@ mod tests {
                 * (-1.0
                     * self.w
                     * self.w
-                    * (std::cmp::max(v, self.m as usize) - std::cmp::min(v, self.m as usize))
+                    * (core::cmp::max(v, self.m as usize) - core::cmp::min(v, self.m as usize))
                         as f64
-                    * (std::cmp::max(v, self.m as usize) - std::cmp::min(v, self.m as usize))
+                    * (core::cmp::max(v, self.m as usize) - core::cmp::min(v, self.m as usize))
                         as f64)
                     .exp()
         }
@@ -151,9 +1152,7 @@ mod tests {
             w: 5.0,
             m: 70,
         };
-        let set = PDFSet {
-            pdf_list: vec![g1, g2, g3],
-        };
+        let set = PDFSet::new(vec![g1, g2, g3]);
         set.finalize()
     }
     fn large_pmodel() -> QuantizedPDFSet {
@@ -172,9 +1171,7 @@ mod tests {
             w: 5.0,
             m: 70,
         };
-        let set = PDFSet {
-            pdf_list: vec![g1, g2, g3],
-        };
+        let set = PDFSet::new(vec![g1, g2, g3]);
         set.finalize()
     }
     #[test]
@@ -217,6 +1214,380 @@ mod tests {
         assert_eq!(ansewr, decoded);
     }
     #[test]
+    fn serialize() {
+        let pm = simple_pmodel();
+        let bytes = pm.to_bytes();
+        let (loaded, len) = QuantizedPDFSet::from_bytes(&bytes).unwrap();
+        assert_eq!(len, bytes.len());
+        assert_eq!(pm.freq, loaded.freq);
+        assert_eq!(pm.cum_freq, loaded.cum_freq);
+        assert!(QuantizedPDFSet::from_bytes(&bytes[..100]).is_err());
+        let mut zero = bytes.clone();
+        zero[2..6].copy_from_slice(&[0; 4]);
+        assert!(QuantizedPDFSet::from_bytes(&zero).is_err());
+    }
+    #[test]
+    fn quantizer_version() {
+        let set = || {
+            PDFSet::new(vec![
+                GaussianDist {
+                    h: 3.0,
+                    w: 20.0,
+                    m: 40,
+                },
+                GaussianDist {
+                    h: 1.0,
+                    w: 5.0,
+                    m: 200,
+                },
+            ])
+        };
+        let model = set().finalize_version(crate::QUANTIZER_VERSION).unwrap();
+        let bytes = model.to_bytes();
+        assert_eq!(bytes, set().finalize().to_bytes());
+        assert_eq!(
+            QuantizedPDFSet::quantizer_version(&bytes).unwrap(),
+            crate::QUANTIZER_VERSION
+        );
+        assert!(matches!(
+            set().finalize_version(crate::QUANTIZER_VERSION + 1),
+            Err(Error::UnsupportedQuantizer(_))
+        ));
+        // バージョンを記録する前の表も読める
+        let mut old = bytes;
+        old[1] &= 0x0f;
+        let (loaded, _) = QuantizedPDFSet::from_bytes(&old).unwrap();
+        assert_eq!(loaded.freq, model.freq);
+        assert_eq!(QuantizedPDFSet::quantizer_version(&old).unwrap(), 1);
+    }
+    #[test]
+    #[cfg(not(feature = "f32"))]
+    fn quantizer_version_1_subnormal() {
+        // 確率が非正規化数の値だけを持つ成分は，バージョン1ではその値に質量を持つ
+        let set = || {
+            PDFSet::new(vec![GaussianDist {
+                h: 1e-313,
+                w: 1000.0,
+                m: 7,
+            }])
+        };
+        let v1 = set().finalize_version(1).unwrap();
+        assert_eq!(v1.c_freq(7), 4294967040);
+        assert_eq!(v1.c_freq(0), 1);
+        assert_eq!(v1.total_freq(), u32::MAX);
+        let v2 = set().finalize_version(2).unwrap();
+        assert_eq!(v2.to_bytes(), QuantizedPDFSet::uniform().to_bytes());
+        assert_eq!(v2.to_bytes(), set().finalize().to_bytes());
+    }
+    #[test]
+    fn try_finalize() {
+        let empty = || PDFSet::<GaussianDist>::new(vec![]);
+        assert!(matches!(empty().try_finalize(), Err(Error::EmptySet)));
+        assert!(matches!(
+            empty().try_finalize_with(crate::ZeroMass::Uniform),
+            Err(Error::EmptySet)
+        ));
+        assert!(std::panic::catch_unwind(|| empty().finalize()).is_err());
+        let uniform = QuantizedPDFSet::uniform();
+        assert_eq!(uniform.c_freq(0), uniform.c_freq(255));
+        let zero = PDFSet::new(vec![GaussianDist {
+            h: 0.0,
+            w: 1.0,
+            m: 0,
+        }]);
+        assert!(zero.try_finalize().is_err());
+    }
+    #[test]
+    fn quantize_never_overflows() {
+        // 決まった種から作る，不正な値や極端な値を含む確率
+        let mut state = 0x9e37_79b9_7f4a_7c15u64;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        };
+        let special = [f64::NAN, f64::INFINITY, -1.0, 0.0, f64::MAX, 1e-310, 1.0];
+        for case in 0..500 {
+            let freq_src: Vec<f64> = (0..256)
+                .map(|_| match next() % 4 {
+                    0 => special[(next() % special.len() as u64) as usize],
+                    1 => f64::from_bits(next() >> 2),
+                    _ => (next() % 1000) as f64,
+                })
+                .collect();
+            let total = crate::total_mass(&freq_src);
+            // 合計より小さいtotalを渡しても溢れない
+            let tot = if case % 2 == 0 { total } else { total / 3.0 };
+            let pm = crate::quantize(&freq_src, tot);
+            let sum: u64 = (0..256).map(|i| pm.c_freq(i) as u64).sum();
+            assert_eq!(sum, pm.total_freq() as u64);
+            for i in 0..256 {
+                assert!(pm.c_freq(i) >= 1);
+                let cum: u64 = (0..i).map(|j| pm.c_freq(j) as u64).sum();
+                assert_eq!(pm.cum_freq(i) as u64, cum);
+            }
+        }
+        // 割合の合計が1を超えるとき
+        let pm = crate::quantize(&[1.0; 256], 1.0);
+        assert!(pm.total_freq() > u32::MAX - 256);
+        assert_eq!(pm.c_freq(0), pm.c_freq(255));
+    }
+    #[test]
+    fn summation_order() {
+        let components: Vec<GaussianDist> = (0..40)
+            .map(|i| GaussianDist {
+                h: 1.0 + i as f64 * 0.37,
+                w: 0.01 + i as f64 * 0.003,
+                m: (i * 41 % 256) as u8,
+            })
+            .collect();
+        let set = || {
+            let mut set = PDFSet::new(vec![]);
+            for (i, c) in components.iter().enumerate() {
+                set.add_weighted_pdf(
+                    GaussianDist {
+                        h: c.h,
+                        w: c.w,
+                        m: c.m,
+                    },
+                    0.5 + i as f64 * 0.1,
+                );
+            }
+            set
+        };
+        let finalized = set().finalize().to_bytes();
+        assert_eq!(set().finalize().to_bytes(), finalized);
+        // 値毎に成分の順に足し，総和は値の順に足す
+        #[cfg(not(feature = "f32"))]
+        {
+            let mut freq_src = vec![0.0; 256];
+            for (v, f) in freq_src.iter_mut().enumerate() {
+                for (i, c) in components.iter().enumerate() {
+                    *f += (0.5 + i as f64 * 0.1) * c.freq(v);
+                }
+            }
+            let total = freq_src.iter().fold(0.0, |t, f| t + f);
+            let expected = crate::quantize(&freq_src, total).to_bytes();
+            assert_eq!(finalized, expected);
+        }
+        #[cfg(feature = "parallel")]
+        assert_eq!(set().finalize_parallel().to_bytes(), finalized);
+    }
+    #[test]
+    fn corrupt_coded_value() {
+        let pm = QuantizedPDFSet::from_freq(vec![1; 256]);
+        // 範囲の上端の値は，総頻度で割ると総頻度以上になる
+        let mut decoder = Decoder::new();
+        decoder.set_data(vec![0xff; 16]);
+        decoder.decode_start();
+        assert_eq!(
+            pm.try_find_index(&decoder),
+            Err(crate::DecodeError::Corrupt)
+        );
+        assert!(crate::codec::ModelDecoder::new(vec![0xff; 16])
+            .try_decode(&pm)
+            .is_err());
+        let mut decoder = Decoder::new();
+        decoder.set_data(crate::codec::encode_slice(&pm, &[77]));
+        decoder.decode_start();
+        assert_eq!(pm.try_find_index(&decoder), Ok(77));
+    }
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "cum_freq[2]")]
+    fn broken_tables() {
+        QuantizedPDFSet::from_tables(vec![1, 2, 3], vec![0, 1, 4]);
+    }
+    #[test]
+    fn verify_roundtrip() {
+        let pm = simple_pmodel();
+        let symbols: Vec<usize> = (0..300).map(|i| (i * 7) % 256).collect();
+        assert_eq!(pm.verify_roundtrip(&symbols), Ok(()));
+        assert_eq!(pm.verify_roundtrip(&[]), Ok(()));
+        assert_eq!(
+            pm.verify_roundtrip(&[1, 256]),
+            Err(crate::VerifyError::SymbolOutOfRange {
+                symbol: 256,
+                alphabet_size: 256
+            })
+        );
+    }
+    #[test]
+    fn quantization_policy() {
+        let set = || {
+            PDFSet::new(vec![GaussianDist {
+                h: 1.0,
+                w: 0.005,
+                m: 128,
+            }])
+        };
+        assert!(set()
+            .try_finalize_with_policy(crate::Quantization::Lossy)
+            .is_ok());
+        assert!(set()
+            .try_finalize_with_policy(crate::Quantization::Strict { max_ratio: 1.01 })
+            .is_ok());
+        // 裾の確率は底上げの1より小さいので，表せない
+        let sharp = || {
+            PDFSet::new(vec![GaussianDist {
+                h: 1.0,
+                w: 0.3,
+                m: 0,
+            }])
+        };
+        match sharp().try_finalize_with_policy(crate::Quantization::Strict { max_ratio: 2.0 }) {
+            Err(Error::InexactQuantization {
+                probability,
+                quantized,
+                ..
+            }) => assert!(quantized > probability * 2.0),
+            r => panic!("{:?}", r.map(|m| m.total_freq())),
+        }
+        // 失う符号量の上限
+        let redundancy = sharp()
+            .finalize()
+            .quantization_report(&sharp())
+            .kl_divergence;
+        assert!(redundancy > 0.0);
+        let bound =
+            |bits| sharp().try_finalize_with_policy(crate::Quantization::max_redundancy_bits(bits));
+        assert!(bound(redundancy * 2.0).is_ok());
+        assert!(matches!(
+            bound(redundancy / 2.0),
+            Err(Error::RedundancyExceeded { .. })
+        ));
+    }
+    #[test]
+    fn quantization_report() {
+        let set = || {
+            PDFSet::new(vec![GaussianDist {
+                h: 1.0,
+                w: 0.05,
+                m: 100,
+            }])
+        };
+        let report = set().finalize().quantization_report(&set());
+        assert_eq!(report.relative_error.len(), 256);
+        // 中心付近は正確で，裾は底上げの分だけ大きくなる
+        assert!(report.relative_error[100].abs() < 1e-6);
+        assert!(report.relative_error[255] > 1.0);
+        assert!(report.total_variation > 0.0 && report.total_variation < 1e-6);
+        assert!(report.kl_divergence >= 0.0 && report.kl_divergence < 1e-6);
+    }
+    #[test]
+    fn checked_tables() {
+        let pm = simple_pmodel();
+        let freq: Vec<u32> = (0..256).map(|i| pm.c_freq(i)).collect();
+        let cum: Vec<u32> = (0..256).map(|i| pm.cum_freq(i)).collect();
+        let loaded = QuantizedPDFSet::from_tables_checked(freq.clone(), cum.clone()).unwrap();
+        assert_eq!(loaded.to_bytes(), pm.to_bytes());
+        let mut broken = cum.clone();
+        broken[7] += 1;
+        assert!(matches!(
+            QuantizedPDFSet::from_tables_checked(freq.clone(), broken),
+            Err(Error::InconsistentCumFreq(7))
+        ));
+        let mut zero = freq.clone();
+        zero[3] = 0;
+        assert!(matches!(
+            QuantizedPDFSet::from_tables_checked(zero, cum.clone()),
+            Err(Error::ZeroFrequency(3))
+        ));
+        let mut large = freq;
+        large[0] = u32::MAX;
+        assert!(matches!(
+            QuantizedPDFSet::from_tables_checked(large, cum),
+            Err(Error::FrequencyOverflow)
+        ));
+        assert!(QuantizedPDFSet::from_tables_checked(vec![1; 3], vec![0, 1, 2]).is_err());
+    }
+    #[test]
+    fn raw_model() {
+        let pm = simple_pmodel();
+        let mut bytes = pm.to_bytes();
+        let (raw, len) = crate::RawModel::from_bytes(&bytes).unwrap();
+        assert_eq!(len, bytes.len());
+        assert_eq!(raw.freq()[34], pm.c_freq(34));
+        assert_eq!(raw.validate().unwrap().to_bytes(), pm.to_bytes());
+        // 頻度0の表も読めるが，検証で拒否される
+        bytes[2 + 4 * 9..2 + 4 * 10].copy_from_slice(&[0; 4]);
+        let (raw, _) = crate::RawModel::from_bytes(&bytes).unwrap();
+        assert!(matches!(raw.validate(), Err(Error::ZeroFrequency(9))));
+        assert!(crate::RawModel::from_freq(vec![1; 255]).validate().is_err());
+    }
+    #[test]
+    fn subnormal_contributions() {
+        // 確率が非正規化数の成分と，重みとの積が非正規化数になる成分は質量を持たない
+        let tiny = PDFSet::new(vec![GaussianDist {
+            h: 1e-310,
+            w: 1.0,
+            m: 100,
+        }]);
+        assert_eq!(
+            tiny.finalize().to_bytes(),
+            QuantizedPDFSet::uniform().to_bytes()
+        );
+        let mut weighted = PDFSet::new(vec![]);
+        weighted.add_weighted_pdf(
+            GaussianDist {
+                h: 1e-10,
+                w: 1.0,
+                m: 100,
+            },
+            1e-300,
+        );
+        assert!(matches!(
+            weighted.try_finalize(),
+            Err(Error::InvalidMass(_))
+        ));
+    }
+    #[test]
+    fn zero_mass() {
+        let zero = || {
+            PDFSet::new(vec![GaussianDist {
+                h: 0.0,
+                w: 1.0,
+                m: 0,
+            }])
+        };
+        assert!(matches!(
+            zero().try_finalize_with(crate::ZeroMass::Fail),
+            Err(Error::InvalidMass(_))
+        ));
+        let uniform = zero().try_finalize_with(crate::ZeroMass::Uniform).unwrap();
+        assert_eq!(uniform.c_freq(0), uniform.c_freq(255));
+        assert!(uniform.total_freq() > u32::MAX / 2);
+        let mut set = zero();
+        set.set_weight(0, 0.0);
+        assert_eq!(set.finalize().to_bytes(), uniform.to_bytes());
+    }
+    #[test]
+    fn invalid_density() {
+        let component = |h| GaussianDist { h, w: 0.1, m: 40 };
+        let mut set = PDFSet::new(vec![component(1.0), component(-1.0)]);
+        match set.validate() {
+            Err(Error::InvalidDensity {
+                component: 1,
+                symbol: 0,
+                value,
+            }) => assert!(value < 0.0),
+            r => panic!("{:?}", r),
+        }
+        set.add_pdf(component(f64::NAN));
+        set.set_weight(1, 1.0);
+        assert!(matches!(
+            set.validate(),
+            Err(Error::InvalidDensity { component: 1, .. })
+        ));
+        let mut set = PDFSet::new(vec![component(1.0)]);
+        set.add_weighted_pdf(component(1.0), f64::INFINITY);
+        assert!(matches!(
+            set.try_finalize(),
+            Err(Error::InvalidWeight { component: 1, .. })
+        ));
+    }
+    #[test]
     fn use_full_range() {
         let pm = large_pmodel();
         assert!((std::u32::MAX as f64)
@@ -230,4 +1601,152 @@ mod tests {
             .mul_add(-0.9999999, pm.total_freq() as f64)
             .is_sign_positive());
     }
+    #[test]
+    fn finalize_ref_reuses_rows() {
+        use crate::dist::Gaussian;
+        let mut set = PDFSet::new(vec![
+            Gaussian::new(1.0, 128.0, 3.0),
+            Gaussian::new(1.0, 30.0, 1.0),
+        ]);
+        set.add_pdf(Gaussian::new(1.0, 128.0, 3.0));
+        let first = set.finalize_ref();
+        assert_eq!(set.rows.borrow().rows.len(), 2);
+        set.set_weight(1, 4.0);
+        let reweighted = set.finalize_ref();
+        assert!(reweighted.c_freq(30) > first.c_freq(30));
+        let mut direct = PDFSet::new(vec![
+            Gaussian::new(1.0, 128.0, 3.0),
+            Gaussian::new(1.0, 30.0, 1.0),
+        ]);
+        direct.add_pdf(Gaussian::new(1.0, 128.0, 3.0));
+        direct.set_weight(1, 4.0);
+        let direct = direct.finalize();
+        assert!((0..256).all(|i| direct.c_freq(i) == reweighted.c_freq(i)));
+    }
+    #[test]
+    fn decode_table() {
+        let symbols: Vec<usize> = (0..2000).map(|i| (i * i + 7 * i) % 256).collect();
+        for bits in &[1, 6, 12] {
+            let pm = simple_pmodel().with_decode_table(*bits);
+            assert!(pm.decode_table_size() <= 2 << *bits);
+            let mut encoder = Encoder::new();
+            for s in &symbols {
+                encoder.encode(&pm, *s);
+            }
+            encoder.finish();
+            let mut decoder = Decoder::new();
+            decoder.set_data(encoder.data().clone());
+            decoder.decode_start();
+            for s in &symbols {
+                assert_eq!(decoder.decode_one_alphabet(&pm), *s);
+            }
+        }
+        assert_eq!(simple_pmodel().decode_table_size(), 0);
+        // 速さの代わりに表の大きさを返し，試した回数を数える
+        let bits = crate::DECODE_TABLE_BITS;
+        let choose = |symbols_per_second: f64| {
+            let mut tried = 0;
+            let model = simple_pmodel().with_decode_table_rated(symbols_per_second, |m| {
+                tried += 1;
+                m.decode_table_size() as f64
+            });
+            (model.decode_table_size(), tried)
+        };
+        assert_eq!(choose(0.0), (2 << bits.start(), 1));
+        assert_eq!(choose((2 << 10) as f64), (2 << 10, 3));
+        // 達成できない速さなら最大の表
+        assert_eq!(
+            choose(f64::INFINITY),
+            (2 << bits.end(), bits.clone().count())
+        );
+        let smallest = simple_pmodel().with_decode_table_for(0.0);
+        assert_eq!(smallest.decode_table_size(), 2 << bits.start());
+    }
+    #[test]
+    fn search_matches_linear() {
+        // 頻度0の値や，端の値も含む
+        let freq = [3u32, 0, 0, 5, 1, 0, 7, 2, 0];
+        let cum: Vec<u32> = freq
+            .iter()
+            .scan(0, |c, f| {
+                let cum = *c;
+                *c += f;
+                Some(cum)
+            })
+            .collect();
+        for rfreq in 0..18u64 {
+            let expected = (0..freq.len())
+                .find(|i| rfreq < (cum[*i] + freq[*i]) as u64)
+                .unwrap();
+            assert_eq!(crate::search_cum_freq(&cum, rfreq), expected, "{}", rfreq);
+        }
+        assert_eq!(crate::search_cum_freq(&cum, 100), freq.len() - 1);
+    }
+    #[test]
+    fn accumulation_rate_penalty() {
+        use crate::dist::Gaussian;
+        let set = || {
+            let mut set = PDFSet::new(vec![Gaussian::new(1.0, 128.0, 9.0)]);
+            set.add_weighted_pdf(Gaussian::new(1.0, 30.0, 2.0), 0.3);
+            set.add_weighted_pdf(Gaussian::new(1.0, 200.0, 5.0), 0.01);
+            set
+        };
+        // f64で値毎に足した基準のモデル
+        let mut freq_src = vec![0f64; crate::RANGE_SIZE];
+        for (v, f) in freq_src.iter_mut().enumerate() {
+            *f = Gaussian::new(1.0, 128.0, 9.0).freq(v)
+                + 0.3 * Gaussian::new(1.0, 30.0, 2.0).freq(v)
+                + 0.01 * Gaussian::new(1.0, 200.0, 5.0).freq(v);
+        }
+        let reference = crate::quantize(&freq_src, freq_src.iter().sum());
+        let symbols: Vec<usize> = (0..5000)
+            .map(|i| [128, 120, 140, 30, 31, 200, 7][i % 7])
+            .collect();
+        let bits = set().finalize().estimate_bits(&symbols);
+        let reference_bits = reference.estimate_bits(&symbols);
+        assert!(
+            (bits - reference_bits).abs() < reference_bits * 1e-4,
+            "{} {}",
+            bits,
+            reference_bits
+        );
+        let cached = set().finalize_ref().estimate_bits(&symbols);
+        assert!((cached - reference_bits).abs() < reference_bits * 1e-4);
+    }
+    #[test]
+    fn small_alphabet_scan() {
+        let mut counts = vec![0u64; 256];
+        counts[3] = 50;
+        counts[4] = 30;
+        counts[200] = 20;
+        let pm = QuantizedPDFSet::from_counts(&counts).unwrap();
+        assert_eq!(pm.small_alphabet_size(), 3);
+        let binary_search = QuantizedPDFSet::from_counts(&counts)
+            .unwrap()
+            .with_small_alphabet_threshold(2);
+        assert_eq!(binary_search.small_alphabet_size(), 0);
+        // 底上げだけの値も復号できる
+        let symbols = vec![3, 4, 200, 3, 0, 255, 5, 199, 201, 3, 4];
+        for pm in &[pm, binary_search] {
+            let mut encoder = Encoder::new();
+            for s in &symbols {
+                encoder.encode(pm, *s);
+            }
+            encoder.finish();
+            let mut decoder = Decoder::new();
+            decoder.set_data(encoder.data().clone());
+            decoder.decode_start();
+            for s in &symbols {
+                assert_eq!(decoder.decode_one_alphabet(pm), *s);
+            }
+        }
+    }
+    #[test]
+    fn memory_usage() {
+        let pm = simple_pmodel().with_small_alphabet_threshold(0);
+        let tables = 2 * 256 * 4;
+        assert!(pm.memory_usage() >= tables && pm.memory_usage() < tables + 128);
+        let with_table = pm.with_decode_table(10);
+        assert!(with_table.memory_usage() >= tables + 2048);
+    }
 }
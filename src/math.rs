@@ -0,0 +1,70 @@
+//! no_stdでの浮動小数点数の関数
+//! coreにはexpやlnなどがないので，stdと同じ名前のメソッドをlibmで実装する
+//! stdがあるときは作らない（テストはstdがあるときだけ行う）
+//! absなどのcoreにあるものは持たない
+
+/// 使う関数だけを，stdのf64/f32のメソッドと同じ名前で持つ
+/// featureによっては使わない関数がある
+#[allow(dead_code)]
+pub(crate) trait Float: Sized {
+    fn exp(self) -> Self;
+    fn ln(self) -> Self;
+    fn log2(self) -> Self;
+    fn sqrt(self) -> Self;
+    fn floor(self) -> Self;
+    fn ceil(self) -> Self;
+    fn round(self) -> Self;
+    fn mul_add(self, a: Self, b: Self) -> Self;
+}
+impl Float for f64 {
+    fn exp(self) -> Self {
+        libm::exp(self)
+    }
+    fn ln(self) -> Self {
+        libm::log(self)
+    }
+    fn log2(self) -> Self {
+        libm::log2(self)
+    }
+    fn sqrt(self) -> Self {
+        libm::sqrt(self)
+    }
+    fn floor(self) -> Self {
+        libm::floor(self)
+    }
+    fn ceil(self) -> Self {
+        libm::ceil(self)
+    }
+    fn round(self) -> Self {
+        libm::round(self)
+    }
+    fn mul_add(self, a: Self, b: Self) -> Self {
+        libm::fma(self, a, b)
+    }
+}
+impl Float for f32 {
+    fn exp(self) -> Self {
+        libm::expf(self)
+    }
+    fn ln(self) -> Self {
+        libm::logf(self)
+    }
+    fn log2(self) -> Self {
+        libm::log2f(self)
+    }
+    fn sqrt(self) -> Self {
+        libm::sqrtf(self)
+    }
+    fn floor(self) -> Self {
+        libm::floorf(self)
+    }
+    fn ceil(self) -> Self {
+        libm::ceilf(self)
+    }
+    fn round(self) -> Self {
+        libm::roundf(self)
+    }
+    fn mul_add(self, a: Self, b: Self) -> Self {
+        libm::fmaf(self, a, b)
+    }
+}
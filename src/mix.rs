@@ -0,0 +1,150 @@
+//! 複数のモデルの予測の混合
+//! 各モデルの対数確率を重み付きで足し合わせ，正規化して1つのモデルにする
+//! （二値のロジスティック混合を多値へ拡げたもの）
+//! 重みは符号化したシンボルの符号長が短くなるよう，勾配法で学習する
+//! 混合器: Mixer
+//! 混合したモデル: MixedModel
+//! 浮動小数点演算を使うので，符号化と復号は同じ環境で行う
+
+use crate::prelude::*;
+use crate::QuantizedPDFSet;
+use range_coder::decoder::Decoder;
+use range_coder::pmodel::PModel;
+
+/// total frequency of a mixed model before the one-count floor.
+const MIXED_TOTAL: f64 = (1u32 << 24) as f64;
+/// default step size of the weight update.
+pub const DEFAULT_LEARNING_RATE: f64 = 0.02;
+
+/// learns weights to mix component models.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mixer {
+    weights: Vec<f64>,
+    alphabet_size: usize,
+    learning_rate: f64,
+}
+impl Mixer {
+    /// mixer of `components` models over `alphabet_size` symbols, starting from equal weights.
+    pub fn new(components: usize, alphabet_size: usize) -> Self {
+        assert!(components > 0 && alphabet_size > 0);
+        Self {
+            weights: vec![1.0 / components as f64; components],
+            alphabet_size,
+            learning_rate: DEFAULT_LEARNING_RATE,
+        }
+    }
+    pub fn with_learning_rate(mut self, learning_rate: f64) -> Self {
+        assert!(learning_rate >= 0.0);
+        self.learning_rate = learning_rate;
+        self
+    }
+    pub fn weights(&self) -> &[f64] {
+        &self.weights
+    }
+    /// mix the predictions of the components, in the same order every time.
+    pub fn mix(&self, components: &[&dyn PModel]) -> MixedModel {
+        assert_eq!(components.len(), self.weights.len());
+        let log_probs: Vec<Vec<f64>> = components
+            .iter()
+            .map(|m| {
+                let total = m.total_freq() as f64;
+                (0..self.alphabet_size)
+                    .map(|s| (m.c_freq(s) as f64 / total).ln())
+                    .collect()
+            })
+            .collect();
+        // 重み付きの対数確率を，桁あふれしないよう最大値を引いてから指数に戻す
+        let logits: Vec<f64> = (0..self.alphabet_size)
+            .map(|s| {
+                self.weights
+                    .iter()
+                    .zip(&log_probs)
+                    .map(|(w, l)| w * l[s])
+                    .sum()
+            })
+            .collect();
+        let max = logits.iter().cloned().fold(f64::NEG_INFINITY, f64::max);
+        let exp: Vec<f64> = logits.iter().map(|l| (l - max).exp()).collect();
+        let sum: f64 = exp.iter().sum();
+        let probs: Vec<f64> = exp.iter().map(|e| e / sum).collect();
+        let freq = probs.iter().map(|p| (p * MIXED_TOTAL) as u32 + 1).collect();
+        MixedModel {
+            table: QuantizedPDFSet::from_freq(freq),
+            log_probs,
+            probs,
+        }
+    }
+    /// learn from the symbol coded with `mixed`, made by `mix` of this mixer.
+    pub fn update(&mut self, mixed: &MixedModel, symbol: usize) {
+        for (w, l) in self.weights.iter_mut().zip(&mixed.log_probs) {
+            // 符号長 -ln P(symbol) の重みについての勾配
+            let expected: f64 = mixed.probs.iter().zip(l).map(|(p, l)| p * l).sum();
+            *w += self.learning_rate * (l[symbol] - expected);
+        }
+    }
+}
+
+/// model mixed by `Mixer::mix`.
+#[derive(Debug)]
+pub struct MixedModel {
+    table: QuantizedPDFSet,
+    /// 各モデルの各シンボルの対数確率
+    log_probs: Vec<Vec<f64>>,
+    /// 混合した確率
+    probs: Vec<f64>,
+}
+impl MixedModel {
+    /// mixed probability of the symbol.
+    pub fn probability(&self, symbol: usize) -> f64 {
+        self.probs[symbol]
+    }
+}
+impl PModel for MixedModel {
+    fn c_freq(&self, index: usize) -> u32 {
+        self.table.c_freq(index)
+    }
+    fn cum_freq(&self, index: usize) -> u32 {
+        self.table.cum_freq(index)
+    }
+    fn total_freq(&self) -> u32 {
+        self.table.total_freq()
+    }
+    fn find_index(&self, decoder: &Decoder) -> usize {
+        self.table.find_index(decoder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::adaptive::{Adaptive, AdaptiveModel};
+    use crate::codec::{ModelDecoder, ModelEncoder};
+    use crate::test_util::simple_pmodel;
+    #[test]
+    fn mixing_learns_better_component() {
+        let prior = simple_pmodel();
+        let symbols: Vec<usize> = (0..3000).map(|i| [5, 6, 7][i % 3]).collect();
+        let mut encoder = ModelEncoder::new();
+        let mut mixer = Mixer::new(2, 256);
+        let mut adaptive = AdaptiveModel::new(256);
+        for s in &symbols {
+            let mixed = mixer.mix(&[&prior, &adaptive]);
+            encoder.encode(&mixed, *s);
+            mixer.update(&mixed, *s);
+            adaptive.update(*s);
+        }
+        // 静的なモデルは5～7をほとんど予測しない
+        assert!(mixer.weights()[1] > mixer.weights()[0]);
+        let data = encoder.finish();
+        let mut decoder = ModelDecoder::new(data);
+        let mut mixer = Mixer::new(2, 256);
+        let mut adaptive = AdaptiveModel::new(256);
+        for s in &symbols {
+            let mixed = mixer.mix(&[&prior, &adaptive]);
+            let decoded = decoder.try_decode(&mixed).unwrap();
+            assert_eq!(decoded, *s);
+            mixer.update(&mixed, decoded);
+            adaptive.update(decoded);
+        }
+    }
+}
@@ -0,0 +1,219 @@
+//! numpyの.npy形式のヒストグラムの読み込み
+//! Pythonで集計した頻度や確率を，中間の形式を通さずにモデルにする
+//! 1次元の整数（u1〜u8，i1〜i8）か浮動小数点数（f4，f8）の配列で，バイト順はどちらでもよい
+//! 整数の配列は頻度として，浮動小数点数の配列は確率として読む: Histogram
+//! 経験分布: Empirical（PDFSetの成分として他の分布と混ぜられる）
+//! ヘッダは信頼できないデータとして検査し，不正ならError::InvalidNpy
+
+use crate::prelude::*;
+use crate::{Error, PDFSet, QuantizedPDFSet, Result, EOF_SYMBOL, PDF};
+#[cfg(feature = "std")]
+use std::path::Path;
+
+const MAGIC: &[u8] = b"\x93NUMPY";
+
+/// a 1-D array read from a `.npy` file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Histogram {
+    /// an array of integers.
+    Counts(Vec<u64>),
+    /// an array of floating point numbers.
+    Probabilities(Vec<f64>),
+}
+impl Histogram {
+    pub fn from_npy(bytes: &[u8]) -> Result<Self> {
+        let (descr, len, data) = parse_header(bytes)?;
+        let big_endian = descr.starts_with('>');
+        let mut kind = descr.trim_start_matches(&['<', '>', '|', '='][..]).chars();
+        let unknown = Error::InvalidNpy("unknown dtype");
+        let (kind, size) = match (kind.next(), kind.as_str().parse::<usize>()) {
+            (Some(kind), Ok(size)) if [1, 2, 4, 8].contains(&size) => (kind, size),
+            _ => return Err(unknown),
+        };
+        let values = data.chunks_exact(size).take(len).map(|c| {
+            let mut b = [0u8; 8];
+            if big_endian {
+                b[8 - size..].copy_from_slice(c);
+                u64::from_be_bytes(b)
+            } else {
+                b[..size].copy_from_slice(c);
+                u64::from_le_bytes(b)
+            }
+        });
+        if data.len() < len.saturating_mul(size) {
+            return Err(Error::UnexpectedEof);
+        }
+        match (kind, size) {
+            ('u', _) => Ok(Self::Counts(values.collect())),
+            ('i', _) => values
+                .map(|v| {
+                    // 符号を拡張して，負の頻度は拒否する
+                    let shift = 64 - 8 * size as u32;
+                    match ((v << shift) as i64) >> shift {
+                        v if v < 0 => Err(Error::InvalidNpy("negative count")),
+                        v => Ok(v as u64),
+                    }
+                })
+                .collect::<Result<_>>()
+                .map(Self::Counts),
+            ('f', 4) => Ok(Self::Probabilities(
+                values.map(|v| f32::from_bits(v as u32) as f64).collect(),
+            )),
+            ('f', 8) => Ok(Self::Probabilities(values.map(f64::from_bits).collect())),
+            _ => Err(unknown),
+        }
+    }
+    #[cfg(feature = "std")]
+    pub fn from_npy_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::from_npy(&std::fs::read(path)?)
+    }
+    /// counts of 256 symbols (or 257 with `EOF_SYMBOL`) as `QuantizedPDFSet::from_counts`,
+    /// or probabilities of 256 values as `PDFSet::try_finalize` of the `Empirical` distribution.
+    pub fn finalize(self) -> Result<QuantizedPDFSet> {
+        match self {
+            Self::Counts(counts) => QuantizedPDFSet::from_counts(&counts),
+            Self::Probabilities(p) if p.len() != EOF_SYMBOL => {
+                Err(Error::InvalidAlphabetSize(p.len()))
+            }
+            Self::Probabilities(p) => PDFSet::new(vec![Empirical::new(p)]).try_finalize(),
+        }
+    }
+    /// the histogram as a distribution, to be mixed with other components.
+    pub fn into_pdf(self) -> Empirical {
+        match self {
+            Self::Counts(counts) => Empirical::new(counts.into_iter().map(|c| c as f64).collect()),
+            Self::Probabilities(p) => Empirical::new(p),
+        }
+    }
+}
+
+/// distribution given by a table of densities, 0 beyond the table.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Empirical {
+    density: Vec<f64>,
+}
+impl Empirical {
+    pub fn new(density: Vec<f64>) -> Self {
+        Self { density }
+    }
+    pub fn density(&self) -> &[f64] {
+        &self.density
+    }
+}
+impl PDF for Empirical {
+    fn freq(&self, v: usize) -> f64 {
+        self.density.get(v).copied().unwrap_or(0.0)
+    }
+}
+
+/// 型，要素の数と，データの部分
+fn parse_header(bytes: &[u8]) -> Result<(&str, usize, &[u8])> {
+    if bytes.len() < MAGIC.len() + 4 {
+        return Err(Error::UnexpectedEof);
+    }
+    if &bytes[..MAGIC.len()] != MAGIC {
+        return Err(Error::BadMagic);
+    }
+    let (header_len, start) = match bytes[6] {
+        1 => (u16::from_le_bytes([bytes[8], bytes[9]]) as usize, 10usize),
+        2 | 3 if bytes.len() >= 12 => (
+            u32::from_le_bytes([bytes[8], bytes[9], bytes[10], bytes[11]]) as usize,
+            12,
+        ),
+        2 | 3 => return Err(Error::UnexpectedEof),
+        version => return Err(Error::UnsupportedVersion(version)),
+    };
+    let end = start
+        .checked_add(header_len)
+        .filter(|end| *end <= bytes.len())
+        .ok_or(Error::UnexpectedEof)?;
+    let header = core::str::from_utf8(&bytes[start..end])
+        .map_err(|_| Error::InvalidNpy("header is not text"))?;
+    let descr = field(header, "descr")?
+        .strip_prefix('\'')
+        .and_then(|d| d.split('\'').next())
+        .ok_or(Error::InvalidNpy("no dtype"))?;
+    let shape = field(header, "shape")?;
+    let dims: Vec<&str> = shape
+        .strip_prefix('(')
+        .and_then(|s| s.split(')').next())
+        .ok_or(Error::InvalidNpy("no shape"))?
+        .split(',')
+        .map(str::trim)
+        .filter(|d| !d.is_empty())
+        .collect();
+    let len = match dims.as_slice() {
+        [len] => len
+            .parse()
+            .map_err(|_| Error::InvalidNpy("invalid shape"))?,
+        _ => return Err(Error::InvalidNpy("array is not 1-D")),
+    };
+    Ok((descr, len, &bytes[end..]))
+}
+/// ヘッダの辞書の，キーの後の値の始まり
+fn field<'a>(header: &'a str, key: &str) -> Result<&'a str> {
+    let key = format!("'{}':", key);
+    let at = header
+        .find(&key)
+        .ok_or(Error::InvalidNpy("missing field"))?;
+    Ok(header[at + key.len()..].trim_start())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use range_coder::pmodel::PModel;
+    /// version 1の.npyのバイト列
+    fn npy(descr: &str, len: usize, data: &[u8]) -> Vec<u8> {
+        let mut header = format!(
+            "{{'descr': '{}', 'fortran_order': False, 'shape': ({},), }}",
+            descr, len
+        );
+        let unpadded = MAGIC.len() + 4 + header.len() + 1;
+        header.push_str(&" ".repeat((64 - unpadded % 64) % 64));
+        header.push('\n');
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&[1, 0]);
+        bytes.extend_from_slice(&(header.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(header.as_bytes());
+        bytes.extend_from_slice(data);
+        bytes
+    }
+    #[test]
+    fn counts_and_probabilities() {
+        let counts: Vec<u8> = (0..256u64).flat_map(|i| (i % 7).to_le_bytes()).collect();
+        let histogram = Histogram::from_npy(&npy("<u8", 256, &counts)).unwrap();
+        let expected: Vec<u64> = (0..256).map(|i| i % 7).collect();
+        assert_eq!(histogram, Histogram::Counts(expected.clone()));
+        let model = histogram.finalize().unwrap();
+        assert_eq!(
+            model.to_bytes(),
+            QuantizedPDFSet::from_counts(&expected).unwrap().to_bytes()
+        );
+        let p: Vec<u8> = (0..256)
+            .flat_map(|i| (1.0 / (1.0 + i as f64)).to_be_bytes())
+            .collect();
+        let histogram = Histogram::from_npy(&npy(">f8", 256, &p)).unwrap();
+        let model = histogram.clone().finalize().unwrap();
+        assert!(model.c_freq(0) > model.c_freq(1));
+        assert_eq!(histogram.into_pdf().freq(1), 0.5);
+        // 負の頻度，2次元，途切れたデータ
+        let negative: Vec<u8> = [-1i16, 3].iter().flat_map(|c| c.to_le_bytes()).collect();
+        assert!(Histogram::from_npy(&npy("<i2", 2, &negative)).is_err());
+        let mut two_d = npy("<u8", 256, &counts);
+        let at = two_d.windows(6).position(|w| w == b"(256,)").unwrap();
+        two_d[at..at + 6].copy_from_slice(b"(16,16");
+        assert!(matches!(
+            Histogram::from_npy(&two_d),
+            Err(Error::InvalidNpy(_))
+        ));
+        assert!(Histogram::from_npy(&npy("<u8", 256, &counts[..100])).is_err());
+        // 大きさが0や8より大きい型，複数バイトの文字の型
+        for descr in &["<u0", "<u16", "<é8", "<", "|b1"] {
+            assert!(matches!(
+                Histogram::from_npy(&npy(descr, 256, &counts)),
+                Err(Error::InvalidNpy("unknown dtype"))
+            ));
+        }
+    }
+}
@@ -0,0 +1,167 @@
+//! モデルの図示 (feature = "plot")
+//! 混合分布の確率，量子化した確率と，標本のヒストグラムを重ねて描き，モデルの当てはまりを目で確かめる
+//! 拡張子が.svgならSVG，それ以外ならPNGなどのビットマップで書き出す: PDFSet::plot_model
+//! 描く値は量子化の前後の確率で，どちらも合計が1になる
+
+use crate::{quantize, Error, PDFSet, Result, PDF};
+use plotters::prelude::*;
+use range_coder::pmodel::PModel;
+use std::path::Path;
+
+/// 画像の大きさ
+const SIZE: (u32, u32) = (1024, 512);
+
+/// 描く系列
+#[derive(Debug)]
+struct Series {
+    mixture: Vec<f64>,
+    quantized: Vec<f64>,
+    histogram: Option<Vec<f64>>,
+}
+impl Series {
+    fn max(&self) -> f64 {
+        let histogram = self.histogram.iter().flatten();
+        self.mixture
+            .iter()
+            .chain(&self.quantized)
+            .chain(histogram)
+            .fold(0.0, |a, b| a.max(*b))
+    }
+}
+
+impl<T: PDF> PDFSet<T> {
+    /// draw the mixture, the quantized probabilities of `finalize`, and the normalized `histogram`
+    /// of samples if given, to an SVG file if `path` ends with `.svg`, or a bitmap otherwise.
+    /// fails as `try_finalize` does, or if the histogram is empty or the image can not be written.
+    pub fn plot_model<P: AsRef<Path>>(&self, path: P, histogram: Option<&[u64]>) -> Result<()> {
+        let series = self.plot_series(histogram)?;
+        let path = path.as_ref();
+        let plot_error = |e: &dyn std::fmt::Display| Error::Plot(e.to_string());
+        if path.extension() == Some("svg".as_ref()) {
+            draw(SVGBackend::new(path, SIZE).into_drawing_area(), &series)
+                .map_err(|e| plot_error(&e))
+        } else {
+            draw(BitMapBackend::new(path, SIZE).into_drawing_area(), &series)
+                .map_err(|e| plot_error(&e))
+        }
+    }
+    fn plot_series(&self, histogram: Option<&[u64]>) -> Result<Series> {
+        if self.is_empty() {
+            return Err(Error::EmptySet);
+        }
+        let (freq_src, tot_freq) = self.checked_mixture()?;
+        if !(tot_freq.is_finite() && tot_freq > 0.0) {
+            return Err(Error::InvalidMass(tot_freq));
+        }
+        let model = quantize(&freq_src, tot_freq);
+        let total = model.total_freq() as f64;
+        let histogram = match histogram {
+            Some(counts) => {
+                let n: u64 = counts.iter().sum();
+                if n == 0 {
+                    return Err(Error::InvalidMass(0.0));
+                }
+                Some(counts.iter().map(|c| *c as f64 / n as f64).collect())
+            }
+            None => None,
+        };
+        Ok(Series {
+            mixture: freq_src.iter().map(|f| f / tot_freq).collect(),
+            quantized: (0..model.alphabet_size())
+                .map(|s| model.c_freq(s) as f64 / total)
+                .collect(),
+            histogram,
+        })
+    }
+}
+
+/// 量子化した確率を棒で，混合分布を線で，ヒストグラムを点で描く
+fn draw<DB: DrawingBackend>(
+    root: DrawingArea<DB, Shift>,
+    series: &Series,
+) -> std::result::Result<(), DrawingAreaErrorKind<DB::ErrorType>> {
+    root.fill(&WHITE)?;
+    let mut chart = ChartBuilder::on(&root)
+        .margin(10)
+        .x_label_area_size(30)
+        .y_label_area_size(60)
+        .build_cartesian_2d(
+            0f64..series.quantized.len() as f64,
+            0f64..series.max() * 1.1,
+        )?;
+    chart
+        .configure_mesh()
+        .x_desc("symbol")
+        .y_desc("probability")
+        .draw()?;
+    let bar = BLUE.mix(0.4).filled();
+    chart
+        .draw_series(
+            series
+                .quantized
+                .iter()
+                .enumerate()
+                .map(|(v, p)| Rectangle::new([(v as f64, 0.0), (v as f64 + 1.0, *p)], bar)),
+        )?
+        .label("quantized")
+        .legend(move |(x, y)| Rectangle::new([(x, y - 5), (x + 10, y + 5)], bar));
+    let centers = |p: &[f64]| -> Vec<(f64, f64)> {
+        p.iter()
+            .enumerate()
+            .map(|(v, p)| (v as f64 + 0.5, *p))
+            .collect()
+    };
+    chart
+        .draw_series(LineSeries::new(centers(&series.mixture), RED))?
+        .label("mixture")
+        .legend(|(x, y)| PathElement::new(vec![(x, y), (x + 10, y)], RED));
+    if let Some(histogram) = &series.histogram {
+        chart
+            .draw_series(
+                centers(histogram)
+                    .into_iter()
+                    .map(|c| Circle::new(c, 2, BLACK.filled())),
+            )?
+            .label("histogram")
+            .legend(|(x, y)| Circle::new((x + 5, y), 2, BLACK.filled()));
+    }
+    chart
+        .configure_series_labels()
+        .background_style(WHITE.mix(0.8))
+        .border_style(BLACK)
+        .draw()?;
+    root.present()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::dist::Gaussian;
+    use crate::{Error, PDFSet};
+    #[test]
+    fn plotted_series() {
+        let set = PDFSet::new(vec![
+            Gaussian::new(1.0, 60.0, 8.0),
+            Gaussian::new(0.5, 180.0, 20.0),
+        ]);
+        let counts: Vec<u64> = (0..256).map(|v| if v == 60 { 9 } else { 1 }).collect();
+        let series = set.plot_series(Some(&counts)).unwrap();
+        for p in [
+            &series.mixture,
+            &series.quantized,
+            series.histogram.as_ref().unwrap(),
+        ]
+        .iter()
+        {
+            assert_eq!(p.len(), 256);
+            assert!((p.iter().sum::<f64>() - 1.0).abs() < 1e-9);
+        }
+        assert!(series.quantized[60] > series.quantized[120]);
+        assert!(matches!(
+            set.plot_series(Some(&[0; 256])),
+            Err(Error::InvalidMass(_))
+        ));
+        let path = std::env::temp_dir().join(format!("plot-model-{}.svg", std::process::id()));
+        set.plot_model(&path, None).unwrap();
+        let _ = std::fs::remove_file(path);
+    }
+}
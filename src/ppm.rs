@@ -0,0 +1,230 @@
+//! PPM（部分一致による予測）
+//! 次数0から最大次数までの文脈で頻度を数え，長い文脈から順に符号化を試みる
+//! 文脈にないシンボルはエスケープを符号化して次数を下げ，
+//! どの文脈にもなければ静的なモデル（QuantizedPDFSet）で符号化する
+//! エスケープの頻度は，文脈に現れたシンボルの種類数とする（PPMC）
+//! PPMによるモデル: PPMModel
+
+use crate::backend::EntropyBackend;
+use crate::codec::{ModelDecoder, ModelEncoder};
+use crate::prelude::*;
+use crate::{QuantizedPDFSet, Result};
+
+#[cfg(not(feature = "std"))]
+use alloc::collections::BTreeMap as HashMap;
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+
+/// 表が確保している項目の数（BTreeMapでは項目の数）
+#[cfg(feature = "std")]
+fn allocated_entries<V>(table: &HashMap<u64, V>) -> usize {
+    table.capacity()
+}
+#[cfg(not(feature = "std"))]
+fn allocated_entries<V>(table: &HashMap<u64, V>) -> usize {
+    table.len()
+}
+
+/// total count of a context at which its counts are halved.
+const CONTEXT_LIMIT: u32 = 1 << 16;
+
+/// 1つの文脈に現れたシンボルとその頻度
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct ContextStats {
+    symbols: Vec<u16>,
+    counts: Vec<u32>,
+}
+impl ContextStats {
+    /// シンボルと頻度の表のバイト数
+    fn heap_bytes(&self) -> usize {
+        self.symbols.capacity() * core::mem::size_of::<u16>()
+            + self.counts.capacity() * core::mem::size_of::<u32>()
+    }
+    /// シンボルの頻度とエスケープの頻度（最後）からなるモデル
+    fn model(&self) -> QuantizedPDFSet {
+        let mut freq = self.counts.clone();
+        freq.push(self.symbols.len() as u32);
+        QuantizedPDFSet::from_freq(freq)
+    }
+    fn update(&mut self, symbol: usize) {
+        match self.symbols.iter().position(|s| *s as usize == symbol) {
+            Some(i) => self.counts[i] += 1,
+            None => {
+                self.symbols.push(symbol as u16);
+                self.counts.push(1);
+            }
+        }
+        if self.counts.iter().sum::<u32>() > CONTEXT_LIMIT {
+            for c in self.counts.iter_mut() {
+                *c = (*c).div_ceil(2);
+            }
+        }
+    }
+}
+
+/// PPM model over orders `0..=max_order`, falling back to a static model.
+#[derive(Debug)]
+pub struct PPMModel {
+    max_order: usize,
+    prior: QuantizedPDFSet,
+    /// 次数毎の，文脈のハッシュから頻度への表
+    contexts: Vec<HashMap<u64, ContextStats>>,
+    /// 直前の最大max_order個のシンボル（古い順）
+    history: Vec<u16>,
+}
+impl PPMModel {
+    /// `prior` codes symbols not seen in any context, and defines the alphabet.
+    pub fn new(max_order: usize, prior: QuantizedPDFSet) -> Self {
+        assert!(prior.alphabet_size() <= u16::MAX as usize + 1);
+        Self {
+            max_order,
+            prior,
+            contexts: vec![HashMap::new(); max_order + 1],
+            history: Vec::with_capacity(max_order),
+        }
+    }
+    pub fn max_order(&self) -> usize {
+        self.max_order
+    }
+    /// number of contexts seen so far in each order.
+    pub fn context_counts(&self) -> Vec<usize> {
+        self.contexts.iter().map(|c| c.len()).collect()
+    }
+    /// approximate bytes used by the prior and the statistics of all contexts.
+    /// entries of the hash tables are counted without the overhead of the tables themselves.
+    pub fn memory_usage(&self) -> usize {
+        let entry = core::mem::size_of::<(u64, ContextStats)>();
+        let contexts: usize = self
+            .contexts
+            .iter()
+            .map(|c| {
+                allocated_entries(c) * entry + c.values().map(|s| s.heap_bytes()).sum::<usize>()
+            })
+            .sum();
+        core::mem::size_of::<Self>()
+            + self.prior.memory_usage()
+            + contexts
+            + self.history.capacity() * core::mem::size_of::<u16>()
+    }
+    /// go back to the state at the start of a message, keeping what was learned.
+    pub fn reset_context(&mut self) {
+        self.history.clear();
+    }
+    pub fn encode<B: EntropyBackend>(&mut self, encoder: &mut ModelEncoder<B>, symbol: usize) {
+        for order in self.orders() {
+            if let Some(stats) = self.contexts[order].get(&self.key(order)) {
+                let model = stats.model();
+                match stats.symbols.iter().position(|s| *s as usize == symbol) {
+                    Some(i) => {
+                        encoder.encode(&model, i);
+                        self.update(symbol);
+                        return;
+                    }
+                    None => encoder.encode(&model, stats.symbols.len()),
+                }
+            }
+        }
+        encoder.encode(&self.prior, symbol);
+        self.update(symbol);
+    }
+    pub fn decode<B: EntropyBackend>(&mut self, decoder: &mut ModelDecoder<B>) -> usize {
+        self.decode_with(|model| Ok(decoder.decode(model))).unwrap()
+    }
+    /// `decode`, failing on corrupt or truncated data.
+    pub fn try_decode<B: EntropyBackend>(
+        &mut self,
+        decoder: &mut ModelDecoder<B>,
+    ) -> Result<usize> {
+        self.decode_with(|model| decoder.try_decode(model))
+    }
+    fn decode_with<F>(&mut self, mut decode: F) -> Result<usize>
+    where
+        F: FnMut(&QuantizedPDFSet) -> Result<usize>,
+    {
+        for order in self.orders() {
+            if let Some(stats) = self.contexts[order].get(&self.key(order)) {
+                let i = decode(&stats.model())?;
+                if i < stats.symbols.len() {
+                    let symbol = stats.symbols[i] as usize;
+                    self.update(symbol);
+                    return Ok(symbol);
+                }
+            }
+        }
+        let symbol = decode(&self.prior)?;
+        self.update(symbol);
+        Ok(symbol)
+    }
+    /// learn from a symbol without coding it.
+    pub fn update(&mut self, symbol: usize) {
+        for order in 0..=self.history.len().min(self.max_order) {
+            let key = self.key(order);
+            self.contexts[order].entry(key).or_default().update(symbol);
+        }
+        if self.history.len() == self.max_order {
+            if self.max_order == 0 {
+                return;
+            }
+            self.history.remove(0);
+        }
+        self.history.push(symbol as u16);
+    }
+    /// 使える次数（長い順）
+    fn orders(&self) -> core::iter::Rev<core::ops::RangeInclusive<usize>> {
+        (0..=self.history.len().min(self.max_order)).rev()
+    }
+    /// 直前のorder個のシンボルのハッシュ（FNV-1a）
+    fn key(&self, order: usize) -> u64 {
+        self.history[self.history.len() - order..]
+            .iter()
+            .fold(0xcbf2_9ce4_8422_2325u64, |h, s| {
+                (h ^ *s as u64).wrapping_mul(0x0100_0000_01b3)
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::simple_pmodel;
+    fn roundtrip(max_order: usize, symbols: &[usize]) -> usize {
+        let mut model = PPMModel::new(max_order, simple_pmodel());
+        let mut encoder = ModelEncoder::new();
+        for s in symbols {
+            model.encode(&mut encoder, *s);
+        }
+        let data = encoder.finish();
+        let len = data.len();
+        let mut model = PPMModel::new(max_order, simple_pmodel());
+        let mut decoder = ModelDecoder::new(data);
+        for s in symbols {
+            assert_eq!(model.try_decode(&mut decoder).unwrap(), *s);
+        }
+        len
+    }
+    #[test]
+    fn higher_orders_compress_text() {
+        let text = b"she sells sea shells by the sea shore. ".repeat(20);
+        let symbols: Vec<usize> = text.iter().map(|b| *b as usize).collect();
+        let order0 = roundtrip(0, &symbols);
+        let order3 = roundtrip(3, &symbols);
+        assert!(order3 < order0 / 2, "{} {}", order3, order0);
+        assert!(order3 < symbols.len() / 6);
+    }
+    #[test]
+    fn context_counts() {
+        let mut model = PPMModel::new(2, simple_pmodel());
+        for s in &[1, 2, 1, 2] {
+            model.update(*s);
+        }
+        // 次数0: 空の文脈，次数1: 1と2，次数2: (1,2)と(2,1)
+        assert_eq!(model.context_counts(), vec![1, 2, 2]);
+        // 文脈が増えるとメモリも増える
+        let before = model.memory_usage();
+        assert!(before > simple_pmodel().memory_usage());
+        for s in 0..50 {
+            model.update(s);
+        }
+        assert!(model.memory_usage() > before);
+    }
+}
@@ -0,0 +1,208 @@
+//! protobufの形式でのモデルとモデルバンクの直列化 (feature = "protobuf")
+//! スキーマは proto/mgf_rangecoding.proto にあり，SCHEMAとしても使える
+//! 他の言語のサービスは，スキーマから生成したコードで，符号化に使うのと同じ頻度表を作り，読める
+//! 依存を増やさないために，スキーマの3つのメッセージの符号化/復号をここで行う
+//! モデル: QuantizedPDFSet::to_protobuf / QuantizedPDFSet::from_protobuf
+//! モデルバンク: ModelBank::to_protobuf / ModelBank::from_protobuf
+//! 読むときは，packedでない繰り返しと未知のフィールドも受け付ける
+
+use crate::bank::ModelBank;
+use crate::prelude::*;
+use crate::{Error, QuantizedPDFSet, RawModel, Result, QUANTIZER_VERSION};
+use core::convert::TryFrom;
+
+/// the protobuf schema of the messages, `proto/mgf_rangecoding.proto`.
+pub const SCHEMA: &str = include_str!("../proto/mgf_rangecoding.proto");
+
+const VARINT: u8 = 0;
+const I64: u8 = 1;
+const LEN: u8 = 2;
+const I32: u8 = 5;
+
+impl QuantizedPDFSet {
+    /// the table as a `Model` message of `SCHEMA`.
+    pub fn to_protobuf(&self) -> Vec<u8> {
+        let mut freq = Vec::with_capacity(5 * self.freq.len());
+        for f in &self.freq {
+            put_varint(&mut freq, *f as u64);
+        }
+        let mut bytes = Vec::with_capacity(freq.len() + 8);
+        put_len(&mut bytes, 1, &freq);
+        put_key(&mut bytes, 2, VARINT);
+        put_varint(&mut bytes, QUANTIZER_VERSION as u64);
+        bytes
+    }
+    /// load a `Model` message, checking the table as `RawModel::validate`.
+    pub fn from_protobuf(bytes: &[u8]) -> Result<Self> {
+        let mut freq = Vec::new();
+        let mut reader = Reader::new(bytes);
+        while let Some((field, wire)) = reader.key()? {
+            match (field, wire) {
+                (1, LEN) => {
+                    let mut packed = Reader::new(reader.delimited()?);
+                    while !packed.is_empty() {
+                        freq.push(to_u32(packed.varint()?)?);
+                    }
+                }
+                (1, VARINT) => freq.push(to_u32(reader.varint()?)?),
+                _ => reader.skip(wire)?,
+            }
+        }
+        RawModel::from_freq(freq).validate()
+    }
+}
+impl ModelBank {
+    /// the bank as a `ModelBank` message of `SCHEMA`.
+    pub fn to_protobuf(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for id in 0..self.len() {
+            let name = self.name(id).unwrap();
+            let mut named = Vec::new();
+            put_len(&mut named, 1, name.as_bytes());
+            put_len(&mut named, 2, &self.get(id).unwrap().to_protobuf());
+            put_len(&mut bytes, 1, &named);
+        }
+        bytes
+    }
+    /// load a `ModelBank` message. a `NamedModel` without a model is an error.
+    pub fn from_protobuf(bytes: &[u8]) -> Result<Self> {
+        let mut bank = Self::new();
+        let mut reader = Reader::new(bytes);
+        while let Some((field, wire)) = reader.key()? {
+            if (field, wire) != (1, LEN) {
+                reader.skip(wire)?;
+                continue;
+            }
+            let mut named = Reader::new(reader.delimited()?);
+            let (mut name, mut model) = ("", None);
+            while let Some((field, wire)) = named.key()? {
+                match (field, wire) {
+                    (1, LEN) => {
+                        name = core::str::from_utf8(named.delimited()?)
+                            .map_err(|_| Error::InvalidProtobuf("name is not UTF-8"))?
+                    }
+                    (2, LEN) => model = Some(QuantizedPDFSet::from_protobuf(named.delimited()?)?),
+                    _ => named.skip(wire)?,
+                }
+            }
+            let model = model.ok_or(Error::InvalidProtobuf("named model without a model"))?;
+            bank.add(name, model)?;
+        }
+        Ok(bank)
+    }
+}
+
+fn to_u32(value: u64) -> Result<u32> {
+    if value > u32::MAX as u64 {
+        return Err(Error::InvalidProtobuf("frequency overflows uint32"));
+    }
+    Ok(value as u32)
+}
+fn put_varint(bytes: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        bytes.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    bytes.push(value as u8);
+}
+fn put_key(bytes: &mut Vec<u8>, field: u32, wire: u8) {
+    put_varint(bytes, (field as u64) << 3 | wire as u64);
+}
+/// 長さを前に置くフィールド
+fn put_len(bytes: &mut Vec<u8>, field: u32, value: &[u8]) {
+    put_key(bytes, field, LEN);
+    put_varint(bytes, value.len() as u64);
+    bytes.extend_from_slice(value);
+}
+
+/// メッセージのフィールドを順に読む
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Self { bytes }
+    }
+    fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0u64;
+        for i in 0..10 {
+            let (byte, rest) = self.bytes.split_first().ok_or(Error::UnexpectedEof)?;
+            self.bytes = rest;
+            value |= ((byte & 0x7f) as u64) << (7 * i);
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(Error::InvalidProtobuf("varint longer than 10 bytes"))
+    }
+    /// 次のフィールドの番号とワイヤ型．メッセージの終わりならNone
+    fn key(&mut self) -> Result<Option<(u32, u8)>> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+        let key = self.varint()?;
+        let field = u32::try_from(key >> 3)
+            .ok()
+            .filter(|f| *f > 0)
+            .ok_or(Error::InvalidProtobuf("invalid field number"))?;
+        Ok(Some((field, (key & 7) as u8)))
+    }
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() < len {
+            return Err(Error::UnexpectedEof);
+        }
+        let (value, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(value)
+    }
+    /// 長さを前に置くフィールドの中身
+    fn delimited(&mut self) -> Result<&'a [u8]> {
+        let len = self.varint()?;
+        self.take(usize::try_from(len).map_err(|_| Error::UnexpectedEof)?)
+    }
+    fn skip(&mut self, wire: u8) -> Result<()> {
+        match wire {
+            VARINT => self.varint().map(drop),
+            I64 => self.take(8).map(drop),
+            LEN => self.delimited().map(drop),
+            I32 => self.take(4).map(drop),
+            _ => Err(Error::InvalidProtobuf("unsupported wire type")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::simple_pmodel;
+    #[test]
+    fn model_and_bank_messages() {
+        let pm = simple_pmodel().with_eof();
+        let bytes = pm.to_protobuf();
+        // packedのfreq（フィールド1，LEN）から始まる
+        assert_eq!(bytes[0], 1 << 3 | LEN);
+        let loaded = QuantizedPDFSet::from_protobuf(&bytes).unwrap();
+        assert_eq!(loaded.to_bytes(), pm.to_bytes());
+        // packedでない繰り返しと未知のフィールド
+        let mut unpacked = Vec::new();
+        put_key(&mut unpacked, 9, I32);
+        unpacked.extend_from_slice(&[0; 4]);
+        for f in &pm.freq {
+            put_key(&mut unpacked, 1, VARINT);
+            put_varint(&mut unpacked, *f as u64);
+        }
+        let loaded = QuantizedPDFSet::from_protobuf(&unpacked).unwrap();
+        assert_eq!(loaded.to_bytes(), pm.to_bytes());
+        assert!(QuantizedPDFSet::from_protobuf(&bytes[..bytes.len() / 2]).is_err());
+
+        let mut bank = ModelBank::new();
+        bank.add("mixture", simple_pmodel()).unwrap();
+        bank.add("uniform", QuantizedPDFSet::uniform()).unwrap();
+        let loaded = ModelBank::from_protobuf(&bank.to_protobuf()).unwrap();
+        assert_eq!(loaded.to_bytes(), bank.to_bytes());
+        assert!(SCHEMA.contains("repeated uint32 freq = 1;"));
+    }
+}
@@ -0,0 +1,189 @@
+//! Pythonからの利用 (feature = "python")
+//! Pythonのパッケージ mgf_rangecoding の中身で，maturin で作る（pyproject.toml）
+//! Pythonで試したモデルを，同じRustの量子化で使うためのもの
+//! 成分のパラメータはnumpyの配列で渡し，シンボル列はnumpyのuint16の配列で受け渡す
+//! 成分の集合: PDFSet（Pythonの名前）
+//! 量子化したモデル: Model（Pythonの名前）
+
+use crate::batch::Component;
+use crate::codec::{encode_slice, try_decode_slice};
+use crate::dist::{Gaussian, Laplace};
+use crate::{Error, QuantizedPDFSet, RawModel};
+use numpy::{PyArray1, PyReadonlyArray1};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+
+fn value_error(e: Error) -> PyErr {
+    PyValueError::new_err(e.to_string())
+}
+
+/// components of a mixture, finalized into a `Model`.
+#[pyclass(name = "PDFSet")]
+#[derive(Debug, Default)]
+pub struct PyPDFSet {
+    components: Vec<Component>,
+}
+impl PyPDFSet {
+    /// パラメータの配列から成分を作って加える
+    fn add(
+        &mut self,
+        weights: &[f64],
+        means: &[f64],
+        scales: &[f64],
+        component: fn(f64, f64, f64) -> Component,
+    ) -> PyResult<()> {
+        if weights.len() != means.len() || weights.len() != scales.len() {
+            return Err(PyValueError::new_err("parameter arrays differ in length"));
+        }
+        if scales.iter().any(|s| !(s.is_finite() && *s > 0.0)) {
+            return Err(PyValueError::new_err(
+                "scale is not a positive finite number",
+            ));
+        }
+        for ((w, m), s) in weights.iter().zip(means).zip(scales) {
+            self.components.push(component(*w, *m, *s));
+        }
+        Ok(())
+    }
+}
+#[pymethods]
+impl PyPDFSet {
+    #[new]
+    fn new() -> Self {
+        Self::default()
+    }
+    /// add gaussians with the weights, means and sigmas of the arrays.
+    fn add_gaussians(
+        &mut self,
+        weights: PyReadonlyArray1<'_, f64>,
+        means: PyReadonlyArray1<'_, f64>,
+        sigmas: PyReadonlyArray1<'_, f64>,
+    ) -> PyResult<()> {
+        self.add(
+            weights.as_slice()?,
+            means.as_slice()?,
+            sigmas.as_slice()?,
+            |w, m, s| Component::Gaussian(Gaussian::new(w, m, s)),
+        )
+    }
+    /// add laplace distributions with the weights, means and scales of the arrays.
+    fn add_laplaces(
+        &mut self,
+        weights: PyReadonlyArray1<'_, f64>,
+        means: PyReadonlyArray1<'_, f64>,
+        scales: PyReadonlyArray1<'_, f64>,
+    ) -> PyResult<()> {
+        self.add(
+            weights.as_slice()?,
+            means.as_slice()?,
+            scales.as_slice()?,
+            |w, m, s| Component::Laplace(Laplace::new(w, m, s)),
+        )
+    }
+    fn __len__(&self) -> usize {
+        self.components.len()
+    }
+    /// quantize as `PDFSet::try_finalize`, raising ValueError when it fails.
+    fn finalize(&self) -> PyResult<PyModel> {
+        let model = crate::PDFSet::new(self.components.clone())
+            .try_finalize()
+            .map_err(value_error)?;
+        Ok(PyModel { model })
+    }
+}
+
+/// a quantized model.
+#[pyclass(name = "Model")]
+#[derive(Debug)]
+pub struct PyModel {
+    model: QuantizedPDFSet,
+}
+#[pymethods]
+impl PyModel {
+    /// model from 256 frequencies, or 257 with the end-of-stream symbol.
+    #[staticmethod]
+    fn from_freq(freq: PyReadonlyArray1<'_, u32>) -> PyResult<Self> {
+        let model = RawModel::from_freq(freq.as_slice()?.to_vec())
+            .validate()
+            .map_err(value_error)?;
+        Ok(Self { model })
+    }
+    /// model serialized by `to_bytes`.
+    #[staticmethod]
+    fn from_bytes(bytes: &[u8]) -> PyResult<Self> {
+        let (model, _) = QuantizedPDFSet::from_bytes(bytes).map_err(value_error)?;
+        Ok(Self { model })
+    }
+    fn to_bytes<'py>(&self, py: Python<'py>) -> &'py PyBytes {
+        PyBytes::new(py, &self.model.to_bytes())
+    }
+    #[getter]
+    fn alphabet_size(&self) -> usize {
+        self.model.alphabet_size()
+    }
+    fn encode<'py>(
+        &self,
+        py: Python<'py>,
+        symbols: PyReadonlyArray1<'_, u16>,
+    ) -> PyResult<&'py PyBytes> {
+        let symbols = self.symbols(symbols.as_slice()?)?;
+        Ok(PyBytes::new(py, &encode_slice(&self.model, &symbols)))
+    }
+    /// decode `len` symbols.
+    fn decode<'py>(
+        &self,
+        py: Python<'py>,
+        data: &[u8],
+        len: usize,
+    ) -> PyResult<&'py PyArray1<u16>> {
+        let symbols = try_decode_slice(&self.model, data.to_vec(), len).map_err(value_error)?;
+        Ok(PyArray1::from_vec(
+            py,
+            symbols.into_iter().map(|s| s as u16).collect(),
+        ))
+    }
+}
+impl PyModel {
+    /// アルファベットの外のシンボルがあれば失敗する
+    fn symbols(&self, symbols: &[u16]) -> PyResult<Vec<usize>> {
+        let alphabet_size = self.model.alphabet_size();
+        match symbols.iter().find(|s| **s as usize >= alphabet_size) {
+            Some(symbol) => Err(value_error(Error::SymbolOutOfRange {
+                symbol: *symbol as usize,
+                alphabet_size,
+            })),
+            None => Ok(symbols.iter().map(|s| *s as usize).collect()),
+        }
+    }
+}
+
+/// the `mgf_rangecoding` Python module.
+#[pymodule]
+fn mgf_rangecoding(_py: Python<'_>, m: &PyModule) -> PyResult<()> {
+    m.add_class::<PyPDFSet>()?;
+    m.add_class::<PyModel>()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn same_model_as_rust() {
+        let mut set = PyPDFSet::new();
+        let gaussian = |w, m, s| Component::Gaussian(Gaussian::new(w, m, s));
+        set.add(&[1.0, 0.5], &[40.0, 200.0], &[3.0, 10.0], gaussian)
+            .unwrap();
+        assert!(set.add(&[1.0], &[], &[1.0], gaussian).is_err());
+        assert!(set.add(&[1.0], &[1.0], &[0.0], gaussian).is_err());
+        let model = set.finalize().unwrap();
+        let expected = crate::PDFSet::new(vec![
+            Gaussian::new(1.0, 40.0, 3.0),
+            Gaussian::new(0.5, 200.0, 10.0),
+        ])
+        .finalize();
+        assert_eq!(model.model.to_bytes(), expected.to_bytes());
+        assert!(model.symbols(&[40, 256]).is_err());
+    }
+}
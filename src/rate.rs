@@ -0,0 +1,135 @@
+//! 符号化せずに符号量を見積もる
+//! シンボル毎の情報量 -log2(p) を積算する: RateEstimator
+//! 符号化器の統計: EncoderStats
+
+use crate::prelude::*;
+use range_coder::pmodel::PModel;
+
+/// information of the symbol under the model, `-log2(p)` in bits.
+pub fn symbol_bits<M: PModel>(model: &M, symbol: usize) -> f64 {
+    (model.total_freq() as f64 / model.c_freq(symbol) as f64).log2()
+}
+
+/// accumulates the ideal code length of coded symbols.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct RateEstimator {
+    bits: f64,
+    symbols: usize,
+}
+impl RateEstimator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// account one symbol coded with the model.
+    pub fn add<M: PModel>(&mut self, model: &M, symbol: usize) {
+        self.bits += symbol_bits(model, symbol);
+        self.symbols += 1;
+    }
+    pub fn add_slice<M: PModel>(&mut self, model: &M, symbols: &[usize]) {
+        for symbol in symbols {
+            self.add(model, *symbol);
+        }
+    }
+    /// estimated bits so far.
+    pub fn bits(&self) -> f64 {
+        self.bits
+    }
+    /// estimated bytes so far, rounded up.
+    pub fn bytes(&self) -> usize {
+        (self.bits / 8.0).ceil() as usize
+    }
+    /// number of symbols accounted so far.
+    pub fn symbols(&self) -> usize {
+        self.symbols
+    }
+    pub fn reset(&mut self) {
+        *self = Self::default();
+    }
+}
+
+/// statistics of an encoder: what the coded symbols cost compared to their entropy.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncoderStats {
+    model: RateEstimator,
+    output_bits: usize,
+    histogram: Vec<usize>,
+}
+impl EncoderStats {
+    pub fn new() -> Self {
+        Self {
+            model: RateEstimator::new(),
+            output_bits: 0,
+            histogram: Vec::new(),
+        }
+    }
+    pub(crate) fn record<M: PModel>(&mut self, model: &M, symbol: usize) {
+        self.model.add(model, symbol);
+        if self.histogram.len() <= symbol {
+            self.histogram.resize(symbol + 1, 0);
+        }
+        self.histogram[symbol] += 1;
+    }
+    pub(crate) fn record_output(&mut self, bytes: usize) {
+        self.output_bits += bytes * 8;
+    }
+    /// number of coded symbols.
+    pub fn symbols(&self) -> usize {
+        self.model.symbols()
+    }
+    /// bits actually output by the coder (flushed messages only).
+    pub fn output_bits(&self) -> usize {
+        self.output_bits
+    }
+    /// ideal code length under the models used, `sum of -log2(p)`.
+    pub fn model_bits(&self) -> f64 {
+        self.model.bits()
+    }
+    /// empirical (order-0) entropy of the coded symbols times their count.
+    pub fn entropy_bits(&self) -> f64 {
+        let n = self.symbols() as f64;
+        self.histogram
+            .iter()
+            .filter(|c| **c > 0)
+            .map(|c| *c as f64 * (n / *c as f64).log2())
+            .sum()
+    }
+    /// output bits per symbol above the empirical entropy.
+    pub fn redundancy_per_symbol(&self) -> f64 {
+        if self.symbols() == 0 {
+            return 0.0;
+        }
+        (self.output_bits as f64 - self.entropy_bits()) / self.symbols() as f64
+    }
+    /// bits per symbol lost by the model (quantization floor and mismatch to the data).
+    pub fn model_redundancy_per_symbol(&self) -> f64 {
+        if self.symbols() == 0 {
+            return 0.0;
+        }
+        (self.model_bits() - self.entropy_bits()) / self.symbols() as f64
+    }
+}
+impl Default for EncoderStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::encode_slice;
+    use crate::test_util::simple_pmodel;
+    #[test]
+    fn estimate_close_to_actual() {
+        let pm = simple_pmodel();
+        let symbols: Vec<usize> = (0..1000).map(|i| [128, 127, 129, 30, 70][i % 5]).collect();
+        let estimated = pm.estimate_bits(&symbols);
+        let actual = encode_slice(&pm, &symbols).len() as f64 * 8.0;
+        assert!(estimated <= actual);
+        assert!(actual - estimated < 256.0);
+        let mut estimator = RateEstimator::new();
+        estimator.add_slice(&pm, &symbols);
+        assert_eq!(estimator.bits(), estimated);
+        assert_eq!(estimator.symbols(), symbols.len());
+    }
+}
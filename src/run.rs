@@ -0,0 +1,137 @@
+//! 直前と同じシンボルの連続（ラン）を予測するモデル
+//! 「直前と同じか」を適応的な二値のモデルで符号化し，違うときだけ主のモデルで符号化する
+//! 二値のモデルは，それまでのランの長さで使い分ける
+//! ランを予測するモデル: RunModel
+
+use crate::adaptive::AdaptiveModel;
+use crate::backend::EntropyBackend;
+use crate::codec::{ModelDecoder, ModelEncoder};
+use crate::prelude::*;
+use crate::Result;
+use range_coder::pmodel::PModel;
+
+/// 「同じ」を表す二値のシンボル
+const SAME: usize = 1;
+const DIFFERENT: usize = 0;
+/// ランの長さの区切り
+const RUN_BUCKETS: [usize; 4] = [1, 2, 4, 8];
+
+/// predicts "same as the previous symbol" with adaptive probabilities,
+/// and falls back to the main model given on each call otherwise.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RunModel {
+    /// ランの長さ毎の，「同じ」の二値のモデル
+    flags: Vec<AdaptiveModel>,
+    previous: Option<usize>,
+    run: usize,
+}
+impl RunModel {
+    pub fn new() -> Self {
+        Self {
+            flags: vec![AdaptiveModel::new(2).with_limit(1 << 10); RUN_BUCKETS.len() + 1],
+            previous: None,
+            run: 0,
+        }
+    }
+    /// length of the current run of the previous symbol.
+    pub fn run_length(&self) -> usize {
+        self.run
+    }
+    /// go back to the state at the start of a message, keeping what was learned.
+    pub fn reset_context(&mut self) {
+        self.previous = None;
+        self.run = 0;
+    }
+    pub fn encode<B: EntropyBackend, M: PModel>(
+        &mut self,
+        encoder: &mut ModelEncoder<B>,
+        main: &M,
+        symbol: usize,
+    ) {
+        if let Some(previous) = self.previous {
+            let same = symbol == previous;
+            let bucket = self.bucket();
+            encoder.encode_adaptive(&mut self.flags[bucket], if same { SAME } else { DIFFERENT });
+            if same {
+                self.push(symbol);
+                return;
+            }
+        }
+        encoder.encode(main, symbol);
+        self.push(symbol);
+    }
+    pub fn decode<B: EntropyBackend, M: PModel>(
+        &mut self,
+        decoder: &mut ModelDecoder<B>,
+        main: &M,
+    ) -> usize {
+        if let Some(previous) = self.previous {
+            let bucket = self.bucket();
+            if decoder.decode_adaptive(&mut self.flags[bucket]) == SAME {
+                self.push(previous);
+                return previous;
+            }
+        }
+        let symbol = decoder.decode(main);
+        self.push(symbol);
+        symbol
+    }
+    /// `decode`, failing on corrupt or truncated data.
+    pub fn try_decode<B: EntropyBackend, M: PModel>(
+        &mut self,
+        decoder: &mut ModelDecoder<B>,
+        main: &M,
+    ) -> Result<usize> {
+        if let Some(previous) = self.previous {
+            let bucket = self.bucket();
+            if decoder.try_decode_adaptive(&mut self.flags[bucket])? == SAME {
+                self.push(previous);
+                return Ok(previous);
+            }
+        }
+        let symbol = decoder.try_decode(main)?;
+        self.push(symbol);
+        Ok(symbol)
+    }
+    fn bucket(&self) -> usize {
+        RUN_BUCKETS.partition_point(|b| *b <= self.run)
+    }
+    fn push(&mut self, symbol: usize) {
+        if self.previous == Some(symbol) {
+            self.run += 1;
+        } else {
+            self.previous = Some(symbol);
+            self.run = 1;
+        }
+    }
+}
+impl Default for RunModel {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::simple_pmodel;
+    #[test]
+    fn runs_are_cheap() {
+        let main = simple_pmodel();
+        // パレット画像のような長いラン
+        let symbols: Vec<usize> = (0..4000).map(|i| [128, 30, 200][(i / 97) % 3]).collect();
+        let mut model = RunModel::new();
+        let mut encoder = ModelEncoder::new();
+        for s in &symbols {
+            model.encode(&mut encoder, &main, *s);
+        }
+        let data = encoder.finish();
+        assert!(data.len() * 8 * 10 < main.estimate_bits(&symbols) as usize);
+        let mut model = RunModel::new();
+        let mut decoder = ModelDecoder::new(data);
+        for s in &symbols {
+            assert_eq!(model.try_decode(&mut decoder, &main).unwrap(), *s);
+        }
+        assert_eq!(model.run_length(), 4000 % 97);
+    }
+}
@@ -0,0 +1,45 @@
+//! モデルからの乱数のシンボルの生成 (feature = "rand")
+//! QuantizedPDFSetはrandのDistribution<usize>を実装し，符号化と同じ量子化した確率でシンボルを生成する
+//! シミュレーションや，モデルに従うテストデータの生成に使う
+//! 全体の頻度の中の一様な乱数を，復号と同じ探索でシンボルにする
+
+use crate::QuantizedPDFSet;
+use rand::distributions::Distribution;
+use rand::Rng;
+use range_coder::pmodel::PModel;
+
+impl Distribution<usize> for QuantizedPDFSet {
+    /// a symbol with the probability `c_freq(symbol) / total_freq()`.
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> usize {
+        self.index_of(rng.gen_range(0..self.total_freq()) as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::codec::{decode_slice, encode_slice};
+    use crate::test_util::simple_pmodel;
+    use rand::distributions::Distribution;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+    use range_coder::pmodel::PModel;
+    #[test]
+    fn sampled_symbols() {
+        let pm = simple_pmodel().with_eof().with_decode_table(8);
+        let symbols: Vec<usize> = (&pm)
+            .sample_iter(StdRng::seed_from_u64(7))
+            .take(100_000)
+            .collect();
+        let mut counts = vec![0usize; pm.alphabet_size()];
+        for s in &symbols {
+            counts[*s] += 1;
+        }
+        for s in [0, 30, 70, 128, 256].iter() {
+            let expected = pm.c_freq(*s) as f64 / pm.total_freq() as f64;
+            let sampled = counts[*s] as f64 / symbols.len() as f64;
+            assert!((sampled - expected).abs() < 0.01, "symbol {}", s);
+        }
+        let data = encode_slice(&pm, &symbols[..1000]);
+        assert_eq!(decode_slice(&pm, data, 1000), &symbols[..1000]);
+    }
+}
@@ -0,0 +1,244 @@
+//! 同期点を挿入した，途中から復号できるストリーム
+//! 一定のシンボル数毎に符号化器をフラッシュし，その位置を索引に記録する
+//! ストリーム: ヘッダ（container::Header） | 間隔(u32 LE) | シンボル数(u64 LE) | 区間数(u32 LE)
+//!             | 各区間の開始位置(u32 LE)... | 区間...
+//! 区間: スナップショットのバイト数(u32 LE) | スナップショット | 符号化したバイト数(u32 LE) | 符号化したバイト列
+
+use crate::codec::{ModelDecoder, ModelEncoder};
+use crate::container::Header;
+use crate::prelude::*;
+use crate::{Error, Result};
+use core::convert::TryFrom;
+use range_coder::pmodel::PModel;
+
+/// encoder which restarts the coder every `interval` symbols.
+pub struct SeekableEncoder {
+    interval: usize,
+    symbols: usize,
+    encoder: ModelEncoder,
+    snapshot: Vec<u8>,
+    /// 区間毎のスナップショットと符号化したバイト列
+    segments: Vec<(Vec<u8>, Vec<u8>)>,
+}
+impl SeekableEncoder {
+    /// panics if `interval` is zero.
+    pub fn new(interval: usize) -> Self {
+        assert!(interval > 0);
+        Self {
+            interval,
+            symbols: 0,
+            encoder: ModelEncoder::new(),
+            snapshot: Vec::new(),
+            segments: Vec::new(),
+        }
+    }
+    /// whether the next symbol starts a new segment.
+    pub fn is_at_sync_point(&self) -> bool {
+        self.symbols % self.interval == 0
+    }
+    /// attach model state to the segment starting at the next symbol,
+    /// given back by `SeekableDecoder::snapshot` after seeking into it.
+    /// only meaningful at a sync point.
+    pub fn attach_snapshot(&mut self, snapshot: Vec<u8>) {
+        debug_assert!(self.is_at_sync_point());
+        self.snapshot = snapshot;
+    }
+    pub fn encode<M: PModel>(&mut self, model: &M, symbol: usize) {
+        self.encoder.encode(model, symbol);
+        self.symbols += 1;
+        if self.is_at_sync_point() {
+            self.flush_segment();
+        }
+    }
+    fn flush_segment(&mut self) {
+        let data = self.encoder.flush();
+        let snapshot = core::mem::take(&mut self.snapshot);
+        self.segments.push((snapshot, data));
+    }
+    /// fails with `Error::IndexOverflow` when the interval, the number of segments,
+    /// a segment or the offset of a segment does not fit the u32 fields of the format.
+    pub fn finish(mut self) -> Result<Vec<u8>> {
+        if !self.is_at_sync_point() {
+            self.flush_segment();
+        }
+        let mut out = Header::new(0).to_bytes().to_vec();
+        out.extend_from_slice(&index_u32(self.interval)?.to_le_bytes());
+        out.extend_from_slice(&(self.symbols as u64).to_le_bytes());
+        out.extend_from_slice(&index_u32(self.segments.len())?.to_le_bytes());
+        let mut offset = 0u64;
+        for (snapshot, data) in &self.segments {
+            let start = u32::try_from(offset).map_err(|_| Error::IndexOverflow)?;
+            out.extend_from_slice(&start.to_le_bytes());
+            offset += 8 + snapshot.len() as u64 + data.len() as u64;
+        }
+        for (snapshot, data) in self.segments {
+            out.extend_from_slice(&index_u32(snapshot.len())?.to_le_bytes());
+            out.extend(snapshot);
+            out.extend_from_slice(&index_u32(data.len())?.to_le_bytes());
+            out.extend(data);
+        }
+        Ok(out)
+    }
+}
+
+/// decoder of a stream written by `SeekableEncoder`, which can start at any sync point.
+pub struct SeekableDecoder {
+    data: Vec<u8>,
+    interval: usize,
+    symbols: usize,
+    /// 各区間の開始位置（dataの先頭から）
+    offsets: Vec<usize>,
+    position: usize,
+    snapshot: core::ops::Range<usize>,
+    decoder: Option<ModelDecoder>,
+}
+impl SeekableDecoder {
+    pub fn new(data: Vec<u8>) -> Result<Self> {
+        let header = Header::from_bytes(&data)?;
+        if header.flags() != 0 {
+            return Err(Error::UnknownFlags(header.flags()));
+        }
+        let mut pos = Header::SIZE;
+        let interval = read_u32(&data, &mut pos)? as usize;
+        let symbols = read_u64(&data, &mut pos)? as usize;
+        let count = read_u32(&data, &mut pos)? as usize;
+        if interval == 0 || count != symbols.div_ceil(interval) {
+            return Err(Error::InvalidIndex);
+        }
+        let body = pos + 4 * count;
+        let offsets = (0..count)
+            .map(|_| Ok(body + read_u32(&data, &mut pos)? as usize))
+            .collect::<Result<Vec<_>>>()?;
+        if offsets.iter().any(|o| *o > data.len()) {
+            return Err(Error::InvalidIndex);
+        }
+        let mut decoder = Self {
+            data,
+            interval,
+            symbols,
+            offsets,
+            position: 0,
+            snapshot: 0..0,
+            decoder: None,
+        };
+        if symbols > 0 {
+            decoder.open_segment(0)?;
+        }
+        Ok(decoder)
+    }
+    /// number of symbols in the stream.
+    pub fn len(&self) -> usize {
+        self.symbols
+    }
+    pub fn is_empty(&self) -> bool {
+        self.symbols == 0
+    }
+    /// index of the next symbol to decode.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+    /// move to the last sync point at or before `symbol`, and return its symbol index.
+    /// the caller restores its model state from `snapshot` and decodes forward from there.
+    pub fn seek(&mut self, symbol: usize) -> Result<usize> {
+        if symbol >= self.symbols {
+            return Err(Error::PositionOutOfRange {
+                position: symbol,
+                len: self.symbols,
+            });
+        }
+        self.open_segment(symbol / self.interval)?;
+        Ok(self.position)
+    }
+    /// model state attached to the current segment by `SeekableEncoder::attach_snapshot`.
+    pub fn snapshot(&self) -> &[u8] {
+        &self.data[self.snapshot.clone()]
+    }
+    /// decode the next symbol. returns `None` at the end of the stream.
+    pub fn decode<M: PModel>(&mut self, model: &M) -> Result<Option<usize>> {
+        if self.position >= self.symbols {
+            return Ok(None);
+        }
+        if self.position > 0 && self.position % self.interval == 0 && self.decoder.is_none() {
+            self.open_segment(self.position / self.interval)?;
+        }
+        let decoder = self.decoder.as_mut().ok_or(Error::InvalidIndex)?;
+        let symbol = decoder.try_decode(model)?;
+        self.position += 1;
+        if self.position % self.interval == 0 {
+            self.decoder = None;
+        }
+        Ok(Some(symbol))
+    }
+    fn open_segment(&mut self, segment: usize) -> Result<()> {
+        let mut pos = self.offsets[segment];
+        let snapshot_len = read_u32(&self.data, &mut pos)? as usize;
+        if pos + snapshot_len > self.data.len() {
+            return Err(Error::UnexpectedEof);
+        }
+        self.snapshot = pos..pos + snapshot_len;
+        pos += snapshot_len;
+        let len = read_u32(&self.data, &mut pos)? as usize;
+        let coded = self.data.get(pos..pos + len).ok_or(Error::UnexpectedEof)?;
+        self.decoder = Some(ModelDecoder::new(coded.to_vec()));
+        self.position = segment * self.interval;
+        Ok(())
+    }
+}
+
+/// 索引のu32の欄に入れる値（収まらなければ失敗する）
+fn index_u32(value: usize) -> Result<u32> {
+    u32::try_from(value).map_err(|_| Error::IndexOverflow)
+}
+fn read_u32(data: &[u8], pos: &mut usize) -> Result<u32> {
+    let bytes = data.get(*pos..*pos + 4).ok_or(Error::UnexpectedEof)?;
+    *pos += 4;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+fn read_u64(data: &[u8], pos: &mut usize) -> Result<u64> {
+    let low = read_u32(data, pos)? as u64;
+    let high = read_u32(data, pos)? as u64;
+    Ok(low | (high << 32))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::simple_pmodel;
+    #[test]
+    fn seek_and_decode() {
+        let pm = simple_pmodel();
+        let symbols: Vec<usize> = (0..250).map(|i| (i * 13) % 256).collect();
+        let mut encoder = SeekableEncoder::new(64);
+        for (i, s) in symbols.iter().enumerate() {
+            if encoder.is_at_sync_point() {
+                encoder.attach_snapshot(vec![(i / 64) as u8]);
+            }
+            encoder.encode(&pm, *s);
+        }
+        let mut decoder = SeekableDecoder::new(encoder.finish().unwrap()).unwrap();
+        assert_eq!(decoder.len(), symbols.len());
+        let mut all = Vec::new();
+        while let Some(s) = decoder.decode(&pm).unwrap() {
+            all.push(s);
+        }
+        assert_eq!(all, symbols);
+        assert_eq!(decoder.seek(200).unwrap(), 192);
+        assert_eq!(decoder.snapshot(), &[3]);
+        let mut tail = Vec::new();
+        while let Some(s) = decoder.decode(&pm).unwrap() {
+            tail.push(s);
+        }
+        assert_eq!(tail, symbols[192..]);
+        assert_eq!(decoder.seek(70).unwrap(), 64);
+        assert_eq!(decoder.decode(&pm).unwrap(), Some(symbols[64]));
+        assert!(decoder.seek(250).is_err());
+    }
+    #[test]
+    #[cfg(target_pointer_width = "64")]
+    fn index_overflow() {
+        let pm = simple_pmodel();
+        let mut encoder = SeekableEncoder::new(u32::MAX as usize + 1);
+        encoder.encode(&pm, 30);
+        assert!(matches!(encoder.finish(), Err(Error::IndexOverflow)));
+    }
+}
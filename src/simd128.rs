@@ -0,0 +1,89 @@
+//! WebAssemblyのベクトル命令（simd128）による計算
+//! wasm32でtarget_feature = "simd128"を有効にしてビルドしたときだけ使う（RUSTFLAGS="-C target-feature=+simd128"）
+//! 混合分布の行の足し合わせ: add_weighted_row（f64の2値ずつ）
+//! 復号の累積頻度の探索: count_at_most（u32の4値ずつ）
+//! 足し算と掛け算の順序は他の環境と同じなので，作られる表はどの環境でも同じ
+//! feature = "f32" の積和演算はwasmのベクトル命令にないので，こちらは使わない
+
+#[cfg(not(feature = "f32"))]
+use crate::flush_subnormal;
+use core::arch::wasm32::*;
+
+/// 行に重みを掛けて足す．非正規化数の確率と寄与は0にする
+/// weightは非正規化数を0にしたもの
+#[cfg(not(feature = "f32"))]
+pub(crate) fn add_weighted_row(acc: &mut [f64], row: &[f64], weight: f64) {
+    let w = f64x2_splat(weight);
+    let mut acc_chunks = acc.chunks_exact_mut(2);
+    let mut row_chunks = row.chunks_exact(2);
+    for (a, r) in (&mut acc_chunks).zip(&mut row_chunks) {
+        let product = f64x2_mul(w, flush(f64x2(r[0], r[1])));
+        let sum = f64x2_add(f64x2(a[0], a[1]), flush(product));
+        a[0] = f64x2_extract_lane::<0>(sum);
+        a[1] = f64x2_extract_lane::<1>(sum);
+    }
+    let rest = acc_chunks.into_remainder();
+    for (a, r) in rest.iter_mut().zip(row_chunks.remainder()) {
+        *a += flush_subnormal(weight * flush_subnormal(*r));
+    }
+}
+/// 絶対値が最小の正規化数より小さい値を0にする（0はそのまま，NaNは残る）
+#[cfg(not(feature = "f32"))]
+fn flush(x: v128) -> v128 {
+    v128_andnot(x, f64x2_lt(f64x2_abs(x), f64x2_splat(f64::MIN_POSITIVE)))
+}
+/// 累積頻度がrfreq以下の値の数．累積頻度は増加するので，rfreqを含む値は先頭からこの数だけ進んだもの
+pub(crate) fn count_at_most(cum_freq: &[u32], rfreq: u64) -> usize {
+    if rfreq > u32::MAX as u64 {
+        return cum_freq.len();
+    }
+    let r = u32x4_splat(rfreq as u32);
+    // 比較の結果の各レーンは真なら-1なので，引いて数える
+    let mut counts = i32x4_splat(0);
+    let mut chunks = cum_freq.chunks_exact(4);
+    for c in &mut chunks {
+        counts = i32x4_sub(counts, u32x4_le(u32x4(c[0], c[1], c[2], c[3]), r));
+    }
+    let count = i32x4_extract_lane::<0>(counts)
+        + i32x4_extract_lane::<1>(counts)
+        + i32x4_extract_lane::<2>(counts)
+        + i32x4_extract_lane::<3>(counts);
+    count as usize
+        + chunks
+            .remainder()
+            .iter()
+            .filter(|c| **c as u64 <= rfreq)
+            .count()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn same_as_scalar() {
+        #[cfg(not(feature = "f32"))]
+        {
+            let row: Vec<f64> = (0..257)
+                .map(|v| match v % 5 {
+                    0 => 1e-310,
+                    1 => f64::NAN,
+                    2 => 0.0,
+                    _ => v as f64 * 0.25,
+                })
+                .collect();
+            for weight in [0.5, 1e-300].iter() {
+                let mut acc = vec![1.0; 257];
+                add_weighted_row(&mut acc, &row, *weight);
+                for (a, r) in acc.iter().zip(&row) {
+                    let expected = 1.0 + flush_subnormal(weight * flush_subnormal(*r));
+                    assert!(a.to_bits() == expected.to_bits() || (a.is_nan() && expected.is_nan()));
+                }
+            }
+        }
+        let cum_freq: Vec<u32> = (0..19).map(|i| i * 10).collect();
+        for rfreq in [0, 9, 10, 95, 180, 1 << 40].iter() {
+            let expected = cum_freq.iter().filter(|c| **c as u64 <= *rfreq).count();
+            assert_eq!(count_at_most(&cum_freq, *rfreq), expected);
+        }
+    }
+}
@@ -0,0 +1,186 @@
+//! 符号化器の出力先
+//! 出力先を表すトレイト: BitSink
+//! ModelEncoder::flush_to とBinaryEncoderが書き出す
+//! 実装: Vec<u8> / WriteSink（io::Write，feature = "std"） / FixedBuffer（確保済みのバッファ） / CountingSink（数えるだけ）
+//! 出力は失敗しない．失敗は出力先に記録し，最後に取り出す
+
+use crate::prelude::*;
+use crate::{Error, Result};
+#[cfg(feature = "std")]
+use std::io::Write;
+
+/// destination of encoded bytes.
+pub trait BitSink {
+    fn put_byte(&mut self, byte: u8);
+    fn put_bytes(&mut self, bytes: &[u8]) {
+        for byte in bytes {
+            self.put_byte(*byte);
+        }
+    }
+    /// number of bytes put so far.
+    fn bytes_written(&self) -> usize;
+}
+
+impl BitSink for Vec<u8> {
+    fn put_byte(&mut self, byte: u8) {
+        self.push(byte);
+    }
+    fn put_bytes(&mut self, bytes: &[u8]) {
+        self.extend_from_slice(bytes);
+    }
+    fn bytes_written(&self) -> usize {
+        self.len()
+    }
+}
+
+/// sink writing to an `io::Write`.
+/// the first error is kept, and later bytes are discarded.
+#[cfg(feature = "std")]
+pub struct WriteSink<W: Write> {
+    writer: W,
+    written: usize,
+    error: Option<std::io::Error>,
+}
+#[cfg(feature = "std")]
+impl<W: Write> WriteSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self {
+            writer,
+            written: 0,
+            error: None,
+        }
+    }
+    /// flush the writer and give it back, or the first error.
+    pub fn into_inner(mut self) -> std::io::Result<W> {
+        if let Some(error) = self.error.take() {
+            return Err(error);
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+#[cfg(feature = "std")]
+impl<W: Write> BitSink for WriteSink<W> {
+    fn put_byte(&mut self, byte: u8) {
+        self.put_bytes(&[byte]);
+    }
+    fn put_bytes(&mut self, bytes: &[u8]) {
+        if self.error.is_some() {
+            return;
+        }
+        match self.writer.write_all(bytes) {
+            Ok(()) => self.written += bytes.len(),
+            Err(error) => self.error = Some(error),
+        }
+    }
+    fn bytes_written(&self) -> usize {
+        self.written
+    }
+}
+
+/// sink over a preallocated buffer.
+/// bytes beyond its end are counted but discarded.
+pub struct FixedBuffer<'a> {
+    buffer: &'a mut [u8],
+    written: usize,
+}
+impl<'a> FixedBuffer<'a> {
+    pub fn new(buffer: &'a mut [u8]) -> Self {
+        Self { buffer, written: 0 }
+    }
+    pub fn overflowed(&self) -> bool {
+        self.written > self.buffer.len()
+    }
+    /// the written part of the buffer, or an error if it was too small.
+    pub fn written(&self) -> Result<&[u8]> {
+        if self.overflowed() {
+            return Err(Error::BufferFull {
+                capacity: self.buffer.len(),
+                required: self.written,
+            });
+        }
+        Ok(&self.buffer[..self.written])
+    }
+}
+impl BitSink for FixedBuffer<'_> {
+    fn put_byte(&mut self, byte: u8) {
+        if let Some(slot) = self.buffer.get_mut(self.written) {
+            *slot = byte;
+        }
+        self.written += 1;
+    }
+    fn bytes_written(&self) -> usize {
+        self.written
+    }
+}
+
+/// sink which only counts bytes, for measuring the exact coded size.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CountingSink {
+    written: usize,
+}
+impl CountingSink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+impl BitSink for CountingSink {
+    fn put_byte(&mut self, _byte: u8) {
+        self.written += 1;
+    }
+    fn put_bytes(&mut self, bytes: &[u8]) {
+        self.written += bytes.len();
+    }
+    fn bytes_written(&self) -> usize {
+        self.written
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::binary::{Binarizer, BinaryEncoder, BinarySymbolCoder};
+    use crate::codec::{encode_slice, ModelEncoder};
+    use crate::test_util::simple_pmodel;
+    fn encode_into<S: BitSink>(sink: S, symbols: &[usize]) -> S {
+        let mut coder = BinarySymbolCoder::new(Binarizer::ExpGolomb(0));
+        let mut encoder = BinaryEncoder::with_sink(sink);
+        for s in symbols {
+            coder.encode(&mut encoder, *s);
+        }
+        encoder.finish();
+        encoder.into_sink()
+    }
+    #[test]
+    fn sinks_agree() {
+        let symbols: Vec<usize> = (0..300).map(|i| (i * 7) % 20).collect();
+        let expected = encode_into(Vec::new(), &symbols);
+        assert_eq!(encode_into(CountingSink::new(), &symbols).bytes_written(), expected.len());
+        let written = encode_into(WriteSink::new(Vec::new()), &symbols);
+        assert_eq!(written.into_inner().unwrap(), expected);
+        let mut buffer = vec![0; expected.len()];
+        let fixed = encode_into(FixedBuffer::new(&mut buffer), &symbols);
+        assert_eq!(fixed.written().unwrap(), &expected[..]);
+        let mut small = vec![0; expected.len() - 1];
+        let fixed = encode_into(FixedBuffer::new(&mut small), &symbols);
+        assert!(matches!(fixed.written(), Err(Error::BufferFull { .. })));
+    }
+    #[test]
+    fn model_encoder_to_sinks() {
+        let pm = simple_pmodel();
+        let symbols: Vec<usize> = (0..300).map(|i| (i * 7) % 256).collect();
+        let expected = encode_slice(&pm, &symbols);
+        let mut encoder = ModelEncoder::new();
+        encoder.enable_stats();
+        encoder.encode_slice(&pm, &symbols);
+        let mut counting = CountingSink::new();
+        encoder.flush_to(&mut counting);
+        assert_eq!(counting.bytes_written(), expected.len());
+        assert_eq!(encoder.stats().unwrap().output_bits(), 8 * expected.len());
+        let mut buffer = vec![0; expected.len()];
+        let mut fixed = FixedBuffer::new(&mut buffer);
+        encoder.encode_slice(&pm, &symbols);
+        encoder.flush_to(&mut fixed);
+        assert_eq!(fixed.written().unwrap(), &expected[..]);
+    }
+}
@@ -0,0 +1,153 @@
+//! 二次的な確率の推定（SSE/APM）
+//! 基のモデルの各シンボルの確率を，小さな文脈毎の補間表で補正する
+//! 表は確率のロジット（stretch）を等間隔に区切った点での補正後の確率で，符号化しながら学習する
+//! 補正器: Apm
+//! 補正したモデル: RefinedModel
+//! 浮動小数点演算を使うので，符号化と復号は同じ環境で行う
+
+use crate::prelude::*;
+use crate::QuantizedPDFSet;
+use range_coder::decoder::Decoder;
+use range_coder::pmodel::PModel;
+
+/// number of interpolation points of the table of each context.
+const POINTS: usize = 33;
+/// ロジットの範囲は [-STRETCH_MAX, STRETCH_MAX]
+const STRETCH_MAX: f64 = 16.0;
+const REFINED_TOTAL: f64 = (1u32 << 24) as f64;
+/// default step size of the table update.
+pub const DEFAULT_RATE: f64 = 0.02;
+
+fn stretch(p: f64) -> f64 {
+    (p / (1.0 - p)).ln()
+}
+fn squash(x: f64) -> f64 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+/// adaptive probability map: refines the probabilities of a model in a small context.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Apm {
+    /// 文脈毎の補間表
+    table: Vec<f64>,
+    contexts: usize,
+    alphabet_size: usize,
+    rate: f64,
+}
+impl Apm {
+    /// map for `contexts` contexts over `alphabet_size` symbols, starting from the identity.
+    pub fn new(contexts: usize, alphabet_size: usize) -> Self {
+        assert!(contexts > 0 && alphabet_size > 1);
+        let step = 2.0 * STRETCH_MAX / (POINTS - 1) as f64;
+        let identity: Vec<f64> = (0..POINTS)
+            .map(|j| squash(j as f64 * step - STRETCH_MAX))
+            .collect();
+        Self {
+            table: identity.repeat(contexts),
+            contexts,
+            alphabet_size,
+            rate: DEFAULT_RATE,
+        }
+    }
+    pub fn with_rate(mut self, rate: f64) -> Self {
+        assert!(rate > 0.0 && rate < 1.0);
+        self.rate = rate;
+        self
+    }
+    pub fn contexts(&self) -> usize {
+        self.contexts
+    }
+    /// refine the model in the context.
+    pub fn refine(&self, model: &dyn PModel, context: usize) -> RefinedModel {
+        assert!(context < self.contexts);
+        let total = model.total_freq() as f64;
+        let row = &self.table[context * POINTS..(context + 1) * POINTS];
+        let step = 2.0 * STRETCH_MAX / (POINTS - 1) as f64;
+        let mut points = Vec::with_capacity(self.alphabet_size);
+        let refined: Vec<f64> = (0..self.alphabet_size)
+            .map(|s| {
+                let p = model.c_freq(s) as f64 / total;
+                let x = (stretch(p).clamp(-STRETCH_MAX, STRETCH_MAX) + STRETCH_MAX) / step;
+                let j = (x as usize).min(POINTS - 2);
+                let frac = x - j as f64;
+                points.push((j, frac));
+                let mapped = row[j] * (1.0 - frac) + row[j + 1] * frac;
+                // 基の確率とも平均して，学習が進むまでの悪化を抑える
+                (p + 3.0 * mapped) / 4.0
+            })
+            .collect();
+        let sum: f64 = refined.iter().sum();
+        let freq = refined
+            .iter()
+            .map(|p| (p / sum * REFINED_TOTAL) as u32 + 1)
+            .collect();
+        RefinedModel {
+            table: QuantizedPDFSet::from_freq(freq),
+            context,
+            points,
+        }
+    }
+    /// learn from the symbol coded with `refined`, made by `refine` of this map.
+    pub fn update(&mut self, refined: &RefinedModel, symbol: usize) {
+        let row = &mut self.table[refined.context * POINTS..(refined.context + 1) * POINTS];
+        for (s, (j, frac)) in refined.points.iter().enumerate() {
+            let target = if s == symbol { 1.0 } else { 0.0 };
+            row[*j] += self.rate * (1.0 - frac) * (target - row[*j]);
+            row[*j + 1] += self.rate * frac * (target - row[*j + 1]);
+        }
+    }
+}
+
+/// model refined by `Apm::refine`.
+#[derive(Debug)]
+pub struct RefinedModel {
+    table: QuantizedPDFSet,
+    context: usize,
+    /// 各シンボルの補間する点と，その間の位置
+    points: Vec<(usize, f64)>,
+}
+impl PModel for RefinedModel {
+    fn c_freq(&self, index: usize) -> u32 {
+        self.table.c_freq(index)
+    }
+    fn cum_freq(&self, index: usize) -> u32 {
+        self.table.cum_freq(index)
+    }
+    fn total_freq(&self) -> u32 {
+        self.table.total_freq()
+    }
+    fn find_index(&self, decoder: &Decoder) -> usize {
+        self.table.find_index(decoder)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{ModelDecoder, ModelEncoder};
+    use crate::test_util::simple_pmodel;
+    #[test]
+    fn refinement_improves_static_model() {
+        // 文脈（偶奇）で分布が変わるのに，基のモデルは変わらない
+        let base = simple_pmodel();
+        let symbols: Vec<usize> = (0..3000)
+            .map(|i| if i % 2 == 0 { 128 } else { 30 })
+            .collect();
+        let mut apm = Apm::new(2, 256);
+        let mut encoder = ModelEncoder::new();
+        for (i, s) in symbols.iter().enumerate() {
+            let refined = apm.refine(&base, i % 2);
+            encoder.encode(&refined, *s);
+            apm.update(&refined, *s);
+        }
+        let data = encoder.finish();
+        assert!(data.len() * 8 < base.estimate_bits(&symbols) as usize);
+        let mut apm = Apm::new(2, 256);
+        let mut decoder = ModelDecoder::new(data);
+        for (i, s) in symbols.iter().enumerate() {
+            let refined = apm.refine(&base, i % 2);
+            assert_eq!(decoder.try_decode(&refined).unwrap(), *s);
+            apm.update(&refined, *s);
+        }
+    }
+}
@@ -0,0 +1,70 @@
+//! プロパティテストのための生成器 (feature = "testing")
+//! 利用者の符号化器を，このクレートの不変条件を満たす入力でテストするためのproptestのStrategy
+//! 生成する量子化した確率分布は，常に正しい頻度表（頻度は1以上，総頻度はu32に収まる）を持つ
+//! 量子化した確率分布: quantized_pdf_set
+//! 正規分布の混合分布: gaussian_pdf_set
+//! シンボルの列: symbols
+
+use crate::dist::Gaussian;
+use crate::{PDFSet, QuantizedPDFSet};
+use proptest::collection::vec;
+use proptest::strategy::Strategy;
+use std::ops::Range;
+
+/// valid models of `alphabet_size` symbols, such as 256 or 257 with `EOF_SYMBOL`.
+pub fn quantized_pdf_set(alphabet_size: usize) -> impl Strategy<Value = QuantizedPDFSet> {
+    assert!(alphabet_size > 0 && alphabet_size <= u16::MAX as usize + 1);
+    // 総頻度がu32に収まるようにする
+    let max = u32::MAX / alphabet_size as u32;
+    vec(1..=max, alphabet_size).prop_map(QuantizedPDFSet::from_freq)
+}
+
+/// mixtures of 1 to `max_components` gaussians over the 256 values,
+/// with positive weights, so that they can always be finalized.
+pub fn gaussian_pdf_set(max_components: usize) -> impl Strategy<Value = PDFSet<Gaussian>> {
+    assert!(max_components > 0);
+    let component = (0.01..10.0, 0.0..255.0, 0.5..64.0);
+    vec(component, 1..max_components + 1).prop_map(|components| {
+        PDFSet::new(
+            components
+                .into_iter()
+                .map(|(weight, mean, sigma)| Gaussian::new(weight, mean, sigma))
+                .collect(),
+        )
+    })
+}
+
+/// sequences of symbols below `alphabet_size`, of a length in `len`.
+pub fn symbols(alphabet_size: usize, len: Range<usize>) -> impl Strategy<Value = Vec<usize>> {
+    assert!(alphabet_size > 0);
+    vec(0..alphabet_size, len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::EOF_SYMBOL;
+    use proptest::test_runner::TestRunner;
+    #[test]
+    fn generated_models_roundtrip() {
+        let models = (
+            quantized_pdf_set(EOF_SYMBOL + 1),
+            symbols(EOF_SYMBOL + 1, 0..300),
+        );
+        TestRunner::default()
+            .run(&models, |(model, symbols)| {
+                assert_eq!(model.alphabet_size(), EOF_SYMBOL + 1);
+                assert_eq!(model.verify_roundtrip(&symbols), Ok(()));
+                Ok(())
+            })
+            .unwrap();
+        let mixtures = (gaussian_pdf_set(4), symbols(EOF_SYMBOL, 1..300));
+        TestRunner::default()
+            .run(&mixtures, |(set, symbols)| {
+                let model = set.try_finalize().unwrap();
+                assert_eq!(model.verify_roundtrip(&symbols), Ok(()));
+                Ok(())
+            })
+            .unwrap();
+    }
+}
@@ -0,0 +1,35 @@
+//! テスト用の確率分布とモデル
+
+use crate::{PDFSet, QuantizedPDFSet, PDF};
+
+pub(crate) struct GaussianDist {
+    pub h: f64,
+    pub w: f64,
+    pub m: u8,
+}
+impl PDF for GaussianDist {
+    fn freq(&self, v: usize) -> f64 {
+        let d = v as f64 - self.m as f64;
+        self.h * self.w * (-self.w * self.w * d * d).exp()
+    }
+}
+pub(crate) fn simple_pmodel() -> QuantizedPDFSet {
+    PDFSet::new(vec![
+        GaussianDist {
+            h: 10.0,
+            w: 5.0,
+            m: 128,
+        },
+        GaussianDist {
+            h: 10.0,
+            w: 2.0,
+            m: 30,
+        },
+        GaussianDist {
+            h: 2.0,
+            w: 5.0,
+            m: 70,
+        },
+    ])
+    .finalize()
+}
@@ -0,0 +1,314 @@
+//! データからの静的なモデルの学習
+//! ファイルやシンボル列の頻度を（必要なら名前付きの文脈毎に）数え，
+//! QuantizedPDFSetとモデルバンク（bank::ModelBank）を作る
+//! 学習器: Trainer
+//! 1つの文脈の頻度を数えるもの: Accumulator
+//! どちらも標本を溜めず，入力の大きさによらずシンボルの種類の数に比例するメモリしか使わない
+//! feature = "parallel" のときは，複数の断片やファイルを複数のスレッドで数えて足し合わせられる
+
+use crate::bank::ModelBank;
+use crate::prelude::*;
+use crate::{Error, QuantizedPDFSet, Result, EOF_SYMBOL};
+#[cfg(feature = "parallel")]
+use rayon::prelude::*;
+#[cfg(feature = "std")]
+use std::io::Read;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+const ALPHABET_SIZE: usize = u8::MAX as usize + 1;
+/// 読み込みに使う固定長のバッファの大きさ
+#[cfg(feature = "std")]
+const READ_BUFFER_SIZE: usize = 8 * 1024;
+
+/// fixed-memory histogram over 256 symbols, fed one symbol or chunk at a time.
+/// symbols are counted, never stored, so it can run on embedded collectors.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Accumulator {
+    counts: Vec<u64>,
+}
+impl Accumulator {
+    pub fn new() -> Self {
+        Self {
+            counts: vec![0; ALPHABET_SIZE],
+        }
+    }
+    pub fn add(&mut self, symbol: u8) {
+        self.counts[symbol as usize] += 1;
+    }
+    pub fn add_bytes(&mut self, bytes: &[u8]) {
+        count_bytes(&mut self.counts, bytes);
+    }
+    /// count the bytes of the reader to its end, through a fixed-size buffer.
+    #[cfg(feature = "std")]
+    pub fn add_reader<R: Read>(&mut self, reader: R) -> Result<()> {
+        count_reader(&mut self.counts, reader)
+    }
+    /// add the counts of another accumulator, e.g. of another shard.
+    pub fn merge(&mut self, other: &Accumulator) {
+        for (c, n) in self.counts.iter_mut().zip(&other.counts) {
+            *c += n;
+        }
+    }
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+    /// number of symbols counted.
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+    /// bytes used, which do not depend on the number of symbols counted.
+    pub fn memory_usage(&self) -> usize {
+        core::mem::size_of::<Self>() + self.counts.capacity() * core::mem::size_of::<u64>()
+    }
+    /// model of the counts. fails if nothing was counted.
+    pub fn finalize(&self) -> Result<QuantizedPDFSet> {
+        QuantizedPDFSet::from_counts(&self.counts)
+    }
+}
+impl Default for Accumulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+fn count_bytes(counts: &mut [u64], bytes: &[u8]) {
+    for byte in bytes {
+        counts[*byte as usize] += 1;
+    }
+}
+/// 固定長のバッファで読みながら数える
+#[cfg(feature = "std")]
+fn count_reader<R: Read>(counts: &mut [u64], mut reader: R) -> Result<()> {
+    let mut buffer = [0; READ_BUFFER_SIZE];
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(n) => count_bytes(counts, &buffer[..n]),
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// accumulates symbol counts per named context, to make static models.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trainer {
+    names: Vec<String>,
+    counts: Vec<Vec<u64>>,
+    with_eof: bool,
+}
+impl Trainer {
+    /// trainer with one context named "default", with id 0.
+    pub fn new() -> Self {
+        let mut trainer = Self {
+            names: Vec::new(),
+            counts: Vec::new(),
+            with_eof: false,
+        };
+        trainer.add_context("default");
+        trainer
+    }
+    /// models get the end-of-stream symbol `EOF_SYMBOL`, counted once per `add_*` call.
+    pub fn with_eof(mut self) -> Self {
+        self.with_eof = true;
+        self.counts
+            .iter_mut()
+            .for_each(|c| c.resize(EOF_SYMBOL + 1, 0));
+        self
+    }
+    /// declare a context and return its id, or the id of the context with the name.
+    pub fn add_context(&mut self, name: &str) -> usize {
+        if let Some(id) = self.names.iter().position(|n| n == name) {
+            return id;
+        }
+        self.names.push(name.to_string());
+        let size = if self.with_eof {
+            EOF_SYMBOL + 1
+        } else {
+            ALPHABET_SIZE
+        };
+        self.counts.push(vec![0; size]);
+        self.counts.len() - 1
+    }
+    /// number of contexts.
+    pub fn contexts(&self) -> usize {
+        self.counts.len()
+    }
+    pub fn counts(&self, context: usize) -> &[u64] {
+        &self.counts[context]
+    }
+    pub fn name(&self, context: usize) -> &str {
+        &self.names[context]
+    }
+    pub fn add_symbols(&mut self, context: usize, symbols: &[usize]) -> Result<()> {
+        let counts = &mut self.counts[context];
+        if let Some(symbol) = symbols.iter().find(|s| **s >= ALPHABET_SIZE) {
+            return Err(Error::SymbolOutOfRange {
+                symbol: *symbol,
+                alphabet_size: ALPHABET_SIZE,
+            });
+        }
+        for symbol in symbols {
+            counts[*symbol] += 1;
+        }
+        if self.with_eof {
+            counts[EOF_SYMBOL] += 1;
+        }
+        Ok(())
+    }
+    pub fn add_bytes(&mut self, context: usize, bytes: &[u8]) {
+        let counts = &mut self.counts[context];
+        count_bytes(counts, bytes);
+        if self.with_eof {
+            counts[EOF_SYMBOL] += 1;
+        }
+    }
+    /// count the bytes of the reader to its end, through a fixed-size buffer.
+    #[cfg(feature = "std")]
+    pub fn add_reader<R: Read>(&mut self, context: usize, reader: R) -> Result<()> {
+        let counts = &mut self.counts[context];
+        count_reader(counts, reader)?;
+        if self.with_eof {
+            counts[EOF_SYMBOL] += 1;
+        }
+        Ok(())
+    }
+    /// count the bytes of a file, without reading it into memory at once.
+    #[cfg(feature = "std")]
+    pub fn add_file<P: AsRef<Path>>(&mut self, context: usize, path: P) -> Result<()> {
+        self.add_reader(context, std::fs::File::open(path)?)
+    }
+    /// `add_bytes` for each shard, counted on the threads of rayon.
+    #[cfg(feature = "parallel")]
+    pub fn add_shards_parallel(&mut self, context: usize, shards: &[&[u8]]) {
+        let histograms: Vec<Accumulator> = shards
+            .par_iter()
+            .map(|s| {
+                let mut accumulator = Accumulator::new();
+                accumulator.add_bytes(s);
+                accumulator
+            })
+            .collect();
+        self.merge(context, &histograms);
+    }
+    /// `add_file` for each file, read and counted on the threads of rayon.
+    /// nothing is counted if a file cannot be read.
+    #[cfg(feature = "parallel")]
+    pub fn add_files_parallel<P: AsRef<Path> + Sync>(
+        &mut self,
+        context: usize,
+        paths: &[P],
+    ) -> Result<()> {
+        let histograms = paths
+            .par_iter()
+            .map(|p| {
+                let mut accumulator = Accumulator::new();
+                accumulator.add_reader(std::fs::File::open(p)?)?;
+                Ok(accumulator)
+            })
+            .collect::<Result<Vec<Accumulator>>>()?;
+        self.merge(context, &histograms);
+        Ok(())
+    }
+    /// 断片毎の頻度を足し合わせる
+    #[cfg(feature = "parallel")]
+    fn merge(&mut self, context: usize, histograms: &[Accumulator]) {
+        let counts = &mut self.counts[context];
+        for h in histograms {
+            for (c, n) in counts.iter_mut().zip(h.counts()) {
+                *c += n;
+            }
+            if self.with_eof {
+                counts[EOF_SYMBOL] += 1;
+            }
+        }
+    }
+    /// model of the context. fails if nothing was counted in it.
+    pub fn finalize(&self, context: usize) -> Result<QuantizedPDFSet> {
+        QuantizedPDFSet::from_counts(&self.counts[context])
+    }
+    /// bank of the models of all contexts, with the context names.
+    pub fn finalize_bank(&self) -> Result<ModelBank> {
+        let mut bank = ModelBank::new();
+        for (id, name) in self.names.iter().enumerate() {
+            bank.add(name, self.finalize(id)?)?;
+        }
+        Ok(bank)
+    }
+}
+impl Default for Trainer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use range_coder::pmodel::PModel;
+    #[test]
+    fn trained_bank() {
+        let mut trainer = Trainer::new();
+        let text = trainer.add_context("text");
+        assert_eq!(trainer.add_context("text"), text);
+        trainer.add_bytes(0, &[0, 0, 0, 1]);
+        trainer.add_bytes(text, b"hello world");
+        assert!(trainer.add_symbols(text, &[256]).is_err());
+        let bank = trainer.finalize_bank().unwrap();
+        assert_eq!(bank.len(), 2);
+        let model = bank.get(bank.id("default").unwrap()).unwrap();
+        assert!(model.c_freq(0) > model.c_freq(1) && model.c_freq(1) > model.c_freq(2));
+        let text = bank.get(bank.id("text").unwrap()).unwrap();
+        assert!(text.estimate_bits(&[108, 111]) < text.estimate_bits(&[0, 255]));
+        let (loaded, _) = ModelBank::from_bytes(&bank.to_bytes()).unwrap();
+        assert_eq!(loaded.name(1), Some("text"));
+        let empty = Trainer::new();
+        assert!(empty.finalize(0).is_err());
+    }
+    #[test]
+    fn trained_with_eof() {
+        let mut trainer = Trainer::new().with_eof();
+        trainer.add_symbols(0, &[3, 3, 4]).unwrap();
+        let model = trainer.finalize(0).unwrap();
+        assert!(model.has_eof());
+        assert!(model.c_freq(EOF_SYMBOL) > 1);
+    }
+    #[test]
+    fn fixed_memory() {
+        let mut accumulator = Accumulator::new();
+        let before = accumulator.memory_usage();
+        // 入力の大きさによらない
+        let input = std::io::repeat(7).take(4 << 20);
+        accumulator.add_reader(input).unwrap();
+        accumulator.add_bytes(&[1, 2, 3]);
+        accumulator.add(3);
+        assert_eq!(accumulator.memory_usage(), before);
+        assert_eq!(accumulator.total(), (4 << 20) + 4);
+        assert_eq!(accumulator.counts()[3], 2);
+        let mut other = Accumulator::new();
+        other.add(200);
+        accumulator.merge(&other);
+        let model = accumulator.finalize().unwrap();
+        assert!(model.c_freq(7) > model.c_freq(200) && model.c_freq(200) > model.c_freq(201));
+        let mut trainer = Trainer::new();
+        trainer.add_reader(0, &[5u8, 5, 6][..]).unwrap();
+        assert_eq!(&trainer.counts(0)[5..7], &[2, 1]);
+        assert!(Accumulator::new().finalize().is_err());
+    }
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_shards() {
+        let corpus: Vec<u8> = (0..10000u32).map(|i| (i * i % 251) as u8).collect();
+        let shards: Vec<&[u8]> = corpus.chunks(999).collect();
+        let mut serial = Trainer::new().with_eof();
+        for shard in &shards {
+            serial.add_bytes(0, shard);
+        }
+        let mut parallel = Trainer::new().with_eof();
+        parallel.add_shards_parallel(0, &shards);
+        assert_eq!(parallel, serial);
+        let missing = ["/nonexistent/shard"];
+        assert!(parallel.add_files_parallel(0, &missing).is_err());
+        assert_eq!(parallel, serial);
+    }
+}
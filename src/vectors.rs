@@ -0,0 +1,172 @@
+//! 他の言語の実装のためのテストベクタ
+//! 別の言語で書いた符号化器/復号器が，このクレートとビット単位で同じ結果になるかを確かめるためのもの
+//! モデルは数えた頻度か整数演算のみ（deterministic）から作り，シンボル列は固定の擬似乱数で作るので，どの環境でも同じ
+//! テストベクタの一覧: test_vectors
+//! ディレクトリへの書き出し: write_test_vectors (feature = "std")，mgfrc vectors <ディレクトリ>
+//! ディレクトリ: manifest.csv | <名前>/model.bin | <名前>/symbols.bin | <名前>/compressed.bin
+//! model.bin: QuantizedPDFSet::to_bytes の形式
+//! symbols.bin: シンボル(u16 LE)の列
+//! compressed.bin: codec::encode_slice の出力（長さは含まない）
+//! manifest.csv: name,alphabet_size,symbols,compressed_bytes の行
+
+use crate::codec::encode_slice;
+use crate::deterministic::{DiscreteGaussian, DiscreteLaplace, IntegerPDFSet};
+use crate::prelude::*;
+use crate::{QuantizedPDFSet, EOF_SYMBOL};
+use range_coder::pmodel::PModel;
+#[cfg(feature = "std")]
+use std::path::Path;
+
+/// a model, the symbols to encode with it, and the bytes this crate encodes them to.
+#[derive(Debug)]
+pub struct TestVector {
+    pub name: &'static str,
+    pub model: QuantizedPDFSet,
+    pub symbols: Vec<usize>,
+    pub compressed: Vec<u8>,
+}
+impl TestVector {
+    fn new(name: &'static str, symbols: Vec<usize>, model: QuantizedPDFSet) -> Self {
+        let compressed = encode_slice(&model, &symbols);
+        Self {
+            name,
+            model,
+            symbols,
+            compressed,
+        }
+    }
+    /// the symbols as u16 little endian, the format of `symbols.bin`.
+    pub fn symbol_bytes(&self) -> Vec<u8> {
+        self.symbols
+            .iter()
+            .flat_map(|s| (*s as u16).to_le_bytes())
+            .collect()
+    }
+}
+
+/// the canonical test vectors, the same on every platform.
+/// they cover the uniform, peaked and counted models, the end-of-stream symbol,
+/// symbols of the floor frequency, and the empty message.
+pub fn test_vectors() -> Vec<TestVector> {
+    let uniform = QuantizedPDFSet::from_counts(&[1; 256]).unwrap();
+    let gaussian = || {
+        let mut set = IntegerPDFSet::new(vec![DiscreteGaussian::new(1000, 128, 9, 10)]);
+        set.add_pdf(DiscreteGaussian::new(300, 30, 1, 2));
+        set.finalize().unwrap()
+    };
+    let laplace = IntegerPDFSet::new(vec![DiscreteLaplace::new(u32::MAX, 0, 7, 8)])
+        .finalize()
+        .unwrap();
+    let text = b"Test vectors for bit-exact range coding, shared across languages.\n".repeat(16);
+    let mut counts = [0u64; 256];
+    for b in text.iter() {
+        counts[*b as usize] += 1;
+    }
+    let counted = QuantizedPDFSet::from_counts(&counts).unwrap();
+    let eof = laplace.with_eof();
+    let mut terminated = sample(&eof, 2000, 3);
+    terminated.retain(|s| *s != EOF_SYMBOL);
+    terminated.push(EOF_SYMBOL);
+    vec![
+        TestVector::new("uniform", sample(&uniform, 1000, 1), uniform),
+        TestVector::new("gaussian", sample(&gaussian(), 4096, 2), gaussian()),
+        TestVector::new("laplace_eof", terminated, eof),
+        TestVector::new(
+            "counted_text",
+            text.iter().map(|b| *b as usize).collect(),
+            counted,
+        ),
+        TestVector::new(
+            "floor_symbols",
+            (0..512).map(|i| [255, 0, 254, 1, 200][i % 5]).collect(),
+            laplace,
+        ),
+        TestVector::new("empty", Vec::new(), gaussian()),
+    ]
+}
+
+/// write `test_vectors` into the directory, creating it if needed.
+/// returns the names of the vectors, which are also the names of their subdirectories.
+#[cfg(feature = "std")]
+pub fn write_test_vectors<P: AsRef<Path>>(dir: P) -> crate::Result<Vec<&'static str>> {
+    let dir = dir.as_ref();
+    let mut manifest = String::from("name,alphabet_size,symbols,compressed_bytes\n");
+    let mut names = Vec::new();
+    for vector in test_vectors() {
+        let path = dir.join(vector.name);
+        std::fs::create_dir_all(&path)?;
+        std::fs::write(path.join("model.bin"), vector.model.to_bytes())?;
+        std::fs::write(path.join("symbols.bin"), vector.symbol_bytes())?;
+        std::fs::write(path.join("compressed.bin"), &vector.compressed)?;
+        manifest += &format!(
+            "{},{},{},{}\n",
+            vector.name,
+            vector.model.alphabet_size(),
+            vector.symbols.len(),
+            vector.compressed.len()
+        );
+        names.push(vector.name);
+    }
+    std::fs::write(dir.join("manifest.csv"), manifest)?;
+    Ok(names)
+}
+
+/// 固定の線形合同法で，モデルの確率に従うシンボル列を作る
+fn sample(model: &QuantizedPDFSet, len: usize, seed: u64) -> Vec<usize> {
+    let mut state = seed;
+    (0..len)
+        .map(|_| {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407);
+            model.index_of((state >> 32) % model.total_freq() as u64)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::try_decode_slice;
+    #[test]
+    fn vectors_roundtrip_and_files() {
+        let vectors = test_vectors();
+        for vector in &vectors {
+            let decoded = try_decode_slice(
+                &vector.model,
+                vector.compressed.clone(),
+                vector.symbols.len(),
+            )
+            .unwrap();
+            assert_eq!(decoded, vector.symbols, "{}", vector.name);
+        }
+        // 同じ入力からは同じベクタ
+        assert_eq!(sample(&vectors[1].model, 100, 2), vectors[1].symbols[..100]);
+        assert_eq!(vectors[2].symbols.last(), Some(&EOF_SYMBOL));
+        assert!(vectors[4]
+            .symbols
+            .iter()
+            .any(|s| vectors[4].model.c_freq(*s) == 1));
+
+        let dir = std::env::temp_dir().join(format!("test-vectors-{}", std::process::id()));
+        let names = write_test_vectors(&dir).unwrap();
+        assert_eq!(names.len(), vectors.len());
+        let manifest = std::fs::read_to_string(dir.join("manifest.csv")).unwrap();
+        assert_eq!(manifest.lines().count(), vectors.len() + 1);
+        assert!(manifest.contains("laplace_eof,257,"));
+        let gaussian = dir.join("gaussian");
+        let (model, _) =
+            QuantizedPDFSet::from_bytes(&std::fs::read(gaussian.join("model.bin")).unwrap())
+                .unwrap();
+        assert_eq!(model.to_bytes(), vectors[1].model.to_bytes());
+        assert_eq!(
+            std::fs::read(gaussian.join("symbols.bin")).unwrap(),
+            vectors[1].symbol_bytes()
+        );
+        assert_eq!(
+            std::fs::read(gaussian.join("compressed.bin")).unwrap(),
+            vectors[1].compressed
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
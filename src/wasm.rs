@@ -0,0 +1,110 @@
+//! JavaScriptからの利用 (feature = "wasm-bindgen")
+//! サーバで符号化したデータを，同じモデルでブラウザで復号するためのもの
+//! バイト列はUint8Array，シンボル列はUint16Array（終端のシンボルを含むため）で受け渡す
+//! モデル: Model
+//! コンテナ形式の圧縮/伸長: compress / decompress
+
+use crate::codec::{encode_slice, try_decode_slice};
+use crate::container::{self, ContainerOptions};
+use crate::dist::Gaussian;
+use crate::{Error, PDFSet, QuantizedPDFSet, RawModel};
+use wasm_bindgen::prelude::*;
+
+/// a quantized model, for JavaScript.
+#[wasm_bindgen]
+pub struct Model {
+    model: QuantizedPDFSet,
+}
+#[wasm_bindgen]
+impl Model {
+    /// model from 256 frequencies, or 257 with the end-of-stream symbol.
+    #[wasm_bindgen(js_name = fromFreq)]
+    pub fn from_freq(freq: &[u32]) -> Result<Model, JsError> {
+        let model = RawModel::from_freq(freq.to_vec()).validate()?;
+        Ok(Self { model })
+    }
+    /// model of the mixture of gaussians given as `weight, mean, sigma` triples.
+    #[wasm_bindgen(js_name = fromGaussians)]
+    pub fn from_gaussians(params: &[f64]) -> Result<Model, JsError> {
+        let triples = params.chunks_exact(3);
+        if !triples.remainder().is_empty() {
+            return Err(JsError::new(
+                "parameters are not weight, mean, sigma triples",
+            ));
+        }
+        let mut set = PDFSet::new(Vec::with_capacity(triples.len()));
+        for p in triples {
+            if !(p[2].is_finite() && p[2] > 0.0) {
+                return Err(JsError::new("sigma is not a positive finite number"));
+            }
+            set.add_pdf(Gaussian::new(p[0], p[1], p[2]));
+        }
+        Ok(Self {
+            model: set.try_finalize()?,
+        })
+    }
+    /// model serialized by `toBytes` or `QuantizedPDFSet::to_bytes`.
+    #[wasm_bindgen(js_name = fromBytes)]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Model, JsError> {
+        let (model, _) = QuantizedPDFSet::from_bytes(bytes)?;
+        Ok(Self { model })
+    }
+    #[wasm_bindgen(js_name = toBytes)]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.model.to_bytes()
+    }
+    #[wasm_bindgen(getter, js_name = alphabetSize)]
+    pub fn alphabet_size(&self) -> usize {
+        self.model.alphabet_size()
+    }
+    pub fn encode(&self, symbols: &[u16]) -> Result<Vec<u8>, JsError> {
+        let symbols: Vec<usize> = symbols.iter().map(|s| *s as usize).collect();
+        if let Some(symbol) = symbols.iter().find(|s| **s >= self.alphabet_size()) {
+            return Err(Error::SymbolOutOfRange {
+                symbol: *symbol,
+                alphabet_size: self.alphabet_size(),
+            }
+            .into());
+        }
+        Ok(encode_slice(&self.model, &symbols))
+    }
+    /// decode `len` symbols.
+    pub fn decode(&self, data: &[u8], len: usize) -> Result<Vec<u16>, JsError> {
+        let symbols = try_decode_slice(&self.model, data.to_vec(), len)?;
+        Ok(symbols.into_iter().map(|s| s as u16).collect())
+    }
+}
+
+/// bytes in the container format with the model of their own frequencies embedded.
+#[wasm_bindgen]
+pub fn compress(bytes: &[u8]) -> Result<Vec<u8>, JsError> {
+    let symbols: Vec<usize> = bytes.iter().map(|b| *b as usize).collect();
+    Ok(container::compress_two_pass(
+        &symbols,
+        ContainerOptions::default(),
+    )?)
+}
+
+/// bytes of a container encoded by `compress` or `container::compress`.
+/// `model` is used when the container has no embedded model.
+#[wasm_bindgen]
+pub fn decompress(data: &[u8], model: Option<Model>) -> Result<Vec<u8>, JsError> {
+    let symbols = container::decompress(data, model.as_ref().map(|m| &m.model))?;
+    Ok(symbols.into_iter().map(|s| s as u8).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn roundtrip_for_javascript() {
+        let model = Model::from_gaussians(&[1.0, 40.0, 3.0, 0.5, 200.0, 10.0]).unwrap();
+        let loaded = Model::from_bytes(&model.to_bytes()).unwrap();
+        assert_eq!(loaded.alphabet_size(), 256);
+        let symbols: Vec<u16> = (0..300).map(|i| [40, 41, 200, 7][i % 4]).collect();
+        let data = model.encode(&symbols).unwrap();
+        assert_eq!(loaded.decode(&data, symbols.len()).unwrap(), symbols);
+        let bytes = b"client-side decompression of assets".to_vec();
+        assert_eq!(decompress(&compress(&bytes).unwrap(), None).unwrap(), bytes);
+    }
+}